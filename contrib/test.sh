@@ -1,6 +1,6 @@
 #!/bin/sh -ex
 
-FEATURES="bitcoinconsensus use-serde rand"
+FEATURES="bitcoinconsensus use-serde rand async"
 
 if [ "$DO_COV" = true ]
 then
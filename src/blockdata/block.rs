@@ -25,15 +25,16 @@
 //! these blocks and the blockchain.
 //!
 
-use std::io;
+use std::{error, fmt, io, mem};
 
 use hashes::{Hash, HashEngine};
-use hash_types::{Wtxid, BlockHash, TxMerkleNode, WitnessMerkleNode, WitnessCommitment};
+use hash_types::{Wtxid, BlockHash, BlockSigHash, TxMerkleNode, WitnessMerkleNode, WitnessCommitment};
 use consensus::{encode, Decodable, Encodable};
+use consensus::encode::{VarInt, MAX_VEC_SIZE};
 use blockdata::transaction::Transaction;
 use util::hash::{bitcoin_merkle_root, BitcoinHash};
-use util::key::PublicKey;
-use util::signature::Signature;
+use util::key::{PrivateKey, PublicKey};
+use util::signature::{self, Signature};
 
 /// A block header, which contains all the block's information except
 /// the actual transactions
@@ -49,38 +50,212 @@ pub struct BlockHeader {
     pub im_merkle_root: TxMerkleNode,
     /// The timestamp of the block, as claimed by the miner
     pub time: u32,
-    ///Aggregate public key of tapyrus-signer used to verify block proof. This field is optional and may be present in all blocks.
-    pub aggregated_public_key: PublicKeyOpt,
+    /// Extension field, e.g. the new aggregate public key of tapyrus-signer used to verify block proof.
+    pub xfield: XField,
     /// Collection holds a signature for block hash which is consisted of block header without Proof.
     pub proof: Option<Signature>,
 }
 
-/// Optional aggregated public key
-pub type PublicKeyOpt = Option<PublicKey>;
+impl BlockHeader {
+    /// Computes the hash which is signed by the federation to produce the block proof.
+    /// This is the double-SHA256 of the header serialized without `proof`.
+    pub fn signature_hash(&self) -> BlockSigHash {
+        let mut engine = BlockSigHash::engine();
+        self.version.consensus_encode(&mut engine).unwrap();
+        self.prev_blockhash.consensus_encode(&mut engine).unwrap();
+        self.merkle_root.consensus_encode(&mut engine).unwrap();
+        self.im_merkle_root.consensus_encode(&mut engine).unwrap();
+        self.time.consensus_encode(&mut engine).unwrap();
+        self.xfield.consensus_encode(&mut engine).unwrap();
+        BlockSigHash::from_engine(engine)
+    }
 
-impl Decodable for PublicKeyOpt {
-    #[inline]
-    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
-        match Decodable::consensus_decode(&mut d) {
-            Ok(pk) => Ok(Some(pk)),
-            Err(_) => Ok(None),
+    /// Verify the block proof against the aggregated public key of the federation.
+    pub fn verify_proof(&self, aggregated_public_key: &PublicKey) -> Result<(), ProofError> {
+        let proof = match self.proof {
+            Some(ref proof) => proof,
+            None => return Err(ProofError::MissingProof),
+        };
+
+        proof.verify(&self.signature_hash().into_inner(), aggregated_public_key)
+            .map_err(ProofError::BadSignature)
+    }
+
+    /// Sign the header with the private key of the federation and set the result as `proof`.
+    pub fn sign(&mut self, privkey: &PrivateKey) -> Result<Signature, signature::Error> {
+        let proof = Signature::sign(privkey, &self.signature_hash().into_inner())?;
+        self.proof = Some(proof);
+        Ok(proof)
+    }
+}
+
+/// An error in verifying the block proof
+#[derive(Debug)]
+pub enum ProofError {
+    /// The header has no proof
+    MissingProof,
+    /// The proof is malformed, or does not verify against the header and the
+    /// aggregated public key. A Schnorr proof cannot tell a wrong key from a
+    /// bad signature, so both are reported as this error.
+    BadSignature(signature::Error),
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProofError::BadSignature(ref e) => write!(f, "bad block proof: {}", e),
+            ProofError::MissingProof => f.write_str(error::Error::description(self)),
         }
     }
 }
 
-impl Encodable for PublicKeyOpt {
+impl error::Error for ProofError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ProofError::BadSignature(ref e) => Some(e),
+            ProofError::MissingProof => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            ProofError::MissingProof => "block header has no proof",
+            ProofError::BadSignature(..) => "bad block proof",
+        }
+    }
+}
+
+/// Extension field of the block header. The header is encoded with a one
+/// byte type followed by the data of that type.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum XField {
+    /// No extension field (type 0)
+    None,
+    /// Aggregate public key of the federation which signs the blocks after
+    /// this one (type 1). It is always encoded in compressed form.
+    AggregatePublicKey(PublicKey),
+    /// Maximum block size in bytes for the blocks after this one (type 2)
+    MaxBlockSize(u32),
+    /// Extension field of a type unknown to this library, with its data
+    Unknown(u8, Vec<u8>),
+}
+
+impl XField {
+    /// The type of the extension field
+    pub fn field_type(&self) -> u8 {
+        match *self {
+            XField::None => 0,
+            XField::AggregatePublicKey(_) => 1,
+            XField::MaxBlockSize(_) => 2,
+            XField::Unknown(field_type, _) => field_type,
+        }
+    }
+
+    /// The aggregate public key, if the extension field carries one
+    pub fn aggregated_public_key(&self) -> Option<&PublicKey> {
+        match *self {
+            XField::AggregatePublicKey(ref pk) => Some(pk),
+            _ => None,
+        }
+    }
+}
+
+impl Default for XField {
+    fn default() -> XField {
+        XField::None
+    }
+}
+
+impl Encodable for XField {
     #[inline]
     fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = self.field_type().consensus_encode(&mut s)?;
         match *self {
-            Some(pk) => pk.consensus_encode(&mut s),
-            None => {
-                let v = Vec::<u8>::new();
-                v.consensus_encode(&mut s)
+            XField::None => {},
+            XField::AggregatePublicKey(ref pk) => {
+                len += pk.key.serialize().to_vec().consensus_encode(&mut s)?;
+            }
+            XField::MaxBlockSize(size) => {
+                len += size.consensus_encode(&mut s)?;
             }
+            XField::Unknown(_, ref data) => {
+                len += data.consensus_encode(&mut s)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for XField {
+    #[inline]
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        match u8::consensus_decode(&mut d)? {
+            0 => Ok(XField::None),
+            1 => {
+                let data: Vec<u8> = Decodable::consensus_decode(&mut d)?;
+                if data.len() != 33 {
+                    return Err(encode::Error::ParseFailed("aggregate public key must be compressed"));
+                }
+                Ok(XField::AggregatePublicKey(PublicKey::from_slice(&data[..])?))
+            }
+            2 => Ok(XField::MaxBlockSize(Decodable::consensus_decode(&mut d)?)),
+            field_type => Ok(XField::Unknown(field_type, Decodable::consensus_decode(&mut d)?)),
         }
     }
 }
 
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for XField {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        struct Visitor;
+        impl<'de> ::serde::de::Visitor<'de> for Visitor {
+            type Value = XField;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a hex-encoded xfield")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: ::serde::de::Error,
+            {
+                let v: Vec<u8> = ::hex::decode(v).map_err(E::custom)?;
+                encode::deserialize(&v[..]).map_err(E::custom)
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+            where
+                E: ::serde::de::Error,
+            {
+                self.visit_str(v)
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+            where
+                E: ::serde::de::Error,
+            {
+                self.visit_str(&v)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for XField {
+    /// User-facing serialization for `XField`.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        serializer.serialize_str(&::hex::encode(encode::serialize(self)))
+    }
+}
+
 /// A Bitcoin block, which is a collection of transactions with an attached
 /// proof of work.
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -92,6 +267,30 @@ pub struct Block {
 }
 
 impl Block {
+    /// Decodes a block whose transactions are in the Tapyrus format, rejecting
+    /// transactions in the segwit format with `encode::Error::WitnessNotSupported`.
+    pub fn consensus_decode_strict<D: io::Read>(mut d: D) -> Result<Block, encode::Error> {
+        let header = BlockHeader::consensus_decode(&mut d)?;
+        let len = VarInt::consensus_decode(&mut d)?.0;
+        let byte_size = (len as usize)
+            .checked_mul(mem::size_of::<Transaction>())
+            .ok_or(encode::Error::ParseFailed("Invalid length"))?;
+        if byte_size > MAX_VEC_SIZE {
+            return Err(encode::Error::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE });
+        }
+        let mut txdata = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            txdata.push(Transaction::consensus_decode_strict(&mut d)?);
+        }
+        Ok(Block { header: header, txdata: txdata })
+    }
+
+    /// Returns the block with the given signature set as the proof of its header.
+    pub fn with_proof(mut self, proof: Signature) -> Block {
+        self.header.proof = Some(proof);
+        self
+    }
+
     /// check if merkle root of header matches merkle root of the transaction list
     pub fn check_merkle_root (&self) -> bool {
         self.header.merkle_root == self.merkle_root() &&
@@ -132,7 +331,7 @@ impl Block {
     }
 
     /// Calculate the immutable transaction merkle root.
-    fn immutable_merkle_root(&self) -> TxMerkleNode {
+    pub fn immutable_merkle_root(&self) -> TxMerkleNode {
         let hashes = self.txdata.iter().map(|obj| obj.malfix_txid().as_hash());
         bitcoin_merkle_root(hashes).into()
     }
@@ -179,7 +378,7 @@ impl_consensus_encoding!(
     merkle_root,
     im_merkle_root,
     time,
-    aggregated_public_key,
+    xfield,
     proof
 );
 impl_consensus_encoding!(Block, header, txdata);
@@ -190,7 +389,7 @@ serde_struct_impl!(
     merkle_root,
     im_merkle_root,
     time,
-    aggregated_public_key,
+    xfield,
     proof);
 serde_struct_impl!(Block, header, txdata);
 
@@ -199,14 +398,15 @@ mod tests {
     use hex::decode as hex_decode;
     use std::str::FromStr;
 
-    use blockdata::block::Block;
+    use blockdata::block::{Block, BlockHeader, ProofError, XField};
     use consensus::encode::{deserialize, serialize};
-    use util::key::PublicKey;
+    use util::key::{PrivateKey, PublicKey};
+    use util::signature::{self, Signature};
 
     #[test]
     fn block_test() {
-        let some_block = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e4921032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af40f1453cd332262d74edf65f96688724b80a15c852fd50151e4aabc41a0d9560d2cd38f0746c3d9c9e18b236f20e37d0ae1bda457ea029db8a55b20f38143517d00201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap();
-        let cutoff_block = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e4921032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac").unwrap();
+        let some_block = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af40f1453cd332262d74edf65f96688724b80a15c852fd50151e4aabc41a0d9560d2cd38f0746c3d9c9e18b236f20e37d0ae1bda457ea029db8a55b20f38143517d00201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap();
+        let cutoff_block = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac").unwrap();
 
         let prevhash =
             hex_decode("4ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000").unwrap();
@@ -232,7 +432,7 @@ mod tests {
         );
         assert_eq!(serialize(&real_decode.header.merkle_root), merkle);
         assert_eq!(real_decode.header.time, 1231965655);
-        assert_eq!(real_decode.header.aggregated_public_key.unwrap(), pk);
+        assert_eq!(real_decode.header.xfield, XField::AggregatePublicKey(pk));
         assert_eq!(real_decode.header.proof.unwrap(), sig);
         // [test] TODO: check the transaction data
 
@@ -249,7 +449,7 @@ mod tests {
 
         assert!(decode.is_ok());
         let real_decode = decode.unwrap();
-        assert!(real_decode.header.aggregated_public_key.is_none());
+        assert_eq!(real_decode.header.xfield, XField::None);
         assert!(real_decode.header.proof.is_none());
     }
 
@@ -282,4 +482,99 @@ mod tests {
 
         assert_eq!(serialize(&real_decode), segwit_block);
     }
+
+    #[test]
+    fn block_proof_test() {
+        let header_hex = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af00").unwrap();
+        let mut header: BlockHeader = deserialize(&header_hex).unwrap();
+        let pk = *header.xfield.aggregated_public_key().unwrap();
+
+        assert_eq!(
+            header.signature_hash().to_string(),
+            "3201da82fe653fcd4ad276fa3238e1d9a1abad817982e7236b2c90af65914af9"
+        );
+
+        match header.verify_proof(&pk) {
+            Err(ProofError::MissingProof) => {},
+            x => panic!("expected MissingProof, got {:?}", x),
+        }
+
+        let sig: Signature = deserialize(&hex_decode("0ab3ac036cf7a4a3ebaad345e8b81e3fa5a0daac8b030103cbcf4900384143c6101bcddee2db6fa8009cfe7d76cc30a857c515e341bddd803017d6c2c6c21d2f").unwrap()).unwrap();
+        header.proof = Some(sig);
+        assert!(header.verify_proof(&pk).is_ok());
+
+        // the proof does not depend on itself
+        assert_eq!(
+            header.signature_hash().to_string(),
+            "3201da82fe653fcd4ad276fa3238e1d9a1abad817982e7236b2c90af65914af9"
+        );
+
+        let other = PublicKey::generator();
+        match header.verify_proof(&other) {
+            Err(ProofError::BadSignature(signature::Error::InvalidSignature)) => {},
+            x => panic!("expected BadSignature, got {:?}", x),
+        }
+
+        header.time += 1;
+        match header.verify_proof(&pk) {
+            Err(ProofError::BadSignature(signature::Error::InvalidSignature)) => {},
+            x => panic!("expected BadSignature, got {:?}", x),
+        }
+        header.time -= 1;
+
+        header.proof = Some(Signature { r_x: sig.r_x, sigma: [0xff; 32] });
+        match header.verify_proof(&pk) {
+            Err(ProofError::BadSignature(_)) => {},
+            x => panic!("expected BadSignature, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn block_sign_test() {
+        let header_hex = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af00").unwrap();
+        let mut header: BlockHeader = deserialize(&header_hex).unwrap();
+        let key = PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+
+        let sig = header.sign(&key).unwrap();
+        assert_eq!(header.proof, Some(sig));
+        assert_eq!(
+            serialize(&sig),
+            hex_decode("0ab3ac036cf7a4a3ebaad345e8b81e3fa5a0daac8b030103cbcf4900384143c6101bcddee2db6fa8009cfe7d76cc30a857c515e341bddd803017d6c2c6c21d2f").unwrap()
+        );
+        assert!(header.verify_proof(header.xfield.aggregated_public_key().unwrap()).is_ok());
+
+        header.proof = None;
+        let block = Block { header: header, txdata: vec![] }.with_proof(sig);
+        assert!(block.header.verify_proof(block.header.xfield.aggregated_public_key().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn xfield_test() {
+        let pk = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+
+        let xfields = vec![
+            (XField::None, "00"),
+            (XField::AggregatePublicKey(pk), "0121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af"),
+            (XField::MaxBlockSize(4000000), "0200093d00"),
+            (XField::Unknown(3, vec![1, 2]), "03020102"),
+        ];
+        for (xfield, hex) in xfields {
+            assert_eq!(serialize(&xfield), hex_decode(hex).unwrap());
+            assert_eq!(deserialize::<XField>(&hex_decode(hex).unwrap()).unwrap(), xfield);
+            #[cfg(feature = "serde")]
+            serde_round_trip!(xfield);
+        }
+
+        // uncompressed keys are always encoded in compressed form
+        let uncompressed = PublicKey { compressed: false, key: pk.key };
+        assert_eq!(serialize(&XField::AggregatePublicKey(uncompressed)), serialize(&XField::AggregatePublicKey(pk)));
+
+        // malformed aggregate public keys are errors
+        assert!(deserialize::<XField>(&hex_decode("0141042e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af191923a2964c177f5b5923ae500fca49e99492d534aa3759d6b25a8bc971b133").unwrap()).is_err());
+        assert!(deserialize::<XField>(&hex_decode("0121052e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap()).is_err());
+        assert!(deserialize::<XField>(&hex_decode("0120032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1").unwrap()).is_err());
+        // truncated data
+        assert!(deserialize::<XField>(&hex_decode("0200093d").unwrap()).is_err());
+        assert!(deserialize::<XField>(&hex_decode("030201").unwrap()).is_err());
+    }
 }
@@ -0,0 +1,275 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Colored coins
+//!
+//! Tapyrus tokens are outputs whose script starts with a color identifier
+//! followed by OP_COLOR. The color identifier is a token type byte followed
+//! by a 32-byte payload, which is derived from the script issuing the token
+//! for reissuable tokens and from an outpoint spent by the issuing
+//! transaction otherwise.
+//!
+
+use std::{error, fmt, io};
+use std::str::FromStr;
+
+use hashes::{sha256, Hash};
+use hashes::hex::{self, FromHex, ToHex};
+
+use blockdata::script::Script;
+use blockdata::transaction::OutPoint;
+use consensus::{encode, serialize, Decodable, Encodable};
+
+/// The size of a serialized color identifier
+pub const COLOR_IDENTIFIER_SIZE: usize = 33;
+
+/// An error in parsing a color identifier
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The color identifier is not 33 bytes long
+    InvalidLength(usize),
+    /// The token type byte is unknown
+    UnknownTokenType(u8),
+    /// The color identifier is not valid hex
+    Hex(hex::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidLength(len) => write!(f, "invalid color identifier length: {}", len),
+            Error::UnknownTokenType(t) => write!(f, "unknown token type: {:#x}", t),
+            Error::Hex(ref e) => write!(f, "invalid hex: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Hex(ref e) => Some(e),
+            Error::InvalidLength(_) | Error::UnknownTokenType(_) => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::InvalidLength(_) => "invalid color identifier length",
+            Error::UnknownTokenType(_) => "unknown token type",
+            Error::Hex(_) => "invalid hex",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<hex::Error> for Error {
+    fn from(e: hex::Error) -> Error {
+        Error::Hex(e)
+    }
+}
+
+/// The type of a token
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum TokenType {
+    /// 0xc1: Token which can be issued again by the issuer of the first issuance
+    Reissuable = 0xc1,
+    /// 0xc2: Token which is issued only once
+    NonReissuable = 0xc2,
+    /// 0xc3: Non-fungible token, whose amount is always 1
+    Nft = 0xc3,
+}
+
+impl TokenType {
+    /// Reads a token type byte
+    pub fn from_u8(n: u8) -> Option<TokenType> {
+        match n {
+            0xc1 => Some(TokenType::Reissuable),
+            0xc2 => Some(TokenType::NonReissuable),
+            0xc3 => Some(TokenType::Nft),
+            _ => None,
+        }
+    }
+
+    /// Converts to a u8
+    pub fn as_u8(&self) -> u8 { *self as u8 }
+}
+
+/// Identifier of a token, which is pushed before OP_COLOR in a colored script
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ColorIdentifier {
+    /// The type of the token
+    pub token_type: TokenType,
+    /// SHA256 of the issuing script or outpoint
+    pub payload: [u8; 32],
+}
+
+impl ColorIdentifier {
+    /// The color of a reissuable token, which is issued by spending outputs with
+    /// `script_pubkey`. `script_pubkey` must not be colored.
+    pub fn reissuable(script_pubkey: &Script) -> ColorIdentifier {
+        ColorIdentifier {
+            token_type: TokenType::Reissuable,
+            payload: sha256::Hash::hash(script_pubkey.as_bytes()).into_inner(),
+        }
+    }
+
+    /// The color of a non-reissuable token, which is issued by a transaction spending `out_point`
+    pub fn non_reissuable(out_point: &OutPoint) -> ColorIdentifier {
+        ColorIdentifier {
+            token_type: TokenType::NonReissuable,
+            payload: sha256::Hash::hash(&serialize(out_point)).into_inner(),
+        }
+    }
+
+    /// The color of an NFT, which is issued by a transaction spending `out_point`
+    pub fn nft(out_point: &OutPoint) -> ColorIdentifier {
+        ColorIdentifier {
+            token_type: TokenType::Nft,
+            payload: sha256::Hash::hash(&serialize(out_point)).into_inner(),
+        }
+    }
+
+    /// Parse a color identifier from its 33-byte encoding
+    pub fn from_slice(data: &[u8]) -> Result<ColorIdentifier, Error> {
+        if data.len() != COLOR_IDENTIFIER_SIZE {
+            return Err(Error::InvalidLength(data.len()));
+        }
+        let token_type = match TokenType::from_u8(data[0]) {
+            Some(t) => t,
+            None => return Err(Error::UnknownTokenType(data[0])),
+        };
+        let mut payload = [0u8; 32];
+        payload.copy_from_slice(&data[1..]);
+        Ok(ColorIdentifier { token_type: token_type, payload: payload })
+    }
+
+    /// The 33-byte encoding of the color identifier
+    pub fn to_bytes(&self) -> [u8; COLOR_IDENTIFIER_SIZE] {
+        let mut result = [0u8; COLOR_IDENTIFIER_SIZE];
+        result[0] = self.token_type.as_u8();
+        result[1..].copy_from_slice(&self.payload[..]);
+        result
+    }
+}
+
+impl fmt::Display for ColorIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_bytes()[..].to_hex())
+    }
+}
+
+impl FromStr for ColorIdentifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ColorIdentifier, Error> {
+        ColorIdentifier::from_slice(&Vec::<u8>::from_hex(s)?[..])
+    }
+}
+
+impl Encodable for ColorIdentifier {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        s.write_all(&self.to_bytes()[..])?;
+        Ok(COLOR_IDENTIFIER_SIZE)
+    }
+}
+
+impl Decodable for ColorIdentifier {
+    #[inline]
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let mut data = [0u8; COLOR_IDENTIFIER_SIZE];
+        d.read_exact(&mut data[..])?;
+        ColorIdentifier::from_slice(&data[..])
+            .map_err(|_| encode::Error::ParseFailed("invalid color identifier"))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for ColorIdentifier {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        struct Visitor;
+        impl<'de> ::serde::de::Visitor<'de> for Visitor {
+            type Value = ColorIdentifier;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a hex-encoded color identifier")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: ::serde::de::Error,
+            {
+                ColorIdentifier::from_str(v).map_err(E::custom)
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+            where
+                E: ::serde::de::Error,
+            {
+                self.visit_str(v)
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+            where
+                E: ::serde::de::Error,
+            {
+                self.visit_str(&v)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for ColorIdentifier {
+    /// User-facing serialization for `ColorIdentifier`.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hashes::hex::FromHex;
+
+    use blockdata::color::{ColorIdentifier, Error, TokenType};
+    use blockdata::script::Script;
+    use blockdata::transaction::OutPoint;
+    use consensus::encode::{deserialize, serialize};
+
+    #[test]
+    fn color_identifier_test() {
+        let script = Script::from(Vec::<u8>::from_hex("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac").unwrap());
+        let color_id = ColorIdentifier::reissuable(&script);
+        assert_eq!(color_id.token_type, TokenType::Reissuable);
+        assert_eq!(color_id.to_string(), "c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167");
+
+        let out_point: OutPoint = deserialize(&Vec::<u8>::from_hex("a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece01000000").unwrap()).unwrap();
+        let color_id = ColorIdentifier::non_reissuable(&out_point);
+        assert_eq!(color_id.to_string(), "c27e1c47c8b6596701242add0caac75208519c59943670d4de8e4d353df3679006");
+        let color_id = ColorIdentifier::nft(&out_point);
+        assert_eq!(color_id.to_string(), "c37e1c47c8b6596701242add0caac75208519c59943670d4de8e4d353df3679006");
+
+        assert_eq!(ColorIdentifier::from_str(&color_id.to_string()), Ok(color_id));
+        let encoded = serialize(&color_id);
+        assert_eq!(encoded.len(), 33);
+        assert_eq!(deserialize::<ColorIdentifier>(&encoded).unwrap(), color_id);
+
+        assert_eq!(ColorIdentifier::from_slice(&encoded[1..]), Err(Error::InvalidLength(32)));
+        assert_eq!(ColorIdentifier::from_str("c47e1c47c8b6596701242add0caac75208519c59943670d4de8e4d353df3679006"),
+                   Err(Error::UnknownTokenType(0xc4)));
+        assert!(deserialize::<ColorIdentifier>(&Vec::<u8>::from_hex("c47e1c47c8b6596701242add0caac75208519c59943670d4de8e4d353df3679006").unwrap()).is_err());
+        #[cfg(feature = "serde")]
+        serde_round_trip!(color_id);
+    }
+}
@@ -29,7 +29,7 @@ use std::str::FromStr;
 
 use hashes::hex::FromHex;
 use hashes::sha256d;
-use blockdata::block::{Block, BlockHeader};
+use blockdata::block::{Block, BlockHeader, XField};
 use blockdata::opcodes;
 use blockdata::script;
 use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
@@ -89,7 +89,19 @@ fn bitcoin_genesis_tx() -> Transaction {
 }
 
 /// Constructs and returns the genesis block
+///
+/// # Panics
+/// Panics if the genesis block of the network is not known, which is the case
+/// for `Network::Custom`. Use `try_genesis_block` for such networks.
 pub fn genesis_block(network: Network) -> Block {
+    match try_genesis_block(network) {
+        Some(block) => block,
+        None => panic!("unknown genesis block for network {}", network),
+    }
+}
+
+/// Constructs and returns the genesis block, or `None` if it is not known
+pub fn try_genesis_block(network: Network) -> Option<Block> {
     let txdata = vec![bitcoin_genesis_tx()];
     let hash: sha256d::Hash = txdata[0].txid().into();
     let merkle_root = hash.into();
@@ -101,61 +113,62 @@ pub fn genesis_block(network: Network) -> Block {
 
     match network {
         Network::Bitcoin => {
-            Block {
+            Some(Block {
                 header: BlockHeader {
                     version: 1,
                     prev_blockhash: Default::default(),
                     merkle_root,
                     im_merkle_root,
                     time: 1231006505,
-                    aggregated_public_key: Some(public_key),
+                    xfield: XField::AggregatePublicKey(public_key),
                     proof: None,
                 },
                 txdata: txdata
-            }
+            })
         }
         Network::Testnet => {
-            Block {
+            Some(Block {
                 header: BlockHeader {
                     version: 1,
                     prev_blockhash: Default::default(),
                     merkle_root,
                     im_merkle_root,
                     time: 1296688602,
-                    aggregated_public_key: Some(public_key),
+                    xfield: XField::AggregatePublicKey(public_key),
                     proof: None,
                 },
                 txdata: txdata
-            }
+            })
         }
         Network::Regtest => {
-            Block {
+            Some(Block {
                 header: BlockHeader {
                     version: 1,
                     prev_blockhash: Default::default(),
                     merkle_root,
                     im_merkle_root,
                     time: 1296688602,
-                    aggregated_public_key: Some(public_key),
+                    xfield: XField::AggregatePublicKey(public_key),
                     proof: None,
                 },
                 txdata: txdata,
-            }
+            })
         }
         Network::Paradium => {
-            Block {
+            Some(Block {
                 header: BlockHeader {
                     version: 1,
                     prev_blockhash: Default::default(),
                     merkle_root,
                     im_merkle_root,
                     time: 1562925929,
-                    aggregated_public_key: Some(public_key),
+                    xfield: XField::AggregatePublicKey(public_key),
                     proof: None,
                 },
                 txdata: txdata,
-            }
+            })
         }
+        Network::Custom(_) => None,
     }
 }
 
@@ -164,9 +177,9 @@ mod test {
     use std::default::Default;
     use hex::decode as hex_decode;
 
-    use network::constants::Network;
+    use network::constants::{Network, NetworkId};
     use consensus::encode::serialize;
-    use blockdata::constants::{genesis_block, bitcoin_genesis_tx};
+    use blockdata::constants::{genesis_block, try_genesis_block, bitcoin_genesis_tx};
     use blockdata::constants::{MAX_SEQUENCE, COIN_VALUE};
     use util::hash::BitcoinHash;
 
@@ -223,6 +236,12 @@ mod test {
         );
     }
 
+    #[test]
+    fn custom_genesis_block() {
+        assert!(try_genesis_block(Network::Custom(NetworkId::from(1234))).is_none());
+        assert_eq!(try_genesis_block(Network::Paradium), Some(genesis_block(Network::Paradium)));
+    }
+
     #[test]
     fn paradium_genesis_full_block() {
         let gen = genesis_block(Network::Paradium);
@@ -0,0 +1,348 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Genesis blocks
+//!
+//! Each Tapyrus chain has its own genesis block, signed by the aggregated
+//! public key of its federation and distributed as a hex-encoded file, e.g.
+//! as produced by `tapyrus-genesis`. This module parses and validates such
+//! genesis blocks, keeps them in a registry per network, and builds new signed
+//! genesis blocks for test chains.
+//!
+
+use std::collections::HashMap;
+use std::{error, fmt, fs, io};
+use std::path::Path;
+
+use hashes::hex::{self, FromHex};
+
+use blockdata::block::{Block, BlockHeader, ProofError, XField};
+use blockdata::constants::{try_genesis_block, MAX_SEQUENCE};
+use blockdata::script::{self, Script};
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use consensus::encode;
+use hash_types::{BlockHash, Txid};
+use network::constants::Network;
+use util::key::PrivateKey;
+use util::signature;
+
+/// An error in loading, validating or building a genesis block
+#[derive(Debug)]
+pub enum Error {
+    /// The genesis block is not valid hex
+    Hex(hex::Error),
+    /// The genesis block could not be decoded
+    Encode(encode::Error),
+    /// The genesis block file could not be read
+    Io(io::Error),
+    /// The previous block hash is not zero
+    NonZeroPrevBlockHash,
+    /// The block does not consist of a single coinbase transaction
+    InvalidCoinbase,
+    /// The merkle roots do not match the transactions
+    BadMerkleRoot,
+    /// The xfield does not carry the aggregated public key
+    NoAggregatedPublicKey,
+    /// The proof does not verify against the aggregated public key
+    InvalidProof(ProofError),
+    /// Signing the genesis block failed
+    Signature(signature::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Hex(ref e) => write!(f, "invalid hex: {}", e),
+            Error::Encode(ref e) => write!(f, "decoding error: {}", e),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::InvalidProof(ref e) => write!(f, "invalid proof: {}", e),
+            Error::Signature(ref e) => write!(f, "signing error: {}", e),
+            Error::NonZeroPrevBlockHash |
+            Error::InvalidCoinbase |
+            Error::BadMerkleRoot |
+            Error::NoAggregatedPublicKey => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Hex(ref e) => Some(e),
+            Error::Encode(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            Error::InvalidProof(ref e) => Some(e),
+            Error::Signature(ref e) => Some(e),
+            Error::NonZeroPrevBlockHash |
+            Error::InvalidCoinbase |
+            Error::BadMerkleRoot |
+            Error::NoAggregatedPublicKey => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Hex(_) => "invalid hex",
+            Error::Encode(_) => "decoding error",
+            Error::Io(_) => "I/O error",
+            Error::NonZeroPrevBlockHash => "genesis block has a previous block",
+            Error::InvalidCoinbase => "genesis block must have a single coinbase transaction",
+            Error::BadMerkleRoot => "merkle root does not match the transactions",
+            Error::NoAggregatedPublicKey => "genesis block has no aggregated public key",
+            Error::InvalidProof(_) => "invalid proof",
+            Error::Signature(_) => "signing error",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<hex::Error> for Error {
+    fn from(e: hex::Error) -> Error {
+        Error::Hex(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(e: encode::Error) -> Error {
+        Error::Encode(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Parse and validate a hex-encoded genesis block. Surrounding whitespace is ignored.
+pub fn parse_genesis_block(s: &str) -> Result<Block, Error> {
+    let block: Block = encode::deserialize(&Vec::<u8>::from_hex(s.trim())?)?;
+    validate_genesis_block(&block)?;
+    Ok(block)
+}
+
+/// Read, parse and validate a genesis block file, which contains the hex-encoded block
+pub fn read_genesis_block<P: AsRef<Path>>(path: P) -> Result<Block, Error> {
+    parse_genesis_block(&fs::read_to_string(path)?)
+}
+
+/// Validate a genesis block: it has no previous block, a single coinbase
+/// transaction matching the merkle roots, and a proof signed by the
+/// aggregated public key in its xfield.
+pub fn validate_genesis_block(block: &Block) -> Result<(), Error> {
+    if block.header.prev_blockhash != BlockHash::default() {
+        return Err(Error::NonZeroPrevBlockHash);
+    }
+    // Only the txid of the spent outpoint must be null: unlike in Bitcoin, the
+    // index of a Tapyrus coinbase outpoint is not fixed to 0xffffffff
+    let is_coinbase = |tx: &Transaction| tx.input.len() == 1 && tx.input[0].previous_output.txid == Txid::default();
+    if block.txdata.len() != 1 || !is_coinbase(&block.txdata[0]) {
+        return Err(Error::InvalidCoinbase);
+    }
+    if !block.check_merkle_root() {
+        return Err(Error::BadMerkleRoot);
+    }
+    let aggregated_public_key = match block.header.xfield.aggregated_public_key() {
+        Some(pk) => pk,
+        None => return Err(Error::NoAggregatedPublicKey),
+    };
+    block.header.verify_proof(aggregated_public_key).map_err(Error::InvalidProof)
+}
+
+/// Genesis blocks of the networks in use, on top of the predefined ones
+#[derive(Clone, Debug, Default)]
+pub struct GenesisRegistry {
+    blocks: HashMap<Network, Block>,
+}
+
+impl GenesisRegistry {
+    /// Create an empty registry
+    pub fn new() -> GenesisRegistry {
+        GenesisRegistry { blocks: HashMap::new() }
+    }
+
+    /// Validate a genesis block and register it for the network, replacing any
+    /// block registered before
+    pub fn register(&mut self, network: Network, block: Block) -> Result<(), Error> {
+        validate_genesis_block(&block)?;
+        self.blocks.insert(network, block);
+        Ok(())
+    }
+
+    /// The genesis block of the network: the registered one if any, otherwise
+    /// the predefined one, or `None` for an unregistered custom network.
+    pub fn get(&self, network: Network) -> Option<Block> {
+        match self.blocks.get(&network) {
+            Some(block) => Some(block.clone()),
+            None => try_genesis_block(network),
+        }
+    }
+}
+
+/// A builder of signed genesis blocks, for test chains
+#[derive(Clone, Debug)]
+pub struct GenesisBuilder {
+    time: u32,
+    script_sig: Script,
+    output: Option<TxOut>,
+}
+
+impl GenesisBuilder {
+    /// Create a builder of a genesis block with the given timestamp
+    pub fn new(time: u32) -> GenesisBuilder {
+        GenesisBuilder {
+            time: time,
+            script_sig: Script::new(),
+            output: None,
+        }
+    }
+
+    /// Push a message into the scriptSig of the coinbase
+    pub fn message(mut self, message: &[u8]) -> GenesisBuilder {
+        self.script_sig = script::Builder::new().push_slice(message).into_script();
+        self
+    }
+
+    /// Pay `value` to `script_pubkey` in the coinbase. Without it, the coinbase
+    /// has a single empty output.
+    pub fn pay_to(mut self, script_pubkey: Script, value: u64) -> GenesisBuilder {
+        self.output = Some(TxOut { value: value, script_pubkey: script_pubkey });
+        self
+    }
+
+    /// Build the genesis block with the public key of `aggregated_private_key`
+    /// in its xfield, and sign it with that key. The public key is always
+    /// compressed.
+    pub fn build(self, aggregated_private_key: &PrivateKey) -> Result<Block, Error> {
+        let secp = ::secp256k1::Secp256k1::signing_only();
+        let mut aggregated_public_key = aggregated_private_key.public_key(&secp);
+        aggregated_public_key.compressed = true;
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: self.script_sig,
+                sequence: MAX_SEQUENCE,
+                witness: vec![],
+            }],
+            output: vec![self.output.unwrap_or_default()],
+        };
+
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                im_merkle_root: Default::default(),
+                time: self.time,
+                xfield: XField::AggregatePublicKey(aggregated_public_key),
+                proof: None,
+            },
+            txdata: vec![coinbase],
+        };
+        block.header.merkle_root = block.merkle_root();
+        block.header.im_merkle_root = block.immutable_merkle_root();
+        block.header.sign(aggregated_private_key).map_err(Error::Signature)?;
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hashes::hex::ToHex;
+
+    use blockdata::block::{ProofError, XField};
+    use blockdata::constants::genesis_block;
+    use blockdata::genesis::{parse_genesis_block, validate_genesis_block, Error, GenesisBuilder, GenesisRegistry};
+    use blockdata::script::Script;
+    use consensus::encode::serialize;
+    use network::constants::{Network, NetworkId};
+    use util::key::{PrivateKey, PublicKey};
+    use util::signature;
+
+    #[test]
+    fn genesis_test() {
+        let key = PrivateKey::from_wif("KzT9HVkpBCGAVi964Hva4yJ6qQuyNQUdZ21HDuDZJpFvkGvBQiT5").unwrap();
+        let pk = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+
+        let genesis = GenesisBuilder::new(1562925929)
+            .message(b"Tapyrus test chain")
+            .pay_to(Script::new(), 0)
+            .build(&key)
+            .unwrap();
+        assert_eq!(genesis.header.xfield, XField::AggregatePublicKey(pk));
+        assert!(genesis.txdata[0].is_coin_base());
+        validate_genesis_block(&genesis).unwrap();
+
+        let hex = serialize(&genesis).to_hex();
+        assert_eq!(parse_genesis_block(&format!("{}\n", hex)).unwrap(), genesis);
+
+        let network = Network::Custom(NetworkId::from(1234));
+        let mut registry = GenesisRegistry::new();
+        assert_eq!(registry.get(network), None);
+        assert_eq!(registry.get(Network::Paradium), Some(genesis_block(Network::Paradium)));
+        registry.register(network, genesis.clone()).unwrap();
+        assert_eq!(registry.get(network), Some(genesis.clone()));
+
+        // the predefined genesis blocks are not signed
+        match registry.register(network, genesis_block(Network::Paradium)) {
+            Err(Error::InvalidProof(ProofError::MissingProof)) => {},
+            x => panic!("expected InvalidProof, got {:?}", x),
+        }
+        assert_eq!(registry.get(network), Some(genesis.clone()));
+
+        let mut invalid = genesis.clone();
+        invalid.header.time += 1;
+        match validate_genesis_block(&invalid) {
+            Err(Error::InvalidProof(ProofError::BadSignature(signature::Error::InvalidSignature))) => {},
+            x => panic!("expected InvalidProof, got {:?}", x),
+        }
+
+        let mut invalid = genesis.clone();
+        invalid.txdata[0].output[0].value = 1;
+        match validate_genesis_block(&invalid) {
+            Err(Error::BadMerkleRoot) => {},
+            x => panic!("expected BadMerkleRoot, got {:?}", x),
+        }
+
+        let mut invalid = genesis.clone();
+        invalid.header.prev_blockhash = invalid.header.merkle_root.as_hash().into();
+        match validate_genesis_block(&invalid) {
+            Err(Error::NonZeroPrevBlockHash) => {},
+            x => panic!("expected NonZeroPrevBlockHash, got {:?}", x),
+        }
+
+        // the coinbase outpoint index of a Tapyrus Core coinbase is the block height
+        let mut genesis = GenesisBuilder::new(1562925929).build(&key).unwrap();
+        genesis.txdata[0].input[0].previous_output.vout = 0;
+        genesis.header.merkle_root = genesis.merkle_root();
+        genesis.header.im_merkle_root = genesis.immutable_merkle_root();
+        genesis.header.sign(&key).unwrap();
+        assert_eq!(parse_genesis_block(&serialize(&genesis).to_hex()).unwrap(), genesis);
+
+        let mut invalid = genesis.clone();
+        invalid.txdata[0].input[0].previous_output.txid = invalid.header.merkle_root.as_hash().into();
+        invalid.header.merkle_root = invalid.merkle_root();
+        invalid.header.im_merkle_root = invalid.immutable_merkle_root();
+        invalid.header.sign(&key).unwrap();
+        match validate_genesis_block(&invalid) {
+            Err(Error::InvalidCoinbase) => {},
+            x => panic!("expected InvalidCoinbase, got {:?}", x),
+        }
+
+        match parse_genesis_block("zz") {
+            Err(Error::Hex(_)) => {},
+            x => panic!("expected Hex, got {:?}", x),
+        }
+        match parse_genesis_block(&hex[..hex.len() - 2]) {
+            Err(Error::Encode(_)) => {},
+            x => panic!("expected Encode, got {:?}", x),
+        }
+    }
+}
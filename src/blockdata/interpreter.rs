@@ -0,0 +1,1458 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Script interpreter
+//!
+//! A native implementation of the Tapyrus script rules, which does not need
+//! the `bitcoinconsensus` library. It follows the legacy Bitcoin script
+//! semantics, without segwit, and adds the Tapyrus rules: Schnorr signatures
+//! in OP_CHECKSIG and OP_CHECKMULTISIG, OP_CHECKDATASIG, and OP_COLOR in
+//! colored scripts.
+//!
+
+use std::{error, fmt, ops};
+
+use hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use secp256k1;
+
+use blockdata::color::ColorIdentifier;
+use blockdata::opcodes;
+use blockdata::script::{self, build_scriptint, read_scriptbool, Builder, Instruction, Script};
+use blockdata::transaction::{OutPoint, Transaction, TxOut};
+use util::key::PublicKey;
+use util::scriptsig::{verify_data_signature, SCHNORR_SIGNATURE_SIZE};
+use util::signature::Signature;
+
+/// Maximum size of a script, in bytes
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Maximum size of a stack element, in bytes
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum number of non-push operations in a script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum number of elements in the stack and the alt stack together
+pub const MAX_STACK_SIZE: usize = 1000;
+/// Maximum number of public keys in OP_CHECKMULTISIG
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Lock times below this are block heights, above it timestamps
+const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+/// Sequence flag disabling the relative lock time (BIP 68)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+/// Sequence flag making the relative lock time a time rather than a height (BIP 68)
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+/// Mask of the relative lock time in a sequence (BIP 68)
+const SEQUENCE_LOCKTIME_MASK: i64 = 0x0000ffff;
+
+/// Flags to select the rules which are enforced when evaluating scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerifyFlags(u32);
+
+impl VerifyFlags {
+    /// No additional rules.
+    pub const NONE: VerifyFlags = VerifyFlags(0);
+
+    /// Evaluate the redeem script of P2SH and CP2SH outputs (BIP 16).
+    pub const P2SH: VerifyFlags = VerifyFlags(1 << 0);
+
+    /// Require strict encoding of signatures, sighash types and public keys.
+    pub const STRICTENC: VerifyFlags = VerifyFlags(1 << 1);
+
+    /// Require strict DER encoding of ECDSA signatures (BIP 66).
+    pub const DERSIG: VerifyFlags = VerifyFlags(1 << 2);
+
+    /// Require ECDSA signatures with a low S value.
+    pub const LOW_S: VerifyFlags = VerifyFlags(1 << 3);
+
+    /// Require the extra element popped by OP_CHECKMULTISIG to be empty (BIP 147).
+    pub const NULLDUMMY: VerifyFlags = VerifyFlags(1 << 4);
+
+    /// Require the scriptSig to only push data.
+    pub const SIGPUSHONLY: VerifyFlags = VerifyFlags(1 << 5);
+
+    /// Require pushes and numbers to be minimally encoded.
+    pub const MINIMALDATA: VerifyFlags = VerifyFlags(1 << 6);
+
+    /// Fail on the NOPs reserved for upgrades.
+    pub const DISCOURAGE_UPGRADABLE_NOPS: VerifyFlags = VerifyFlags(1 << 7);
+
+    /// Require exactly one element on the stack after evaluation. Requires `P2SH`:
+    /// verification fails with `Error::InvalidFlags` otherwise.
+    pub const CLEANSTACK: VerifyFlags = VerifyFlags(1 << 8);
+
+    /// Evaluate OP_CHECKLOCKTIMEVERIFY (BIP 65).
+    pub const CHECKLOCKTIMEVERIFY: VerifyFlags = VerifyFlags(1 << 9);
+
+    /// Evaluate OP_CHECKSEQUENCEVERIFY (BIP 112).
+    pub const CHECKSEQUENCEVERIFY: VerifyFlags = VerifyFlags(1 << 10);
+
+    /// Require the argument of OP_IF and OP_NOTIF to be empty or 0x01.
+    pub const MINIMALIF: VerifyFlags = VerifyFlags(1 << 11);
+
+    /// Require failed signatures to be empty.
+    pub const NULLFAIL: VerifyFlags = VerifyFlags(1 << 12);
+
+    /// The rules which every block must follow.
+    pub const MANDATORY: VerifyFlags = VerifyFlags(
+        (1 << 0) | (1 << 2) | (1 << 4) | (1 << 9) | (1 << 10)
+    );
+
+    /// The rules which transactions must follow to be relayed.
+    pub const STANDARD: VerifyFlags = VerifyFlags(
+        (1 << 0) | (1 << 1) | (1 << 2) | (1 << 3) | (1 << 4) | (1 << 6) |
+        (1 << 7) | (1 << 8) | (1 << 9) | (1 << 10) | (1 << 11) | (1 << 12)
+    );
+
+    /// Add [VerifyFlags] together.
+    ///
+    /// Returns itself.
+    pub fn add(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 |= other.0;
+        *self
+    }
+
+    /// Remove [VerifyFlags] from this.
+    ///
+    /// Returns itself.
+    pub fn remove(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 &= !other.0;
+        *self
+    }
+
+    /// Check whether [VerifyFlags] are included in this one.
+    pub fn has(&self, flags: VerifyFlags) -> bool {
+        (self.0 | flags.0) == self.0
+    }
+
+    /// Get the integer representation of this [VerifyFlags].
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for VerifyFlags {
+    fn from(f: u32) -> Self {
+        VerifyFlags(f)
+    }
+}
+
+impl ops::BitOr for VerifyFlags {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self {
+        self.add(rhs)
+    }
+}
+
+impl ops::BitOrAssign for VerifyFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.add(rhs);
+    }
+}
+
+/// Ways that the evaluation of a script might fail
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The script could not be parsed, or a number could not be read
+    Script(script::Error),
+    /// The script evaluated to false
+    EvalFalse,
+    /// OP_RETURN was executed
+    OpReturn,
+    /// The script is larger than `MAX_SCRIPT_SIZE`
+    ScriptSize,
+    /// A push is larger than `MAX_SCRIPT_ELEMENT_SIZE`
+    PushSize,
+    /// The script has more than `MAX_OPS_PER_SCRIPT` operations
+    OpCount,
+    /// The stack has more than `MAX_STACK_SIZE` elements
+    StackSize,
+    /// Invalid signature count in OP_CHECKMULTISIG
+    SigCount,
+    /// Invalid public key count in OP_CHECKMULTISIG
+    PubkeyCount,
+    /// OP_VERIFY failed
+    Verify,
+    /// OP_EQUALVERIFY failed
+    EqualVerify,
+    /// OP_CHECKSIGVERIFY failed
+    CheckSigVerify,
+    /// OP_CHECKMULTISIGVERIFY failed
+    CheckMultiSigVerify,
+    /// OP_NUMEQUALVERIFY failed
+    NumEqualVerify,
+    /// OP_CHECKDATASIGVERIFY failed
+    CheckDataSigVerify,
+    /// An unknown or reserved opcode was executed
+    BadOpcode,
+    /// A disabled opcode is in the script
+    DisabledOpcode,
+    /// An operation needs more elements than the stack has
+    InvalidStackOperation,
+    /// OP_FROMALTSTACK with an empty alt stack
+    InvalidAltstackOperation,
+    /// OP_ELSE or OP_ENDIF without OP_IF, or OP_IF without OP_ENDIF
+    UnbalancedConditional,
+    /// The lock time of OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY is negative
+    NegativeLockTime,
+    /// The lock time of OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY is not satisfied
+    UnsatisfiedLockTime,
+    /// The sighash type of a signature is not defined
+    SigHashType,
+    /// An ECDSA signature is not strict DER
+    SigDer,
+    /// A push or a number is not minimally encoded
+    MinimalData,
+    /// The scriptSig does not only push data
+    SigPushOnly,
+    /// An ECDSA signature has a high S value
+    SigHighS,
+    /// The extra element popped by OP_CHECKMULTISIG is not empty
+    SigNullDummy,
+    /// A public key is neither compressed nor uncompressed
+    PubkeyType,
+    /// The stack does not have exactly one element after evaluation
+    CleanStack,
+    /// The argument of OP_IF or OP_NOTIF is not minimal
+    MinimalIf,
+    /// A failed signature is not empty
+    SigNullFail,
+    /// A NOP reserved for upgrades was executed
+    DiscourageUpgradableNops,
+    /// The element popped by OP_COLOR is not a valid color identifier
+    InvalidColorIdentifier,
+    /// OP_COLOR appears more than once in the script
+    MultipleColor,
+    /// OP_COLOR is executed inside a conditional branch
+    ColorInBranch,
+    /// The verification flags set CLEANSTACK without P2SH
+    InvalidFlags,
+    /// The input index is out of the range of the transaction inputs
+    InputIndexOutOfRange(usize),
+    /// The output spent by an input is unknown
+    UnknownSpentOutput(OutPoint),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Script(ref e) => write!(f, "invalid script: {}", e),
+            Error::InputIndexOutOfRange(index) => write!(f, "input index out of range: {}", index),
+            Error::UnknownSpentOutput(ref out_point) => write!(f, "unknown spent output: {}", out_point),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Script(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Script(_) => "invalid script",
+            Error::EvalFalse => "script evaluated to false",
+            Error::OpReturn => "OP_RETURN was executed",
+            Error::ScriptSize => "script is too large",
+            Error::PushSize => "push is too large",
+            Error::OpCount => "too many operations",
+            Error::StackSize => "stack is too large",
+            Error::SigCount => "invalid signature count",
+            Error::PubkeyCount => "invalid public key count",
+            Error::Verify => "OP_VERIFY failed",
+            Error::EqualVerify => "OP_EQUALVERIFY failed",
+            Error::CheckSigVerify => "OP_CHECKSIGVERIFY failed",
+            Error::CheckMultiSigVerify => "OP_CHECKMULTISIGVERIFY failed",
+            Error::NumEqualVerify => "OP_NUMEQUALVERIFY failed",
+            Error::CheckDataSigVerify => "OP_CHECKDATASIGVERIFY failed",
+            Error::BadOpcode => "bad opcode",
+            Error::DisabledOpcode => "disabled opcode",
+            Error::InvalidStackOperation => "operation not valid with the current stack size",
+            Error::InvalidAltstackOperation => "operation not valid with the current alt stack size",
+            Error::UnbalancedConditional => "unbalanced conditional",
+            Error::NegativeLockTime => "negative lock time",
+            Error::UnsatisfiedLockTime => "lock time requirement not satisfied",
+            Error::SigHashType => "undefined sighash type",
+            Error::SigDer => "non-DER ECDSA signature",
+            Error::MinimalData => "non-minimal data",
+            Error::SigPushOnly => "scriptSig is not push only",
+            Error::SigHighS => "ECDSA signature with high S value",
+            Error::SigNullDummy => "OP_CHECKMULTISIG dummy element is not empty",
+            Error::PubkeyType => "invalid public key encoding",
+            Error::CleanStack => "stack is not clean after evaluation",
+            Error::MinimalIf => "OP_IF argument is not minimal",
+            Error::SigNullFail => "failed signature is not empty",
+            Error::DiscourageUpgradableNops => "NOP reserved for upgrades",
+            Error::InvalidColorIdentifier => "invalid color identifier",
+            Error::MultipleColor => "more than one OP_COLOR",
+            Error::ColorInBranch => "OP_COLOR in a conditional branch",
+            Error::InvalidFlags => "CLEANSTACK without P2SH",
+            Error::InputIndexOutOfRange(_) => "input index out of range",
+            Error::UnknownSpentOutput(_) => "unknown spent output",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<script::Error> for Error {
+    fn from(e: script::Error) -> Error {
+        Error::Script(e)
+    }
+}
+
+/// Checks the signatures and lock times of the scripts of a transaction input
+pub trait SignatureChecker {
+    /// Check a signature, followed by its sighash type, against a public key.
+    /// `script_code` is the script whose signature hash is signed.
+    fn check_signature(&self, sig: &[u8], pk: &[u8], script_code: &Script) -> bool;
+
+    /// Check the argument of OP_CHECKLOCKTIMEVERIFY against the transaction
+    fn check_lock_time(&self, lock_time: i64) -> bool;
+
+    /// Check the argument of OP_CHECKSEQUENCEVERIFY against the input
+    fn check_sequence(&self, sequence: i64) -> bool;
+}
+
+/// Checks the signatures and lock times of an input of a transaction
+pub struct TransactionSignatureChecker<'a> {
+    tx: &'a Transaction,
+    input_index: usize,
+    secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+}
+
+impl<'a> TransactionSignatureChecker<'a> {
+    /// Create a checker of the input `input_index` of `tx`.
+    pub fn new(tx: &'a Transaction, input_index: usize) -> Result<TransactionSignatureChecker<'a>, Error> {
+        if input_index >= tx.input.len() {
+            return Err(Error::InputIndexOutOfRange(input_index));
+        }
+        Ok(TransactionSignatureChecker {
+            tx: tx,
+            input_index: input_index,
+            secp: secp256k1::Secp256k1::verification_only(),
+        })
+    }
+}
+
+impl<'a> SignatureChecker for TransactionSignatureChecker<'a> {
+    fn check_signature(&self, sig: &[u8], pk: &[u8], script_code: &Script) -> bool {
+        let (&sighash_type, sig) = match sig.split_last() {
+            Some(x) => x,
+            None => return false,
+        };
+        let pk = match PublicKey::from_slice(pk) {
+            Ok(pk) => pk,
+            Err(_) => return false,
+        };
+        let sighash = self.tx.signature_hash(self.input_index, script_code, sighash_type as u32);
+
+        if sig.len() == SCHNORR_SIGNATURE_SIZE {
+            let mut r_x = [0u8; 32];
+            let mut sigma = [0u8; 32];
+            r_x.copy_from_slice(&sig[..32]);
+            sigma.copy_from_slice(&sig[32..]);
+            Signature { r_x: r_x, sigma: sigma }.verify(&sighash.into_inner(), &pk).is_ok()
+        } else {
+            let mut sig = match secp256k1::Signature::from_der_lax(sig) {
+                Ok(sig) => sig,
+                Err(_) => return false,
+            };
+            // libsecp256k1 only accepts low S signatures
+            sig.normalize_s();
+            let msg = match secp256k1::Message::from_slice(&sighash[..]) {
+                Ok(msg) => msg,
+                Err(_) => return false,
+            };
+            self.secp.verify(&msg, &sig, &pk.key).is_ok()
+        }
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.tx.lock_time as i64;
+        // the lock time and the lock time of the transaction must be of the same type
+        if (tx_lock_time < LOCKTIME_THRESHOLD) != (lock_time < LOCKTIME_THRESHOLD) {
+            return false;
+        }
+        if lock_time > tx_lock_time {
+            return false;
+        }
+        // the lock time of the transaction is ignored if the input is final
+        self.tx.input[self.input_index].sequence != 0xffffffff
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.tx.input[self.input_index].sequence as i64;
+        if self.tx.version < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let tx_sequence = tx_sequence & mask;
+        let sequence = sequence & mask;
+        if (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+        sequence <= tx_sequence
+    }
+}
+
+/// Evaluate a script on a stack
+pub fn eval_script<C: SignatureChecker>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &Script,
+    flags: VerifyFlags,
+    checker: &C,
+) -> Result<(), Error> {
+    if script.len() > MAX_SCRIPT_SIZE {
+        return Err(Error::ScriptSize);
+    }
+    let require_minimal = flags.has(VerifyFlags::MINIMALDATA);
+
+    let bytes = script.as_bytes();
+    let mut exec_stack: Vec<bool> = vec![];
+    let mut alt_stack: Vec<Vec<u8>> = vec![];
+    let mut op_count = 0;
+    // the position after the last executed OP_CODESEPARATOR
+    let mut code_separator = 0;
+    let mut has_color = false;
+
+    let mut instructions = script.iter(false);
+    loop {
+        let pos = bytes.len() - instructions.remaining().len();
+        let instruction = match instructions.next() {
+            Some(instruction) => instruction,
+            None => break,
+        };
+        let executing = exec_stack.iter().all(|&b| b);
+
+        let op = match instruction {
+            Instruction::Error(e) => return Err(Error::Script(e)),
+            Instruction::PushBytes(data) => {
+                if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    return Err(Error::PushSize);
+                }
+                if executing {
+                    if require_minimal && !is_minimal_push(bytes[pos], data) {
+                        return Err(Error::MinimalData);
+                    }
+                    stack.push(data.to_vec());
+                }
+                check_stack_size(stack, &alt_stack)?;
+                continue;
+            }
+            Instruction::Op(op) => op,
+        };
+
+        if op.into_u8() > opcodes::all::OP_PUSHNUM_16.into_u8() {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(Error::OpCount);
+            }
+        }
+
+        let ordinary = match op.classify() {
+            opcodes::Class::IllegalOp => {
+                if op == opcodes::all::OP_VERIF || op == opcodes::all::OP_VERNOTIF {
+                    return Err(Error::BadOpcode);
+                }
+                return Err(Error::DisabledOpcode);
+            }
+            opcodes::Class::Ordinary(ordinary) => Some(ordinary),
+            _ => None,
+        };
+        let is_conditional = match ordinary {
+            Some(opcodes::Ordinary::OP_IF) | Some(opcodes::Ordinary::OP_NOTIF) |
+            Some(opcodes::Ordinary::OP_ELSE) | Some(opcodes::Ordinary::OP_ENDIF) => true,
+            _ => false,
+        };
+        if !executing && !is_conditional {
+            continue;
+        }
+
+        match op.classify() {
+            opcodes::Class::PushNum(n) => stack.push(build_scriptint(n as i64)),
+            opcodes::Class::ReturnOp => {
+                if op == opcodes::all::OP_RETURN {
+                    return Err(Error::OpReturn);
+                }
+                return Err(Error::BadOpcode);
+            }
+            opcodes::Class::NoOp => {
+                if op == opcodes::all::OP_CLTV && flags.has(VerifyFlags::CHECKLOCKTIMEVERIFY) {
+                    check_depth(stack, 1)?;
+                    let lock_time = read_num(top(stack, 1), require_minimal, 5)?;
+                    if lock_time < 0 {
+                        return Err(Error::NegativeLockTime);
+                    }
+                    if !checker.check_lock_time(lock_time) {
+                        return Err(Error::UnsatisfiedLockTime);
+                    }
+                } else if op == opcodes::all::OP_CSV && flags.has(VerifyFlags::CHECKSEQUENCEVERIFY) {
+                    check_depth(stack, 1)?;
+                    let sequence = read_num(top(stack, 1), require_minimal, 5)?;
+                    if sequence < 0 {
+                        return Err(Error::NegativeLockTime);
+                    }
+                    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0 && !checker.check_sequence(sequence) {
+                        return Err(Error::UnsatisfiedLockTime);
+                    }
+                } else if op != opcodes::all::OP_NOP && flags.has(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                    return Err(Error::DiscourageUpgradableNops);
+                }
+            }
+            opcodes::Class::PushBytes(_) | opcodes::Class::IllegalOp => return Err(Error::BadOpcode),
+            opcodes::Class::Ordinary(ordinary) => {
+                use blockdata::opcodes::Ordinary::*;
+                match ordinary {
+                    // handled by the iterator
+                    OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => return Err(Error::BadOpcode),
+
+                    // control flow
+                    OP_IF | OP_NOTIF => {
+                        let mut value = false;
+                        if executing {
+                            let condition = match stack.pop() {
+                                Some(condition) => condition,
+                                None => return Err(Error::UnbalancedConditional),
+                            };
+                            if flags.has(VerifyFlags::MINIMALIF) &&
+                               (condition.len() > 1 || (condition.len() == 1 && condition[0] != 1)) {
+                                return Err(Error::MinimalIf);
+                            }
+                            value = read_scriptbool(&condition);
+                            if ordinary == OP_NOTIF {
+                                value = !value;
+                            }
+                        }
+                        exec_stack.push(value);
+                    }
+                    OP_ELSE => match exec_stack.last_mut() {
+                        Some(value) => *value = !*value,
+                        None => return Err(Error::UnbalancedConditional),
+                    },
+                    OP_ENDIF => if exec_stack.pop().is_none() {
+                        return Err(Error::UnbalancedConditional);
+                    },
+                    OP_VERIFY => if !pop_bool(stack)? {
+                        return Err(Error::Verify);
+                    },
+
+                    // stack
+                    OP_TOALTSTACK => alt_stack.push(pop(stack)?),
+                    OP_FROMALTSTACK => match alt_stack.pop() {
+                        Some(v) => stack.push(v),
+                        None => return Err(Error::InvalidAltstackOperation),
+                    },
+                    OP_2DROP => {
+                        check_depth(stack, 2)?;
+                        stack.pop();
+                        stack.pop();
+                    }
+                    OP_2DUP => {
+                        check_depth(stack, 2)?;
+                        let a = top(stack, 2).clone();
+                        let b = top(stack, 1).clone();
+                        stack.push(a);
+                        stack.push(b);
+                    }
+                    OP_3DUP => {
+                        check_depth(stack, 3)?;
+                        let a = top(stack, 3).clone();
+                        let b = top(stack, 2).clone();
+                        let c = top(stack, 1).clone();
+                        stack.push(a);
+                        stack.push(b);
+                        stack.push(c);
+                    }
+                    OP_2OVER => {
+                        check_depth(stack, 4)?;
+                        let a = top(stack, 4).clone();
+                        let b = top(stack, 3).clone();
+                        stack.push(a);
+                        stack.push(b);
+                    }
+                    OP_2ROT => {
+                        check_depth(stack, 6)?;
+                        let len = stack.len();
+                        let a = stack.remove(len - 6);
+                        let b = stack.remove(len - 6);
+                        stack.push(a);
+                        stack.push(b);
+                    }
+                    OP_2SWAP => {
+                        check_depth(stack, 4)?;
+                        let len = stack.len();
+                        stack.swap(len - 4, len - 2);
+                        stack.swap(len - 3, len - 1);
+                    }
+                    OP_DROP => {
+                        pop(stack)?;
+                    }
+                    OP_DUP => {
+                        check_depth(stack, 1)?;
+                        let a = top(stack, 1).clone();
+                        stack.push(a);
+                    }
+                    OP_NIP => {
+                        check_depth(stack, 2)?;
+                        let len = stack.len();
+                        stack.remove(len - 2);
+                    }
+                    OP_OVER => {
+                        check_depth(stack, 2)?;
+                        let a = top(stack, 2).clone();
+                        stack.push(a);
+                    }
+                    OP_PICK | OP_ROLL => {
+                        let n = pop_num(stack, require_minimal)?;
+                        if n < 0 || n as usize >= stack.len() {
+                            return Err(Error::InvalidStackOperation);
+                        }
+                        let index = stack.len() - 1 - n as usize;
+                        let a = if ordinary == OP_ROLL {
+                            stack.remove(index)
+                        } else {
+                            stack[index].clone()
+                        };
+                        stack.push(a);
+                    }
+                    OP_ROT => {
+                        check_depth(stack, 3)?;
+                        let len = stack.len();
+                        let a = stack.remove(len - 3);
+                        stack.push(a);
+                    }
+                    OP_SWAP => {
+                        check_depth(stack, 2)?;
+                        let len = stack.len();
+                        stack.swap(len - 2, len - 1);
+                    }
+                    OP_TUCK => {
+                        check_depth(stack, 2)?;
+                        let len = stack.len();
+                        let a = top(stack, 1).clone();
+                        stack.insert(len - 2, a);
+                    }
+                    OP_IFDUP => {
+                        check_depth(stack, 1)?;
+                        if read_scriptbool(top(stack, 1)) {
+                            let a = top(stack, 1).clone();
+                            stack.push(a);
+                        }
+                    }
+                    OP_DEPTH => {
+                        let depth = stack.len() as i64;
+                        stack.push(build_scriptint(depth));
+                    }
+                    OP_SIZE => {
+                        check_depth(stack, 1)?;
+                        let size = top(stack, 1).len() as i64;
+                        stack.push(build_scriptint(size));
+                    }
+
+                    // equality
+                    OP_EQUAL | OP_EQUALVERIFY => {
+                        check_depth(stack, 2)?;
+                        let b = pop(stack)?;
+                        let a = pop(stack)?;
+                        let equal = a == b;
+                        if ordinary == OP_EQUALVERIFY {
+                            if !equal {
+                                return Err(Error::EqualVerify);
+                            }
+                        } else {
+                            stack.push(encode_bool(equal));
+                        }
+                    }
+
+                    // arithmetic
+                    OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                        let a = pop_num(stack, require_minimal)?;
+                        let result = match ordinary {
+                            OP_1ADD => a + 1,
+                            OP_1SUB => a - 1,
+                            OP_NEGATE => -a,
+                            OP_ABS => a.abs(),
+                            OP_NOT => (a == 0) as i64,
+                            _ => (a != 0) as i64,
+                        };
+                        stack.push(build_scriptint(result));
+                    }
+                    OP_ADD | OP_SUB | OP_BOOLAND | OP_BOOLOR |
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY | OP_NUMNOTEQUAL |
+                    OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL | OP_GREATERTHANOREQUAL |
+                    OP_MIN | OP_MAX => {
+                        check_depth(stack, 2)?;
+                        let b = pop_num(stack, require_minimal)?;
+                        let a = pop_num(stack, require_minimal)?;
+                        let result = match ordinary {
+                            OP_ADD => a + b,
+                            OP_SUB => a - b,
+                            OP_BOOLAND => (a != 0 && b != 0) as i64,
+                            OP_BOOLOR => (a != 0 || b != 0) as i64,
+                            OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                            OP_NUMNOTEQUAL => (a != b) as i64,
+                            OP_LESSTHAN => (a < b) as i64,
+                            OP_GREATERTHAN => (a > b) as i64,
+                            OP_LESSTHANOREQUAL => (a <= b) as i64,
+                            OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                            OP_MIN => if a < b { a } else { b },
+                            _ => if a > b { a } else { b },
+                        };
+                        if ordinary == OP_NUMEQUALVERIFY {
+                            if result == 0 {
+                                return Err(Error::NumEqualVerify);
+                            }
+                        } else {
+                            stack.push(build_scriptint(result));
+                        }
+                    }
+                    OP_WITHIN => {
+                        check_depth(stack, 3)?;
+                        let max = pop_num(stack, require_minimal)?;
+                        let min = pop_num(stack, require_minimal)?;
+                        let x = pop_num(stack, require_minimal)?;
+                        stack.push(encode_bool(min <= x && x < max));
+                    }
+
+                    // crypto
+                    OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                        let a = pop(stack)?;
+                        let hash = match ordinary {
+                            OP_RIPEMD160 => ripemd160::Hash::hash(&a).into_inner().to_vec(),
+                            OP_SHA1 => sha1::Hash::hash(&a).into_inner().to_vec(),
+                            OP_SHA256 => sha256::Hash::hash(&a).into_inner().to_vec(),
+                            OP_HASH160 => hash160::Hash::hash(&a).into_inner().to_vec(),
+                            _ => sha256d::Hash::hash(&a).into_inner().to_vec(),
+                        };
+                        stack.push(hash);
+                    }
+                    OP_CODESEPARATOR => code_separator = pos + 1,
+                    OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                        check_depth(stack, 2)?;
+                        let pk = pop(stack)?;
+                        let sig = pop(stack)?;
+
+                        let script_code = find_and_delete(&subscript(bytes, code_separator), &sig);
+                        check_signature_encoding(&sig, flags)?;
+                        check_pubkey_encoding(&pk, flags)?;
+                        let success = checker.check_signature(&sig, &pk, &script_code);
+                        if !success && flags.has(VerifyFlags::NULLFAIL) && !sig.is_empty() {
+                            return Err(Error::SigNullFail);
+                        }
+
+                        if ordinary == OP_CHECKSIGVERIFY {
+                            if !success {
+                                return Err(Error::CheckSigVerify);
+                            }
+                        } else {
+                            stack.push(encode_bool(success));
+                        }
+                    }
+                    OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                        // positions from the top of the stack, which is 1
+                        let mut i = 1;
+                        check_depth(stack, i)?;
+                        let mut n_keys = read_num(top(stack, i), require_minimal, 4)?;
+                        if n_keys < 0 || n_keys > MAX_PUBKEYS_PER_MULTISIG as i64 {
+                            return Err(Error::PubkeyCount);
+                        }
+                        op_count += n_keys as usize;
+                        if op_count > MAX_OPS_PER_SCRIPT {
+                            return Err(Error::OpCount);
+                        }
+                        i += 1;
+                        let mut ikey = i;
+                        // the position of the last key, for NULLFAIL
+                        let mut ikey2 = n_keys as usize + 2;
+                        i += n_keys as usize;
+                        check_depth(stack, i)?;
+
+                        let mut n_sigs = read_num(top(stack, i), require_minimal, 4)?;
+                        if n_sigs < 0 || n_sigs > n_keys {
+                            return Err(Error::SigCount);
+                        }
+                        i += 1;
+                        let mut isig = i;
+                        i += n_sigs as usize;
+                        check_depth(stack, i)?;
+
+                        let mut script_code = subscript(bytes, code_separator);
+                        for k in 0..n_sigs as usize {
+                            script_code = find_and_delete(&script_code, top(stack, isig + k));
+                        }
+
+                        let mut success = true;
+                        while success && n_sigs > 0 {
+                            let sig = top(stack, isig);
+                            let pk = top(stack, ikey);
+                            check_signature_encoding(sig, flags)?;
+                            check_pubkey_encoding(pk, flags)?;
+                            if checker.check_signature(sig, pk, &script_code) {
+                                isig += 1;
+                                n_sigs -= 1;
+                            }
+                            ikey += 1;
+                            n_keys -= 1;
+                            // more signatures left than keys
+                            if n_sigs > n_keys {
+                                success = false;
+                            }
+                        }
+
+                        // remove the counts, the keys and the signatures
+                        while i > 1 {
+                            i -= 1;
+                            if !success && flags.has(VerifyFlags::NULLFAIL) && ikey2 == 0 && !top(stack, 1).is_empty() {
+                                return Err(Error::SigNullFail);
+                            }
+                            if ikey2 > 0 {
+                                ikey2 -= 1;
+                            }
+                            stack.pop();
+                        }
+                        // remove the extra element, due to a bug in the original implementation
+                        let dummy = pop(stack)?;
+                        if flags.has(VerifyFlags::NULLDUMMY) && !dummy.is_empty() {
+                            return Err(Error::SigNullDummy);
+                        }
+
+                        if ordinary == OP_CHECKMULTISIGVERIFY {
+                            if !success {
+                                return Err(Error::CheckMultiSigVerify);
+                            }
+                        } else {
+                            stack.push(encode_bool(success));
+                        }
+                    }
+
+                    // tapyrus
+                    OP_CHECKDATASIG | OP_CHECKDATASIGVERIFY => {
+                        check_depth(stack, 3)?;
+                        let pk = pop(stack)?;
+                        let message = pop(stack)?;
+                        let sig = pop(stack)?;
+
+                        check_data_signature_encoding(&sig, flags)?;
+                        check_pubkey_encoding(&pk, flags)?;
+                        let success = !sig.is_empty() && match PublicKey::from_slice(&pk) {
+                            Ok(pk) => verify_data_signature(&sig, &message, &pk).is_ok(),
+                            Err(_) => false,
+                        };
+                        if !success && flags.has(VerifyFlags::NULLFAIL) && !sig.is_empty() {
+                            return Err(Error::SigNullFail);
+                        }
+
+                        if ordinary == OP_CHECKDATASIGVERIFY {
+                            if !success {
+                                return Err(Error::CheckDataSigVerify);
+                            }
+                        } else {
+                            stack.push(encode_bool(success));
+                        }
+                    }
+                    OP_COLOR => {
+                        if !exec_stack.is_empty() {
+                            return Err(Error::ColorInBranch);
+                        }
+                        if has_color {
+                            return Err(Error::MultipleColor);
+                        }
+                        let color_id = pop(stack)?;
+                        if ColorIdentifier::from_slice(&color_id).is_err() {
+                            return Err(Error::InvalidColorIdentifier);
+                        }
+                        has_color = true;
+                    }
+                }
+            }
+        }
+
+        check_stack_size(stack, &alt_stack)?;
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(Error::UnbalancedConditional);
+    }
+    Ok(())
+}
+
+/// Verify a scriptSig against the script pubkey of the output it spends
+pub fn verify_script<C: SignatureChecker>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    flags: VerifyFlags,
+    checker: &C,
+) -> Result<(), Error> {
+    if flags.has(VerifyFlags::CLEANSTACK) && !flags.has(VerifyFlags::P2SH) {
+        return Err(Error::InvalidFlags);
+    }
+    if flags.has(VerifyFlags::SIGPUSHONLY) && !is_push_only(script_sig) {
+        return Err(Error::SigPushOnly);
+    }
+
+    let mut stack = vec![];
+    eval_script(&mut stack, script_sig, flags, checker)?;
+    let mut p2sh_stack = stack.clone();
+    eval_script(&mut stack, script_pubkey, flags, checker)?;
+    if !stack.last().map_or(false, |top| read_scriptbool(top)) {
+        return Err(Error::EvalFalse);
+    }
+
+    if flags.has(VerifyFlags::P2SH) && (script_pubkey.is_p2sh() || script_pubkey.is_cp2sh()) {
+        if !is_push_only(script_sig) {
+            return Err(Error::SigPushOnly);
+        }
+        // the stack is not empty, since the script pubkey hashed its top element
+        let redeem_script = Script::from(pop(&mut p2sh_stack)?);
+        eval_script(&mut p2sh_stack, &redeem_script, flags, checker)?;
+        if !p2sh_stack.last().map_or(false, |top| read_scriptbool(top)) {
+            return Err(Error::EvalFalse);
+        }
+        stack = p2sh_stack;
+    }
+
+    if flags.has(VerifyFlags::CLEANSTACK) && stack.len() != 1 {
+        return Err(Error::CleanStack);
+    }
+    Ok(())
+}
+
+/// Verify the scriptSig of an input of a transaction against the script
+/// pubkey of the output it spends
+pub fn verify_input(
+    tx: &Transaction,
+    input_index: usize,
+    script_pubkey: &Script,
+    flags: VerifyFlags,
+) -> Result<(), Error> {
+    let checker = TransactionSignatureChecker::new(tx, input_index)?;
+    verify_script(&tx.input[input_index].script_sig, script_pubkey, flags, &checker)
+}
+
+/// Verify all inputs of a transaction. `spent` returns the output spent by an
+/// input, and should not return the same output twice.
+pub fn verify_transaction<S>(tx: &Transaction, mut spent: S, flags: VerifyFlags) -> Result<(), Error>
+    where S: FnMut(&OutPoint) -> Option<TxOut> {
+    for (index, input) in tx.input.iter().enumerate() {
+        match spent(&input.previous_output) {
+            Some(output) => verify_input(tx, index, &output.script_pubkey, flags)?,
+            None => return Err(Error::UnknownSpentOutput(input.previous_output)),
+        }
+    }
+    Ok(())
+}
+
+/// Count the signature operations of a script. If `accurate`, the public key
+/// count of OP_CHECKMULTISIG is read from the preceding opcode, otherwise it
+/// counts as `MAX_PUBKEYS_PER_MULTISIG`.
+pub fn count_sigops(script: &Script, accurate: bool) -> usize {
+    let mut count = 0;
+    let mut last_op = None;
+    for instruction in script.iter(false) {
+        match instruction {
+            Instruction::Op(op) => {
+                if op == opcodes::all::OP_CHECKSIG || op == opcodes::all::OP_CHECKSIGVERIFY ||
+                   op == opcodes::all::OP_CHECKDATASIG || op == opcodes::all::OP_CHECKDATASIGVERIFY {
+                    count += 1;
+                } else if op == opcodes::all::OP_CHECKMULTISIG || op == opcodes::all::OP_CHECKMULTISIGVERIFY {
+                    count += match last_op.map(|op: opcodes::All| op.classify()) {
+                        Some(opcodes::Class::PushNum(n)) if accurate && n > 0 => n as usize,
+                        _ => MAX_PUBKEYS_PER_MULTISIG,
+                    };
+                }
+                last_op = Some(op);
+            }
+            Instruction::PushBytes(_) => last_op = None,
+            Instruction::Error(_) => break,
+        }
+    }
+    count
+}
+
+/// Count the signature operations of the redeem script pushed by `script_sig`
+/// if `script_pubkey` is P2SH or CP2SH, otherwise of `script_pubkey`.
+pub fn count_p2sh_sigops(script_sig: &Script, script_pubkey: &Script) -> usize {
+    if !script_pubkey.is_p2sh() && !script_pubkey.is_cp2sh() {
+        return count_sigops(script_pubkey, true);
+    }
+
+    let mut redeem_script = None;
+    for instruction in script_sig.iter(false) {
+        match instruction {
+            Instruction::PushBytes(data) => redeem_script = Some(data),
+            Instruction::Op(op) if op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8() => redeem_script = None,
+            _ => return 0,
+        }
+    }
+    match redeem_script {
+        Some(data) => count_sigops(&Script::from(data.to_vec()), true),
+        None => 0,
+    }
+}
+
+/// Whether the script only pushes data
+fn is_push_only(script: &Script) -> bool {
+    script.iter(false).all(|instruction| match instruction {
+        Instruction::PushBytes(_) => true,
+        Instruction::Op(op) => op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8(),
+        Instruction::Error(_) => false,
+    })
+}
+
+/// Whether `data` is pushed with the smallest possible push opcode
+fn is_minimal_push(opcode: u8, data: &[u8]) -> bool {
+    if data.is_empty() {
+        opcode == opcodes::all::OP_PUSHBYTES_0.into_u8()
+    } else if data.len() == 1 && (data[0] == 0x81 || (1 <= data[0] && data[0] <= 16)) {
+        // should be OP_1NEGATE or OP_1 to OP_16
+        false
+    } else if data.len() <= 75 {
+        opcode as usize == data.len()
+    } else if data.len() <= 0xff {
+        opcode == opcodes::all::OP_PUSHDATA1.into_u8()
+    } else if data.len() <= 0xffff {
+        opcode == opcodes::all::OP_PUSHDATA2.into_u8()
+    } else {
+        true
+    }
+}
+
+/// Read a number of at most `max_size` bytes from the stack
+fn read_num(v: &[u8], require_minimal: bool, max_size: usize) -> Result<i64, Error> {
+    if v.len() > max_size {
+        return Err(Error::Script(script::Error::NumericOverflow));
+    }
+    if v.is_empty() {
+        return Ok(0);
+    }
+    let last = v[v.len() - 1];
+    // a number is not minimal if its most significant byte is only the sign,
+    // unless the sign bit of the previous byte is set
+    if require_minimal && last & 0x7f == 0 && (v.len() == 1 || v[v.len() - 2] & 0x80 == 0) {
+        return Err(Error::MinimalData);
+    }
+
+    let mut ret = 0i64;
+    for (i, b) in v.iter().enumerate() {
+        ret |= (*b as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        ret &= !(0x80i64 << (8 * (v.len() - 1)));
+        ret = -ret;
+    }
+    Ok(ret)
+}
+
+fn encode_bool(b: bool) -> Vec<u8> {
+    if b { vec![1] } else { vec![] }
+}
+
+/// The element at `depth` from the top of the stack, the top being 1. The
+/// depth must have been checked.
+fn top(stack: &[Vec<u8>], depth: usize) -> &Vec<u8> {
+    &stack[stack.len() - depth]
+}
+
+fn check_depth(stack: &[Vec<u8>], depth: usize) -> Result<(), Error> {
+    if stack.len() < depth {
+        Err(Error::InvalidStackOperation)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_stack_size(stack: &[Vec<u8>], alt_stack: &[Vec<u8>]) -> Result<(), Error> {
+    if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+        Err(Error::StackSize)
+    } else {
+        Ok(())
+    }
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, Error> {
+    stack.pop().ok_or(Error::InvalidStackOperation)
+}
+
+fn pop_num(stack: &mut Vec<Vec<u8>>, require_minimal: bool) -> Result<i64, Error> {
+    read_num(&pop(stack)?, require_minimal, 4)
+}
+
+fn pop_bool(stack: &mut Vec<Vec<u8>>) -> Result<bool, Error> {
+    Ok(read_scriptbool(&pop(stack)?))
+}
+
+/// The script from `start` on
+fn subscript(bytes: &[u8], start: usize) -> Script {
+    Script::from(bytes[start..].to_vec())
+}
+
+/// Remove the pushes of `data` from the script, as signatures cannot sign themselves
+fn find_and_delete(script: &Script, data: &[u8]) -> Script {
+    let pattern = Builder::new().push_slice(data).into_script();
+    let pattern = pattern.as_bytes();
+    let bytes = script.as_bytes();
+
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut instructions = script.iter(false);
+    let mut pos = 0;
+    while instructions.next().is_some() {
+        let next = bytes.len() - instructions.remaining().len();
+        // the pattern is a single push, so it is parsed as the instruction
+        if !bytes[pos..].starts_with(pattern) {
+            result.extend_from_slice(&bytes[pos..next]);
+        }
+        pos = next;
+    }
+    Script::from(result)
+}
+
+/// Check the encoding of a signature against the flags
+fn check_signature_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+    // an empty signature is a compact way to provide an invalid one
+    if sig.is_empty() {
+        return Ok(());
+    }
+
+    if sig.len() != SCHNORR_SIGNATURE_SIZE + 1 {
+        check_ecdsa_encoding(&sig[..sig.len() - 1], flags)?;
+    }
+
+    let sighash_type = sig[sig.len() - 1] & !0x80;
+    if flags.has(VerifyFlags::STRICTENC) && (sighash_type < 1 || sighash_type > 3) {
+        return Err(Error::SigHashType);
+    }
+    Ok(())
+}
+
+/// Check the encoding of a data signature, which has no sighash type, against the flags
+fn check_data_signature_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+    if sig.is_empty() || sig.len() == SCHNORR_SIGNATURE_SIZE {
+        return Ok(());
+    }
+    check_ecdsa_encoding(sig, flags)
+}
+
+/// Check the encoding of an ECDSA signature without sighash type against the flags
+fn check_ecdsa_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+    if (flags.has(VerifyFlags::DERSIG) || flags.has(VerifyFlags::LOW_S) || flags.has(VerifyFlags::STRICTENC)) &&
+       !is_valid_der_encoding(sig) {
+        return Err(Error::SigDer);
+    }
+    if flags.has(VerifyFlags::LOW_S) {
+        let mut normalized = match secp256k1::Signature::from_der(sig) {
+            Ok(sig) => sig,
+            Err(_) => return Err(Error::SigDer),
+        };
+        let compact = normalized.serialize_compact();
+        normalized.normalize_s();
+        if normalized.serialize_compact()[..] != compact[..] {
+            return Err(Error::SigHighS);
+        }
+    }
+    Ok(())
+}
+
+/// Whether an ECDSA signature is strict DER (BIP 66)
+fn is_valid_der_encoding(sig: &[u8]) -> bool {
+    // 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S]
+    if sig.len() < 8 || sig.len() > 72 {
+        return false;
+    }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 2 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 6 != sig.len() {
+        return false;
+    }
+
+    // R must be a positive integer without unnecessary padding
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+
+    // S must be a positive integer without unnecessary padding
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+
+/// Check the encoding of a public key against the flags
+fn check_pubkey_encoding(pk: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+    let valid = match pk.len() {
+        33 => pk[0] == 0x02 || pk[0] == 0x03,
+        65 => pk[0] == 0x04,
+        _ => false,
+    };
+    if flags.has(VerifyFlags::STRICTENC) && !valid {
+        return Err(Error::PubkeyType);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Message, Secp256k1};
+
+    use hashes::{sha256, Hash};
+    use hashes::hex::FromHex;
+
+    use blockdata::color::ColorIdentifier;
+    use blockdata::interpreter::*;
+    use blockdata::opcodes::all::*;
+    use blockdata::script::{self, Builder, Script};
+    use blockdata::transaction::{SigHashType, Transaction, TxOut};
+    use consensus::encode::deserialize;
+    use util::address::Address;
+    use util::key::PrivateKey;
+    use util::scriptsig::ScriptSignature;
+    use util::signature::Signature;
+
+    fn tx() -> Transaction {
+        deserialize(&Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()[..]).unwrap()
+    }
+
+    fn eval(script: &Script, flags: VerifyFlags) -> Result<Vec<Vec<u8>>, Error> {
+        let tx = tx();
+        let mut stack = vec![];
+        eval_script(&mut stack, script, flags, &TransactionSignatureChecker::new(&tx, 0).unwrap())?;
+        Ok(stack)
+    }
+
+    #[test]
+    fn eval_script_test() {
+        let script = Builder::new()
+            .push_int(2).push_int(3).push_opcode(OP_ADD)
+            .push_int(5).push_opcode(OP_EQUAL)
+            .into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Ok(vec![vec![1]]));
+
+        let script = Builder::new()
+            .push_int(1).push_int(2).push_int(3).push_opcode(OP_ROT)
+            .push_opcode(OP_DEPTH).push_opcode(OP_SWAP).push_opcode(OP_DROP)
+            .into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Ok(vec![vec![2], vec![3], vec![3]]));
+
+        let script = Builder::new()
+            .push_slice(b"abc").push_opcode(OP_SHA256)
+            .push_slice(&Vec::<u8>::from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad").unwrap())
+            .push_opcode(OP_EQUALVERIFY)
+            .push_int(-1).push_opcode(OP_ABS)
+            .into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Ok(vec![vec![1]]));
+
+        // conditionals
+        let script = Builder::new()
+            .push_int(0)
+            .push_opcode(OP_IF).push_opcode(OP_RETURN)
+            .push_opcode(OP_ELSE).push_int(7)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Ok(vec![vec![7]]));
+        let script = Builder::new().push_int(1).push_opcode(OP_IF).push_opcode(OP_RETURN).push_opcode(OP_ENDIF).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::OpReturn));
+        let script = Builder::new().push_int(1).push_opcode(OP_IF).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::UnbalancedConditional));
+        let script = Builder::new().push_opcode(OP_ENDIF).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::UnbalancedConditional));
+        let script = Builder::new().push_int(2).push_opcode(OP_IF).push_opcode(OP_ENDIF).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::MinimalIf));
+        assert_eq!(eval(&script, VerifyFlags::NONE), Ok(vec![]));
+
+        // disabled opcodes fail even if not executed
+        let script = Builder::new().push_int(0).push_opcode(OP_IF).push_opcode(OP_CAT).push_opcode(OP_ENDIF).into_script();
+        assert_eq!(eval(&script, VerifyFlags::NONE), Err(Error::DisabledOpcode));
+
+        // stack errors
+        assert_eq!(eval(&Builder::new().push_opcode(OP_DROP).into_script(), VerifyFlags::NONE),
+                   Err(Error::InvalidStackOperation));
+        assert_eq!(eval(&Builder::new().push_opcode(OP_FROMALTSTACK).into_script(), VerifyFlags::NONE),
+                   Err(Error::InvalidAltstackOperation));
+        assert_eq!(eval(&Builder::new().push_int(0).push_opcode(OP_VERIFY).into_script(), VerifyFlags::NONE),
+                   Err(Error::Verify));
+
+        // numbers
+        let script = Script::from(vec![0x01, 0x05]);
+        assert_eq!(eval(&script, VerifyFlags::MINIMALDATA), Err(Error::MinimalData));
+        assert_eq!(eval(&script, VerifyFlags::NONE), Ok(vec![vec![5]]));
+        let script = Builder::new().push_slice(&[1, 0]).push_opcode(OP_1ADD).into_script();
+        assert_eq!(eval(&script, VerifyFlags::MINIMALDATA), Err(Error::MinimalData));
+        assert_eq!(eval(&script, VerifyFlags::NONE), Ok(vec![vec![2]]));
+        let script = Builder::new().push_slice(&[1, 0, 0, 0, 0]).push_opcode(OP_1ADD).into_script();
+        assert_eq!(eval(&script, VerifyFlags::NONE), Err(Error::Script(script::Error::NumericOverflow)));
+
+        // limits
+        let mut builder = Builder::new();
+        for _ in 0..MAX_OPS_PER_SCRIPT + 1 {
+            builder = builder.push_opcode(OP_NOP);
+        }
+        assert_eq!(eval(&builder.into_script(), VerifyFlags::NONE), Err(Error::OpCount));
+        let script = Builder::new().push_slice(&[0; MAX_SCRIPT_ELEMENT_SIZE + 1]).into_script();
+        assert_eq!(eval(&script, VerifyFlags::NONE), Err(Error::PushSize));
+        let mut builder = Builder::new();
+        for _ in 0..MAX_STACK_SIZE + 1 {
+            builder = builder.push_int(1);
+        }
+        assert_eq!(eval(&builder.into_script(), VerifyFlags::NONE), Err(Error::StackSize));
+
+        // upgradable NOPs
+        let script = Builder::new().push_opcode(OP_NOP10).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::DiscourageUpgradableNops));
+        assert_eq!(eval(&script, VerifyFlags::MANDATORY), Ok(vec![]));
+
+        // lock times: the transaction has lock time 0 and a final input
+        let script = Builder::new().push_int(0).push_opcode(OP_CLTV).into_script();
+        assert_eq!(eval(&script, VerifyFlags::MANDATORY), Err(Error::UnsatisfiedLockTime));
+        let script = Builder::new().push_int(-1).push_opcode(OP_CLTV).into_script();
+        assert_eq!(eval(&script, VerifyFlags::MANDATORY), Err(Error::NegativeLockTime));
+        let script = Builder::new().push_int(1).push_opcode(OP_CSV).into_script();
+        assert_eq!(eval(&script, VerifyFlags::MANDATORY), Err(Error::UnsatisfiedLockTime));
+    }
+
+    #[test]
+    fn color_test() {
+        let p2pkh = Script::from(Vec::<u8>::from_hex("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac").unwrap());
+        let color_id = ColorIdentifier::reissuable(&p2pkh);
+
+        let script = Builder::new().push_slice(&color_id.to_bytes()).push_opcode(OP_COLOR).push_int(1).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Ok(vec![vec![1]]));
+
+        let mut bytes = color_id.to_bytes();
+        bytes[0] = 0xc4;
+        let script = Builder::new().push_slice(&bytes).push_opcode(OP_COLOR).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::InvalidColorIdentifier));
+
+        let script = Builder::new()
+            .push_slice(&color_id.to_bytes()).push_opcode(OP_COLOR)
+            .push_slice(&color_id.to_bytes()).push_opcode(OP_COLOR)
+            .into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::MultipleColor));
+
+        let script = Builder::new()
+            .push_slice(&color_id.to_bytes()).push_int(1)
+            .push_opcode(OP_IF).push_opcode(OP_COLOR).push_opcode(OP_ENDIF)
+            .into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::ColorInBranch));
+    }
+
+    #[test]
+    fn check_data_sig_test() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pk = key.public_key(&secp);
+        let message = b"the price is 100";
+        let hash = sha256::Hash::hash(message);
+
+        let schnorr = Signature::sign(&key, &hash.into_inner()).unwrap();
+        let mut schnorr_sig = schnorr.r_x.to_vec();
+        schnorr_sig.extend_from_slice(&schnorr.sigma[..]);
+        let ecdsa_sig = secp.sign(&Message::from_slice(&hash[..]).unwrap(), &key.key).serialize_der().to_vec();
+
+        for sig in &[schnorr_sig, ecdsa_sig] {
+            let script = Builder::new().push_slice(sig).push_check_data_sig(message, &pk).into_script();
+            assert_eq!(eval(&script, VerifyFlags::STANDARD), Ok(vec![vec![1]]));
+            assert_eq!(count_sigops(&script, true), 1);
+
+            let script = Builder::new().push_slice(sig).push_check_data_sig(b"the price is 200", &pk).into_script();
+            assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::SigNullFail));
+            assert_eq!(eval(&script, VerifyFlags::MANDATORY), Ok(vec![vec![]]));
+            let script = Builder::new().push_slice(sig).push_check_data_sig(b"the price is 200", &pk).push_verify().into_script();
+            assert_eq!(eval(&script, VerifyFlags::MANDATORY), Err(Error::CheckDataSigVerify));
+        }
+
+        let script = Builder::new().push_int(0).push_check_data_sig(message, &pk).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Ok(vec![vec![]]));
+        let script = Builder::new().push_slice(&[0x30, 0x00]).push_check_data_sig(message, &pk).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::SigDer));
+        let script = Builder::new().push_check_data_sig(message, &pk).into_script();
+        assert_eq!(eval(&script, VerifyFlags::STANDARD), Err(Error::InvalidStackOperation));
+    }
+
+    #[test]
+    fn verify_input_test() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pk = key.public_key(&secp);
+        let other = PrivateKey::from_wif("cMcfH8sRgBgDMfpBNG6H3haaxLkaYXgqMRef8Nev6tWyBSNr6c3n").unwrap();
+        let other_pk = other.public_key(&secp);
+        let flags = VerifyFlags::STANDARD;
+
+        // Schnorr and ECDSA signatures for p2pkh and cp2pkh
+        let mut tx = tx();
+        let p2pkh = Address::p2pkh(&pk, key.network).script_pubkey();
+        let cp2pkh = p2pkh.add_color(&ColorIdentifier::reissuable(&p2pkh)).unwrap();
+        for script_pubkey in &[p2pkh.clone(), cp2pkh] {
+            let schnorr = ScriptSignature::sign_schnorr(&tx, 0, script_pubkey, SigHashType::All, &key).unwrap();
+            let ecdsa = ScriptSignature::sign_ecdsa(&secp, &tx, 0, script_pubkey, SigHashType::All, &key).unwrap();
+            for sig in &[schnorr, ecdsa] {
+                tx.input[0].script_sig = Builder::new().push_slice(&sig.serialize()).push_key(&pk).into_script();
+                assert_eq!(verify_input(&tx, 0, script_pubkey, flags), Ok(()));
+                tx.input[0].script_sig = Builder::new().push_slice(&sig.serialize()).push_key(&other_pk).into_script();
+                assert_eq!(verify_input(&tx, 0, script_pubkey, flags), Err(Error::EqualVerify));
+            }
+        }
+
+        let ecdsa = ScriptSignature::sign_ecdsa(&secp, &tx, 0, &p2pkh, SigHashType::All, &key).unwrap();
+        tx.input[0].script_sig = Builder::new().push_slice(&ecdsa.serialize()).push_key(&pk).into_script();
+        assert_eq!(verify_input(&tx, 1, &p2pkh, flags), Err(Error::InputIndexOutOfRange(1)));
+        assert!(TransactionSignatureChecker::new(&tx, 1).is_err());
+        assert_eq!(verify_input(&tx, 0, &p2pkh, VerifyFlags::CLEANSTACK), Err(Error::InvalidFlags));
+        let prev_out = tx.input[0].previous_output;
+        assert_eq!(verify_transaction(&tx, |_| Some(TxOut { value: 0, script_pubkey: p2pkh.clone() }), flags), Ok(()));
+        assert_eq!(verify_transaction(&tx, |_| None, flags), Err(Error::UnknownSpentOutput(prev_out)));
+
+        // wrong signature
+        let schnorr = ScriptSignature::sign_schnorr(&tx, 0, &p2pkh, SigHashType::All, &other).unwrap();
+        let script_pubkey = Builder::new().push_key(&pk).push_opcode(OP_CHECKSIG).into_script();
+        tx.input[0].script_sig = Builder::new().push_slice(&schnorr.serialize()).into_script();
+        assert_eq!(verify_input(&tx, 0, &script_pubkey, flags), Err(Error::SigNullFail));
+        assert_eq!(verify_input(&tx, 0, &script_pubkey, VerifyFlags::MANDATORY), Err(Error::EvalFalse));
+        tx.input[0].script_sig = Builder::new().push_int(0).into_script();
+        assert_eq!(verify_input(&tx, 0, &script_pubkey, flags), Err(Error::EvalFalse));
+
+        // 1-of-2 multisig in P2SH
+        let redeem_script = Builder::new()
+            .push_int(1).push_key(&other_pk).push_key(&pk).push_int(2).push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let p2sh = redeem_script.to_p2sh();
+        let schnorr = ScriptSignature::sign_schnorr(&tx, 0, &redeem_script, SigHashType::All, &key).unwrap();
+        tx.input[0].script_sig = Builder::new()
+            .push_int(0).push_slice(&schnorr.serialize()).push_slice(redeem_script.as_bytes())
+            .into_script();
+        assert_eq!(verify_input(&tx, 0, &p2sh, flags), Ok(()));
+        tx.input[0].script_sig = Builder::new()
+            .push_int(1).push_slice(&schnorr.serialize()).push_slice(redeem_script.as_bytes())
+            .into_script();
+        assert_eq!(verify_input(&tx, 0, &p2sh, flags), Err(Error::SigNullDummy));
+        tx.input[0].script_sig = Builder::new()
+            .push_int(0).push_slice(&schnorr.serialize()).push_slice(redeem_script.as_bytes())
+            .push_opcode(OP_NOP)
+            .into_script();
+        assert_eq!(verify_input(&tx, 0, &p2sh, VerifyFlags::P2SH), Err(Error::SigPushOnly));
+
+        // sigops
+        assert_eq!(count_sigops(&p2pkh, true), 1);
+        assert_eq!(count_sigops(&redeem_script, true), 2);
+        assert_eq!(count_sigops(&redeem_script, false), MAX_PUBKEYS_PER_MULTISIG);
+        assert_eq!(count_sigops(&p2sh, true), 0);
+        tx.input[0].script_sig = Builder::new()
+            .push_int(0).push_slice(&schnorr.serialize()).push_slice(redeem_script.as_bytes())
+            .into_script();
+        assert_eq!(count_p2sh_sigops(&tx.input[0].script_sig, &p2sh), 2);
+    }
+}
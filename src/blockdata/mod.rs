@@ -19,7 +19,10 @@
 //!
 
 pub mod block;
+pub mod color;
 pub mod constants;
+pub mod genesis;
+pub mod interpreter;
 pub mod opcodes;
 pub mod script;
 pub mod transaction;
@@ -415,12 +415,13 @@ pub mod all {
     /// Does nothing
     pub const OP_NOP10: All = All {code: 0xb9};
     // Every other opcode acts as OP_RETURN
-    /// Synonym for OP_RETURN
-    pub const OP_RETURN_186: All = All {code: 0xba};
-    /// Synonym for OP_RETURN
-    pub const OP_RETURN_187: All = All {code: 0xbb};
-    /// Synonym for OP_RETURN
-    pub const OP_RETURN_188: All = All {code: 0xbc};
+    /// Pop a public key, a message and a signature, and push 1 if the signature
+    /// of the SHA256 of the message is valid for the key, 0 otherwise
+    pub const OP_CHECKDATASIG: All = All {code: 0xba};
+    /// Same as OP_CHECKDATASIG, but fail the script instead of pushing 0
+    pub const OP_CHECKDATASIGVERIFY: All = All {code: 0xbb};
+    /// Pop the top stack item as a color identifier and mark the output as colored with it
+    pub const OP_COLOR: All = All {code: 0xbc};
     /// Synonym for OP_RETURN
     pub const OP_RETURN_189: All = All {code: 0xbd};
     /// Synonym for OP_RETURN
@@ -649,6 +650,9 @@ impl fmt::Debug for All {
             all::OP_CHECKMULTISIGVERIFY => write!(f, "CHECKMULTISIGVERIFY"),
             all::OP_CLTV => write!(f, "CLTV"),
             all::OP_CSV => write!(f, "CSV"),
+            all::OP_CHECKDATASIG => write!(f, "CHECKDATASIG"),
+            all::OP_CHECKDATASIGVERIFY => write!(f, "CHECKDATASIGVERIFY"),
+            all::OP_COLOR => write!(f, "COLOR"),
             All {code: x} if x >= all::OP_NOP1.code && x <= all::OP_NOP10.code => write!(f, "NOP{}", x - all::OP_NOP1.code + 1),
             All {code: x} => write!(f, "RETURN_{}", x),
         }
@@ -674,10 +678,10 @@ impl All {
                   (all::OP_NOP1.code <= self.code &&
                    self.code <= all::OP_NOP10.code) {
             Class::NoOp
-        // 75 opcodes
+        // 72 opcodes
         } else if *self == all::OP_RESERVED || *self == all::OP_VER || *self == all::OP_RETURN ||
                   *self == all::OP_RESERVED1 || *self == all::OP_RESERVED2 ||
-                  self.code >= all::OP_RETURN_186.code {
+                  self.code >= all::OP_RETURN_189.code {
             Class::ReturnOp
         // 1 opcode
         } else if *self == all::OP_PUSHNUM_NEG1 {
@@ -689,7 +693,7 @@ impl All {
         // 76 opcodes
         } else if self.code <= all::OP_PUSHBYTES_75.code {
             Class::PushBytes(self.code as u32)
-        // 60 opcodes
+        // 63 opcodes
         } else {
             Class::Ordinary(Ordinary::try_from_all(*self).unwrap())
         }
@@ -781,7 +785,7 @@ macro_rules! ordinary_opcode {
     );
 }
 
-// "Ordinary" opcodes -- should be 60 of these
+// "Ordinary" opcodes -- should be 63 of these
 ordinary_opcode! {
     // pushdata
     OP_PUSHDATA1, OP_PUSHDATA2, OP_PUSHDATA4,
@@ -803,7 +807,9 @@ ordinary_opcode! {
     // crypto
     OP_RIPEMD160, OP_SHA1, OP_SHA256, OP_HASH160, OP_HASH256,
     OP_CODESEPARATOR, OP_CHECKSIG, OP_CHECKSIGVERIFY,
-    OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY
+    OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY,
+    // tapyrus
+    OP_CHECKDATASIG, OP_CHECKDATASIGVERIFY, OP_COLOR
 }
 
 impl Ordinary {
@@ -1021,9 +1027,9 @@ mod tests {
         roundtrip!(unique, OP_NOP8);
         roundtrip!(unique, OP_NOP9);
         roundtrip!(unique, OP_NOP10);
-        roundtrip!(unique, OP_RETURN_186);
-        roundtrip!(unique, OP_RETURN_187);
-        roundtrip!(unique, OP_RETURN_188);
+        roundtrip!(unique, OP_CHECKDATASIG);
+        roundtrip!(unique, OP_CHECKDATASIGVERIFY);
+        roundtrip!(unique, OP_COLOR);
         roundtrip!(unique, OP_RETURN_189);
         roundtrip!(unique, OP_RETURN_190);
         roundtrip!(unique, OP_RETURN_191);
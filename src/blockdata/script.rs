@@ -30,6 +30,7 @@ use std::{error, fmt, io};
 #[cfg(feature = "serde")] use serde;
 
 use hash_types::{ScriptHash, WScriptHash};
+use blockdata::color::{ColorIdentifier, COLOR_IDENTIFIER_SIZE};
 use blockdata::opcodes;
 use consensus::{encode, Decodable, Encodable};
 use hashes::Hash;
@@ -137,7 +138,7 @@ impl convert::From<bitcoinconsensus::Error> for Error {
     }
 }
 /// Helper to encode an integer in script format
-fn build_scriptint(n: i64) -> Vec<u8> {
+pub(crate) fn build_scriptint(n: i64) -> Vec<u8> {
     if n == 0 { return vec![] }
 
     let neg = n < 0;
@@ -278,6 +279,35 @@ impl Script {
             self.0[34] == opcodes::all::OP_CHECKSIG.into_u8())
     }
 
+    /// Checks whether a script pubkey is a bare multisig output, i.e.
+    /// `m <pubkey>... n OP_CHECKMULTISIG` with `m <= n`
+    pub fn is_multisig(&self) -> bool {
+        let len = self.0.len();
+        if len < 3 || self.0[len - 1] != opcodes::all::OP_CHECKMULTISIG.into_u8() {
+            return false;
+        }
+        let pushnum = |b: u8| {
+            if b >= opcodes::all::OP_PUSHNUM_1.into_u8() && b <= opcodes::all::OP_PUSHNUM_16.into_u8() {
+                Some(b - opcodes::all::OP_PUSHNUM_1.into_u8() + 1)
+            } else {
+                None
+            }
+        };
+        let n = match (pushnum(self.0[0]), pushnum(self.0[len - 2])) {
+            (Some(m), Some(n)) if m <= n => n,
+            _ => return false,
+        };
+        let keys = Instructions { data: &self.0[1..len - 2], enforce_minimal: true };
+        let mut count = 0;
+        for instruction in keys {
+            match instruction {
+                Instruction::PushBytes(key) if key.len() == 33 || key.len() == 65 => count += 1,
+                _ => return false,
+            }
+        }
+        count == n
+    }
+
     /// Checks whether a script pubkey is a Segregated Witness (segwit) program.
     #[inline]
     pub fn is_witness_program(&self) -> bool {
@@ -319,6 +349,64 @@ impl Script {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]) == opcodes::all::OP_RETURN)
     }
 
+    /// Checks whether a script pubkey is colored, i.e. starts with a valid color
+    /// identifier followed by OP_COLOR
+    #[inline]
+    pub fn is_colored(&self) -> bool {
+        self.color_id().is_some()
+    }
+
+    /// Checks whether a script pubkey is a colored p2pkh (cp2pkh) output
+    #[inline]
+    pub fn is_cp2pkh(&self) -> bool {
+        self.is_colored() && self.uncolored_bytes().len() == 25 && self.remove_color().is_p2pkh()
+    }
+
+    /// Checks whether a script pubkey is a colored p2sh (cp2sh) output
+    #[inline]
+    pub fn is_cp2sh(&self) -> bool {
+        self.is_colored() && self.uncolored_bytes().len() == 23 && self.remove_color().is_p2sh()
+    }
+
+    /// Returns the color identifier of a colored script pubkey
+    pub fn color_id(&self) -> Option<ColorIdentifier> {
+        if self.0.len() <= COLOR_IDENTIFIER_SIZE + 1 ||
+            self.0[0] != opcodes::all::OP_PUSHBYTES_33.into_u8() ||
+            self.0[COLOR_IDENTIFIER_SIZE + 1] != opcodes::all::OP_COLOR.into_u8() {
+            return None;
+        }
+        ColorIdentifier::from_slice(&self.0[1..COLOR_IDENTIFIER_SIZE + 1]).ok()
+    }
+
+    /// Colors a p2pkh or p2sh script pubkey with `color_id`, turning it into a
+    /// cp2pkh or cp2sh output. Returns `None` for any other script.
+    pub fn add_color(&self, color_id: &ColorIdentifier) -> Option<Script> {
+        if !self.is_p2pkh() && !self.is_p2sh() {
+            return None;
+        }
+        let mut data = Builder::new()
+            .push_slice(&color_id.to_bytes()[..])
+            .push_opcode(opcodes::all::OP_COLOR)
+            .into_script()
+            .into_bytes();
+        data.extend_from_slice(&self.0[..]);
+        Some(Script::from(data))
+    }
+
+    /// Returns the script pubkey without its color identifier and OP_COLOR,
+    /// or a copy of the script if it is not colored
+    pub fn remove_color(&self) -> Script {
+        Script::from(self.uncolored_bytes().to_vec())
+    }
+
+    fn uncolored_bytes(&self) -> &[u8] {
+        if self.is_colored() {
+            &self.0[COLOR_IDENTIFIER_SIZE + 2..]
+        } else {
+            &self.0[..]
+        }
+    }
+
     /// Whether a script can be proven to have no satisfying input
     pub fn is_provably_unspendable(&self) -> bool {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]).classify() == opcodes::Class::ReturnOp ||
@@ -447,6 +535,13 @@ pub struct Instructions<'a> {
     enforce_minimal: bool,
 }
 
+impl<'a> Instructions<'a> {
+    /// The part of the script which has not been iterated over yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
 impl<'a> Iterator for Instructions<'a> {
     type Item = Instruction<'a>;
 
@@ -629,6 +724,15 @@ impl Builder {
         }
     }
 
+    /// Adds a check of a data signature, e.g. of an oracle: the signature pushed
+    /// before must be a signature of the SHA256 of `message` by `key`.
+    /// Followed by `push_verify`, it becomes OP_CHECKDATASIGVERIFY.
+    pub fn push_check_data_sig(self, message: &[u8], key: &PublicKey) -> Builder {
+        self.push_slice(message)
+            .push_key(key)
+            .push_opcode(opcodes::all::OP_CHECKDATASIG)
+    }
+
     /// Adds a single opcode to the script
     pub fn push_opcode(mut self, data: opcodes::All) -> Builder {
         self.0.push(data.into_u8());
@@ -657,6 +761,10 @@ impl Builder {
                 self.0.pop();
                 self.push_opcode(opcodes::all::OP_CHECKMULTISIGVERIFY)
             },
+            Some(opcodes::all::OP_CHECKDATASIG) => {
+                self.0.pop();
+                self.push_opcode(opcodes::all::OP_CHECKDATASIGVERIFY)
+            },
             _ => self.push_opcode(opcodes::all::OP_VERIFY),
         }
     }
@@ -818,6 +926,18 @@ mod test {
         assert_eq!(&format!("{:x}", script), "76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac");
     }
 
+    #[test]
+    fn script_builder_check_data_sig() {
+        let key = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        let script = Builder::new()
+            .push_check_data_sig(b"hello", &key)
+            .push_verify()
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        assert_eq!(&format!("{:x}", script), "0568656c6c6f21032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1afbbac");
+        assert_eq!(script.asm(), "OP_PUSHBYTES_5 68656c6c6f OP_PUSHBYTES_33 032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af OP_CHECKDATASIGVERIFY OP_CHECKSIG");
+    }
+
     #[test]
     fn script_builder_verify() {
         let simple = Builder::new()
@@ -879,6 +999,16 @@ mod test {
             .into_script();
         assert_eq!(format!("{:x}", checkmultisig2), "af");
 
+        let checkdatasig = Builder::new()
+            .push_opcode(opcodes::all::OP_CHECKDATASIG)
+            .push_verify()
+            .into_script();
+        assert_eq!(format!("{:x}", checkdatasig), "bb");
+        let checkdatasig2 = Builder::from(vec![0xba])
+            .push_verify()
+            .into_script();
+        assert_eq!(format!("{:x}", checkdatasig2), "bb");
+
         let trick_slice = Builder::new()
             .push_slice(&[0xae]) // OP_CHECKMULTISIG
             .push_verify()
@@ -956,6 +1086,47 @@ mod test {
                    "OP_0 OP_PUSHBYTES_71 304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401 OP_0 OP_PUSHDATA1 552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae");
     }
 
+    #[test]
+    fn colored_script_test() {
+        let color_id = ColorIdentifier::from_str("c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167").unwrap();
+        let p2pkh = Script::from(hex_decode("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac").unwrap());
+        let p2sh = Script::from(hex_decode("a91409ab2f9b6dc85c0d7f4a4a29d0d4e0e2ee8ab8d087").unwrap());
+
+        let cp2pkh = p2pkh.add_color(&color_id).unwrap();
+        assert_eq!(format!("{:x}", cp2pkh),
+                   "21c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167bc76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac");
+        assert_eq!(cp2pkh.asm(),
+                   "OP_PUSHBYTES_33 c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167 OP_COLOR OP_DUP OP_HASH160 OP_PUSHBYTES_20 0389035a9225b3839e2bbf32d826a1e222031fd8 OP_EQUALVERIFY OP_CHECKSIG");
+        assert!(cp2pkh.is_colored());
+        assert!(cp2pkh.is_cp2pkh());
+        assert!(!cp2pkh.is_cp2sh());
+        assert!(!cp2pkh.is_p2pkh());
+        assert_eq!(cp2pkh.color_id(), Some(color_id));
+        assert_eq!(cp2pkh.remove_color(), p2pkh);
+
+        let cp2sh = p2sh.add_color(&color_id).unwrap();
+        assert_eq!(cp2sh.len(), 58);
+        assert!(cp2sh.is_colored());
+        assert!(cp2sh.is_cp2sh());
+        assert!(!cp2sh.is_cp2pkh());
+        assert_eq!(cp2sh.color_id(), Some(color_id));
+        assert_eq!(cp2sh.remove_color(), p2sh);
+
+        assert!(!p2pkh.is_colored());
+        assert_eq!(p2pkh.color_id(), None);
+        assert_eq!(p2pkh.remove_color(), p2pkh);
+        assert_eq!(cp2pkh.add_color(&color_id), None);
+        assert_eq!(Script::new().add_color(&color_id), None);
+
+        // unknown token type
+        let mut data = cp2pkh.to_bytes();
+        data[1] = 0xc4;
+        let script = Script::from(data);
+        assert!(!script.is_colored());
+        assert!(!script.is_cp2pkh());
+        assert!(!script.is_provably_unspendable());
+    }
+
     #[test]
     fn script_p2sh_p2p2k_template() {
         // random outputs I picked out of the mempool
@@ -974,6 +1145,17 @@ mod test {
         assert!(hex_script!("410496b538e853519c726a2c91e61ec11600ae1390813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781e62294721166bf621e73a82cbf2342c858eeac").is_p2pk());
     }
 
+    #[test]
+    fn script_multisig() {
+        let key = "21021aeaf2f8638a129a3156fbe7e5ef635226b0bafd495ff03afe2c843d7e3a4b51";
+        assert!(hex_script!(&format!("51{}{}52ae", key, key)).is_multisig());
+        assert!(hex_script!(&format!("52{}{}52ae", key, key)).is_multisig());
+        assert!(!hex_script!(&format!("53{}{}52ae", key, key)).is_multisig());
+        assert!(!hex_script!(&format!("51{}{}53ae", key, key)).is_multisig());
+        assert!(!hex_script!(&format!("51{}0151{}52ae", key, key)).is_multisig());
+        assert!(!hex_script!(&format!("{}ac", key)).is_multisig());
+    }
+
     #[test]
     fn p2sh_p2wsh_conversion() {
         // Test vectors taken from Core tests/data/script_tests.json
@@ -44,8 +44,8 @@ use VarInt;
 /// A reference to a transaction output
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct OutPoint {
-    /// The referenced transaction's txid
-    pub txid: Txid,
+    /// The referenced transaction's immutable txid, see `Transaction::malfix_txid`
+    pub txid: MalFixTxid,
     /// The index of the referenced output in its transaction's vout
     pub vout: u32,
 }
@@ -54,7 +54,7 @@ serde_struct_human_string_impl!(OutPoint, "an OutPoint", txid, vout);
 impl OutPoint {
     /// Create a new [OutPoint].
     #[inline]
-    pub fn new(txid: Txid, vout: u32) -> OutPoint {
+    pub fn new(txid: MalFixTxid, vout: u32) -> OutPoint {
         OutPoint {
             txid: txid,
             vout: vout,
@@ -180,7 +180,7 @@ impl ::std::str::FromStr for OutPoint {
             return Err(ParseOutPointError::Format);
         }
         Ok(OutPoint {
-            txid: Txid::from_hex(&s[..colon]).map_err(ParseOutPointError::Txid)?,
+            txid: MalFixTxid::from_hex(&s[..colon]).map_err(ParseOutPointError::Txid)?,
             vout: parse_vout(&s[colon+1..])?,
         })
     }
@@ -318,8 +318,9 @@ impl Transaction {
 
     /// Computes an "immutable TXID".  The double SHA256 taken from a transaction
     /// after stripping it of all input scripts including their length prefixes.
-    pub fn malfix_txid(&self) -> Txid {
-        let mut enc = sha256d::Hash::engine();
+    /// Outputs of the transaction are referenced by this id.
+    pub fn malfix_txid(&self) -> MalFixTxid {
+        let mut enc = MalFixTxid::engine();
         self.version.consensus_encode(&mut enc).unwrap();
         VarInt(self.input.len() as u64)
             .consensus_encode(&mut enc)
@@ -330,7 +331,7 @@ impl Transaction {
         }
         self.output.consensus_encode(&mut enc).unwrap();
         self.lock_time.consensus_encode(&mut enc).unwrap();
-        Txid::from_engine(enc)
+        MalFixTxid::from_engine(enc)
     }
 
     /// Computes a signature hash for a given input index with a given sighash flag.
@@ -466,6 +467,39 @@ impl Transaction {
     pub fn is_coin_base(&self) -> bool {
         self.input.len() == 1 && self.input[0].previous_output.is_null()
     }
+
+    /// Encodes the transaction in the Tapyrus format, which has no segwit marker
+    /// nor witnesses. Fails with `encode::Error::WitnessNotSupported` if any
+    /// input has a witness.
+    pub fn consensus_encode_strict<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        if self.input.iter().any(|input| !input.witness.is_empty()) {
+            return Err(encode::Error::WitnessNotSupported);
+        }
+        let mut len = 0;
+        len += self.version.consensus_encode(&mut s)?;
+        len += self.input.consensus_encode(&mut s)?;
+        len += self.output.consensus_encode(&mut s)?;
+        len += self.lock_time.consensus_encode(s)?;
+        Ok(len)
+    }
+
+    /// Decodes a transaction in the Tapyrus format. Unlike `consensus_decode`,
+    /// a transaction in the segwit format is rejected with
+    /// `encode::Error::WitnessNotSupported`, as is a transaction without inputs,
+    /// which cannot be told apart from the segwit marker.
+    pub fn consensus_decode_strict<D: io::Read>(mut d: D) -> Result<Transaction, encode::Error> {
+        let version = u32::consensus_decode(&mut d)?;
+        let input = Vec::<TxIn>::consensus_decode(&mut d)?;
+        if input.is_empty() {
+            return Err(encode::Error::WitnessNotSupported);
+        }
+        Ok(Transaction {
+            version: version,
+            input: input,
+            output: Decodable::consensus_decode(&mut d)?,
+            lock_time: Decodable::consensus_decode(d)?,
+        })
+    }
 }
 
 impl_consensus_encoding!(TxOut, value, script_pubkey);
@@ -648,6 +682,7 @@ mod tests {
     use blockdata::script::Script;
     use consensus::encode::serialize;
     use consensus::encode::deserialize;
+    use consensus::encode;
 
     use hashes::Hash;
     use hashes::hex::FromHex;
@@ -677,12 +712,12 @@ mod tests {
 
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:42"),
                    Ok(OutPoint{
-                       txid: Txid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
+                       txid: MalFixTxid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
                        vout: 42,
                    }));
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0"),
                    Ok(OutPoint{
-                       txid: Txid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
+                       txid: MalFixTxid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
                        vout: 0,
                    }));
     }
@@ -785,6 +820,35 @@ mod tests {
         assert_eq!(hex_tx, reser);
     }
 
+    #[test]
+    fn test_strict_encoding() {
+        let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let mut tx = Transaction::consensus_decode_strict(&hex_tx[..]).unwrap();
+        assert_eq!(tx, deserialize(&hex_tx).unwrap());
+        let mut encoded = Vec::new();
+        assert_eq!(tx.consensus_encode_strict(&mut encoded).unwrap(), hex_tx.len());
+        assert_eq!(encoded, hex_tx);
+
+        tx.input[0].witness = vec![vec![1]];
+        match tx.consensus_encode_strict(&mut Vec::<u8>::new()) {
+            Err(encode::Error::WitnessNotSupported) => {},
+            x => panic!("expected WitnessNotSupported, got {:?}", x),
+        }
+
+        let segwit_tx = Vec::<u8>::from_hex(
+            "02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c01000000\
+            00ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022\
+            100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd271\
+            0e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed89410\
+            55d3bcb8627d085e94553e62f057dcc00000000"
+        ).unwrap();
+        assert!(deserialize::<Transaction>(&segwit_tx).is_ok());
+        match Transaction::consensus_decode_strict(&segwit_tx[..]) {
+            Err(encode::Error::WitnessNotSupported) => {},
+            x => panic!("expected WitnessNotSupported, got {:?}", x),
+        }
+    }
+
     #[test]
     fn test_ntxid() {
         let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
@@ -800,6 +864,22 @@ mod tests {
         assert!(old_ntxid != tx.ntxid());
     }
 
+    #[test]
+    fn test_malfix_txid() {
+        let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let mut tx: Transaction = deserialize(&hex_tx).unwrap();
+
+        let malfix_txid = tx.malfix_txid();
+        assert_eq!(format!("{:x}", malfix_txid), "86c676062ae25c83bb1b34de4103d83dc2afc870a210521e99bd75c0e0b1c6c5");
+        assert!(malfix_txid.as_hash() != tx.txid().as_hash());
+        // changing sigs does not affect it
+        tx.input[0].script_sig = Script::new();
+        assert_eq!(malfix_txid, tx.malfix_txid());
+        // changing outputs does
+        tx.output[0].value = 0;
+        assert!(malfix_txid != tx.malfix_txid());
+    }
+
     #[test]
     fn test_txid() {
         // segwit tx from Liquid integration tests, txid/hash from Core decoderawtransaction
@@ -1200,10 +1280,11 @@ mod tests {
         let spent3: Transaction = deserialize(hex_decode("01000000027a1120a30cef95422638e8dab9dedf720ec614b1b21e451a4957a5969afb869d000000006a47304402200ecc318a829a6cad4aa9db152adbf09b0cd2de36f47b53f5dade3bc7ef086ca702205722cda7404edd6012eedd79b2d6f24c0a0c657df1a442d0a2166614fb164a4701210372f4b97b34e9c408741cd1fc97bcc7ffdda6941213ccfde1cb4075c0f17aab06ffffffffc23b43e5a18e5a66087c0d5e64d58e8e21fcf83ce3f5e4f7ecb902b0e80a7fb6010000006b483045022100f10076a0ea4b4cf8816ed27a1065883efca230933bf2ff81d5db6258691ff75202206b001ef87624e76244377f57f0c84bc5127d0dd3f6e0ef28b276f176badb223a01210309a3a61776afd39de4ed29b622cd399d99ecd942909c36a8696cfd22fc5b5a1affffffff0200127a000000000017a914f895e1dd9b29cb228e9b06a15204e3b57feaf7cc8769311d09000000001976a9144d00da12aaa51849d2583ae64525d4a06cd70fde88ac00000000")
             .unwrap().as_slice()).unwrap();
 
+        // bitcoin transactions reference their previous outputs by txid
         let mut spent = HashMap::new();
-        spent.insert(spent1.txid(), spent1);
-        spent.insert(spent2.txid(), spent2);
-        spent.insert(spent3.txid(), spent3);
+        spent.insert(MalFixTxid::from(spent1.txid().as_hash()), spent1);
+        spent.insert(MalFixTxid::from(spent2.txid().as_hash()), spent2);
+        spent.insert(MalFixTxid::from(spent3.txid().as_hash()), spent3);
         let mut spent2 = spent.clone();
         let mut spent3 = spent.clone();
 
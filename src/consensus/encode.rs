@@ -84,6 +84,8 @@ pub enum Error {
     ParseFailed(&'static str),
     /// Unsupported Segwit flag
     UnsupportedSegwitFlag(u8),
+    /// Transaction with witnesses, which Tapyrus does not support
+    WitnessNotSupported,
     /// Unrecognized network command
     UnrecognizedNetworkCommand(String),
     /// Invalid Inventory type
@@ -107,6 +109,7 @@ impl fmt::Display for Error {
             Error::ParseFailed(ref e) => write!(f, "parse failed: {}", e),
             Error::UnsupportedSegwitFlag(ref swflag) => write!(f,
                 "unsupported segwit version: {}", swflag),
+            Error::WitnessNotSupported => write!(f, "witness data is not supported in Tapyrus"),
             Error::UnrecognizedNetworkCommand(ref nwcmd) => write!(f,
                 "unrecognized network command: {}", nwcmd),
             Error::UnknownInventoryType(ref tp) => write!(f, "Unknown Inventory type: {}", tp),
@@ -127,6 +130,7 @@ impl error::Error for Error {
             | Error::UnknownNetworkMagic(..)
             | Error::ParseFailed(..)
             | Error::UnsupportedSegwitFlag(..)
+            | Error::WitnessNotSupported
             | Error::UnrecognizedNetworkCommand(..)
             | Error::UnknownInventoryType(..) => None,
         }
@@ -546,6 +550,7 @@ macro_rules! impl_array {
 
 impl_array!(2);
 impl_array!(4);
+impl_array!(6);
 impl_array!(8);
 impl_array!(12);
 impl_array!(16);
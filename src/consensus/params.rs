@@ -19,16 +19,106 @@
 
 //! Consensus parameters
 //!
-//! This module provides predefined set of parameters for different chains.
+//! This module provides predefined set of parameters for different chains,
+//! and reads the parameters of other chains from a configuration file.
 //!
 
-use network::constants::Network;
+use std::{error, fmt, fs, io};
+use std::path::{Path, PathBuf};
+
+use blockdata::block::Block;
+use blockdata::constants::{max_money, COIN_VALUE};
+use blockdata::genesis::{self, read_genesis_block};
+use hash_types::BlockHash;
+use network::constants::{Network, NetworkId};
+use util::hash::BitcoinHash;
+use util::key::PublicKey;
+
+/// The default maximum serialized size of a block, in bytes
+pub const DEFAULT_MAX_BLOCK_SIZE: u32 = 4_000_000;
+/// The number of blocks before a coinbase output can be spent
+pub const COINBASE_MATURITY: u32 = 100;
+/// The default dust relay fee, in tapyrus per kB
+pub const DEFAULT_DUST_RELAY_FEE: u64 = 3000;
+
+/// An error in reading parameters from a configuration file
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration file could not be read
+    Io(io::Error),
+    /// The configuration has no `networkid`
+    MissingNetworkId,
+    /// A value in the configuration could not be parsed
+    InvalidValue(String, String),
+    /// The genesis block is invalid
+    Genesis(genesis::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::MissingNetworkId => f.write_str(error::Error::description(self)),
+            Error::InvalidValue(ref key, ref value) => write!(f, "invalid value for {}: {}", key, value),
+            Error::Genesis(ref e) => write!(f, "invalid genesis block: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Genesis(ref e) => Some(e),
+            Error::MissingNetworkId | Error::InvalidValue(..) => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::MissingNetworkId => "missing networkid",
+            Error::InvalidValue(..) => "invalid configuration value",
+            Error::Genesis(_) => "invalid genesis block",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<genesis::Error> for Error {
+    fn from(e: genesis::Error) -> Error {
+        Error::Genesis(e)
+    }
+}
 
 #[derive(Debug, Clone)]
 /// Parameters that influence chain consensus.
 pub struct Params {
     /// Network for which parameters are valid.
     pub network: Network,
+    /// Network id, from which the magic bytes of the messages are derived.
+    pub network_id: NetworkId,
+    /// Hash of the genesis block, if it is known.
+    pub genesis_hash: Option<BlockHash>,
+    /// Aggregated public key of the federation, with which the blocks are
+    /// signed until the federation is changed by an xfield, if the genesis
+    /// block is known.
+    pub aggregated_public_key: Option<PublicKey>,
+    /// Maximum serialized size of a block, in bytes.
+    pub max_block_size: u32,
+    /// Number of confirmations after which a coinbase output can be spent.
+    pub coinbase_maturity: u32,
+    /// Fee rate below which an output is dust, in tapyrus per kB.
+    pub dust_relay_fee: u64,
+    /// Maximum amount of an output, and of the outputs of a transaction.
+    pub max_money: u64,
     /// Minimum blocks including miner confirmation of the total of 2016 blocks in a retargeting period,
     /// (nPowTargetTimespan / nPowTargetSpacing) which is also used for BIP9 deployments.
     /// Examples: 1916 for 95%, 1512 for testchains.
@@ -38,29 +128,213 @@ pub struct Params {
 }
 
 impl Params {
-    /// Creates parameters set for the given network.
+    /// Creates parameters set for the given network. No signed Tapyrus genesis
+    /// block is embedded in this library, so that `genesis_hash` and
+    /// `aggregated_public_key` are `None`: use `Params::with_genesis`, e.g.
+    /// with a block of a `GenesisRegistry`, or `Params::from_config` to set them.
     pub fn new(network: Network) -> Self {
-        match network {
-            Network::Bitcoin => Params {
-                network: Network::Bitcoin,
-                rule_change_activation_threshold: 1916, // 95%
-                miner_confirmation_window: 2016,
-            },
-            Network::Testnet => Params {
-                network: Network::Testnet,
-                rule_change_activation_threshold: 1512, // 75%
-                miner_confirmation_window: 2016,
-            },
-            Network::Regtest => Params {
-                network: Network::Regtest,
-                rule_change_activation_threshold: 108, // 75%
-                miner_confirmation_window: 144,
-            },
-            Network::Paradium => Params {
-                network: Network::Paradium,
-                rule_change_activation_threshold: 108, // 75%
-                miner_confirmation_window: 144,
-            },
+        let (rule_change_activation_threshold, miner_confirmation_window) = match network {
+            Network::Bitcoin => (1916, 2016), // 95%
+            Network::Testnet => (1512, 2016), // 75%
+            Network::Regtest | Network::Paradium | Network::Custom(_) => (108, 144), // 75%
+        };
+        Params {
+            network: network,
+            network_id: network.network_id(),
+            genesis_hash: None,
+            aggregated_public_key: None,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            coinbase_maturity: COINBASE_MATURITY,
+            dust_relay_fee: DEFAULT_DUST_RELAY_FEE,
+            max_money: max_money(network),
+            rule_change_activation_threshold: rule_change_activation_threshold,
+            miner_confirmation_window: miner_confirmation_window,
+        }
+    }
+
+    /// Parameters of the Tapyrus production network
+    pub fn prod() -> Self {
+        Params::new(Network::Bitcoin)
+    }
+
+    /// Parameters of the Tapyrus development network
+    pub fn dev() -> Self {
+        Params::new(Network::Testnet)
+    }
+
+    /// Parameters of the Tapyrus regression test network
+    pub fn regtest() -> Self {
+        Params::new(Network::Regtest)
+    }
+
+    /// Creates parameters set for a network with the given genesis block, which
+    /// must carry the aggregated public key of the federation. The genesis
+    /// block is not validated: use `blockdata::genesis` for that.
+    pub fn with_genesis(network: Network, genesis: &Block) -> Result<Self, genesis::Error> {
+        let aggregated_public_key = match genesis.header.xfield.aggregated_public_key() {
+            Some(pk) => *pk,
+            None => return Err(genesis::Error::NoAggregatedPublicKey),
+        };
+        let mut params = Params::new(network);
+        params.genesis_hash = Some(genesis.bitcoin_hash());
+        params.aggregated_public_key = Some(aggregated_public_key);
+        Ok(params)
+    }
+
+    /// Reads parameters from a configuration file of `key=value` lines, as
+    /// `tapyrus.conf`. Blank lines, comments starting with `#` and unknown keys
+    /// are ignored. The keys are:
+    ///
+    /// * `networkid`: the network id, required
+    /// * `genesis`: the path of the hex-encoded genesis block file, relative to
+    ///   the directory of the configuration file. Defaults to `genesis.<networkid>.dat`.
+    /// * `maxblocksize`, `coinbasematurity`, `dustrelayfee` (in tapyrus per kB)
+    ///   and `maxmoney` (in TPC): override the defaults
+    ///
+    /// The genesis block is validated.
+    pub fn from_config<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path)?;
+
+        let mut network_id = None;
+        let mut genesis_path = None;
+        let mut max_block_size = None;
+        let mut coinbase_maturity = None;
+        let mut dust_relay_fee = None;
+        let mut max_money = None;
+        for line in config.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut kv = line.splitn(2, '=');
+            let key = kv.next().unwrap().trim();
+            let value = kv.next().unwrap_or("").trim();
+            match key {
+                "networkid" => network_id = Some(parse_value::<u32>(key, value)?),
+                "genesis" => genesis_path = Some(PathBuf::from(value)),
+                "maxblocksize" => max_block_size = Some(parse_value(key, value)?),
+                "coinbasematurity" => coinbase_maturity = Some(parse_value(key, value)?),
+                "dustrelayfee" => dust_relay_fee = Some(parse_value(key, value)?),
+                "maxmoney" => {
+                    let tpc: u64 = parse_value(key, value)?;
+                    match tpc.checked_mul(COIN_VALUE) {
+                        Some(v) => max_money = Some(v),
+                        None => return Err(Error::InvalidValue(key.to_owned(), value.to_owned())),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let network_id = match network_id {
+            Some(id) => NetworkId::from(id),
+            None => return Err(Error::MissingNetworkId),
+        };
+        let genesis_path = genesis_path.unwrap_or_else(|| PathBuf::from(format!("genesis.{}.dat", network_id)));
+        let genesis_path = match path.parent() {
+            Some(dir) => dir.join(genesis_path),
+            None => genesis_path,
+        };
+        let genesis = read_genesis_block(genesis_path)?;
+
+        let mut params = Params::with_genesis(Network::from_network_id(network_id), &genesis)?;
+        if let Some(v) = max_block_size {
+            params.max_block_size = v;
+        }
+        if let Some(v) = coinbase_maturity {
+            params.coinbase_maturity = v;
         }
+        if let Some(v) = dust_relay_fee {
+            params.dust_relay_fee = v;
+        }
+        if let Some(v) = max_money {
+            params.max_money = v;
+        }
+        Ok(params)
+    }
+}
+
+fn parse_value<T: ::std::str::FromStr>(key: &str, value: &str) -> Result<T, Error> {
+    value.parse().map_err(|_| Error::InvalidValue(key.to_owned(), value.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use hashes::hex::ToHex;
+
+    use blockdata::genesis::{GenesisBuilder, GenesisRegistry};
+    use consensus::encode::serialize;
+    use consensus::params::{Error, Params};
+    use network::constants::{Network, NetworkId};
+    use util::hash::BitcoinHash;
+    use util::key::PrivateKey;
+
+    #[test]
+    fn presets_test() {
+        let prod = Params::prod();
+        assert_eq!(prod.network, Network::Bitcoin);
+        assert_eq!(prod.network_id, NetworkId::from(1));
+        assert_eq!(prod.max_money, 21_000_000 * 100_000_000);
+        assert_eq!(prod.coinbase_maturity, 100);
+        assert_eq!(Params::dev().network_id, NetworkId::from(1939510133));
+        assert_eq!(Params::regtest().network_id, NetworkId::from(1905960821));
+        assert_eq!(Params::regtest().miner_confirmation_window, 144);
+        // the embedded genesis blocks are placeholders, not Tapyrus genesis blocks
+        assert_eq!(prod.genesis_hash, None);
+        assert_eq!(prod.aggregated_public_key, None);
+
+        let key = PrivateKey::from_wif("KzT9HVkpBCGAVi964Hva4yJ6qQuyNQUdZ21HDuDZJpFvkGvBQiT5").unwrap();
+        let genesis = GenesisBuilder::new(1562925929).build(&key).unwrap();
+        let mut registry = GenesisRegistry::new();
+        registry.register(Network::Bitcoin, genesis.clone()).unwrap();
+        let prod = Params::with_genesis(Network::Bitcoin, &registry.get(Network::Bitcoin).unwrap()).unwrap();
+        assert_eq!(prod.genesis_hash, Some(genesis.bitcoin_hash()));
+        assert_eq!(prod.aggregated_public_key.as_ref(), genesis.header.xfield.aggregated_public_key());
+
+        let custom = Params::new(Network::Custom(NetworkId::from(1234)));
+        assert_eq!(custom.network_id, NetworkId::from(1234));
+        assert_eq!(custom.genesis_hash, None);
+        assert_eq!(custom.aggregated_public_key, None);
+    }
+
+    #[test]
+    fn from_config_test() {
+        let key = PrivateKey::from_wif("KzT9HVkpBCGAVi964Hva4yJ6qQuyNQUdZ21HDuDZJpFvkGvBQiT5").unwrap();
+        let genesis = GenesisBuilder::new(1562925929).build(&key).unwrap();
+
+        let dir = env::temp_dir().join(format!("tapyrus-params-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("genesis.1234.dat"), serialize(&genesis).to_hex()).unwrap();
+
+        let config = dir.join("tapyrus.conf");
+        fs::write(&config, "# test chain\nnetworkid=1234\ndustrelayfee=1000\nmaxmoney=1000\ntxindex=1\n").unwrap();
+        let params = Params::from_config(&config).unwrap();
+        assert_eq!(params.network, Network::Custom(NetworkId::from(1234)));
+        assert_eq!(params.genesis_hash, Some(genesis.bitcoin_hash()));
+        assert_eq!(params.aggregated_public_key.as_ref(), genesis.header.xfield.aggregated_public_key());
+        assert_eq!(params.dust_relay_fee, 1000);
+        assert_eq!(params.max_money, 1000 * 100_000_000);
+        assert_eq!(params.coinbase_maturity, 100);
+
+        fs::write(&config, "networkid=1234\ncoinbasematurity=x\n").unwrap();
+        match Params::from_config(&config) {
+            Err(Error::InvalidValue(ref key, ref value)) if key == "coinbasematurity" && value == "x" => {},
+            x => panic!("expected InvalidValue, got {:?}", x),
+        }
+        fs::write(&config, "dustrelayfee=1000\n").unwrap();
+        match Params::from_config(&config) {
+            Err(Error::MissingNetworkId) => {},
+            x => panic!("expected MissingNetworkId, got {:?}", x),
+        }
+        fs::write(&config, "networkid=1235\n").unwrap();
+        match Params::from_config(&config) {
+            Err(Error::Genesis(_)) => {},
+            x => panic!("expected Genesis error, got {:?}", x),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
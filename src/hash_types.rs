@@ -40,7 +40,9 @@ macro_rules! impl_hashencode {
 
 hash_newtype!(Txid, sha256d::Hash, 32, doc="A bitcoin transaction hash/transaction ID.");
 hash_newtype!(Wtxid, sha256d::Hash, 32, doc="A bitcoin witness transaction ID.");
+hash_newtype!(MalFixTxid, sha256d::Hash, 32, doc="An immutable transaction ID, which does not commit to the input scripts.");
 hash_newtype!(BlockHash, sha256d::Hash, 32, doc="A bitcoin block hash.");
+hash_newtype!(BlockSigHash, sha256d::Hash, 32, doc="Hash of the block header without proof, which is signed by the federation.");
 hash_newtype!(SigHash, sha256d::Hash, 32, doc="Hash of the transaction according to the signature algorithm");
 
 hash_newtype!(PubkeyHash, hash160::Hash, 20, doc="A hash of a public key.");
@@ -58,8 +60,10 @@ hash_newtype!(FilterHash, sha256d::Hash, 32, doc="Bloom filter souble-SHA256 loc
 
 impl_hashencode!(Txid);
 impl_hashencode!(Wtxid);
+impl_hashencode!(MalFixTxid);
 impl_hashencode!(SigHash);
 impl_hashencode!(BlockHash);
+impl_hashencode!(BlockSigHash);
 impl_hashencode!(TxMerkleNode);
 impl_hashencode!(WitnessMerkleNode);
 impl_hashencode!(FilterHash);
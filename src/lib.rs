@@ -63,6 +63,8 @@ pub extern crate bech32;
 #[cfg(all(test, feature = "serde"))] extern crate serde_test;
 #[cfg(all(test, feature = "unstable"))] extern crate test;
 #[cfg(feature="bitcoinconsensus")] extern crate bitcoinconsensus;
+#[cfg(feature = "async")] extern crate futures_io;
+#[cfg(all(test, feature = "async"))] extern crate futures_task;
 
 extern crate rug;
 
@@ -105,4 +107,5 @@ pub use util::hash::BitcoinHash;
 pub use util::key::PrivateKey;
 pub use util::key::PublicKey;
 pub use util::merkleblock::MerkleBlock;
+pub use util::merkleblock::MalFixMerkleBlock;
 pub use util::signature::Signature;
@@ -0,0 +1,562 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Message framing
+//!
+//! This module defines `MessageDecoder`, an incremental decoder which is fed
+//! chunks of bytes of any size, e.g. as they are read from a socket, and
+//! yields complete `RawNetworkMessage`s. The header of each message is checked
+//! before its payload is buffered: the magic bytes must be those of the
+//! network, the command well-formed and the payload length within the limit.
+//! The checksum is verified before the payload is decoded.
+//!
+//! With the `async` feature, `AsyncMessageStream` reads and writes messages
+//! over the `AsyncRead` and `AsyncWrite` traits of `futures-io`.
+//!
+
+use std::{error, fmt, io};
+
+use hashes::{sha256d, Hash};
+
+use consensus::encode::{self, MAX_VEC_SIZE};
+use network::message::RawNetworkMessage;
+use util::endian;
+
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+#[cfg(feature = "async")]
+use futures_io::{AsyncRead, AsyncWrite};
+
+/// The size of a message header: magic, command, payload length and checksum
+pub const HEADER_SIZE: usize = 24;
+
+/// An error in decoding a stream of messages
+#[derive(Debug)]
+pub enum Error {
+    /// The magic bytes are not those of the network. The stream cannot be decoded further.
+    UnexpectedMagic {
+        /// The magic bytes of the network
+        expected: u32,
+        /// The magic bytes of the message
+        actual: u32,
+    },
+    /// The command is not NUL-padded printable ASCII. The stream cannot be decoded further.
+    InvalidCommand([u8; 12]),
+    /// The payload is larger than allowed. The stream cannot be decoded further.
+    OversizedPayload {
+        /// The length of the payload
+        size: usize,
+        /// The maximum length
+        max: usize,
+    },
+    /// The checksum does not match the payload; the message was skipped
+    InvalidChecksum {
+        /// The checksum of the payload
+        expected: [u8; 4],
+        /// The checksum in the header
+        actual: [u8; 4],
+    },
+    /// The payload could not be decoded, e.g. as the command is unknown; the message was skipped
+    Payload(encode::Error),
+    /// An I/O error of the underlying stream
+    Io(io::Error),
+}
+
+impl Error {
+    /// Whether the stream can be decoded further after this error
+    pub fn is_recoverable(&self) -> bool {
+        match *self {
+            Error::InvalidChecksum { .. } | Error::Payload(_) => true,
+            Error::UnexpectedMagic { .. } | Error::InvalidCommand(_) | Error::OversizedPayload { .. } | Error::Io(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnexpectedMagic { expected, actual } => write!(f,
+                "unexpected network magic: expected {:#010x}, actual {:#010x}", expected, actual),
+            Error::InvalidCommand(ref command) => write!(f, "invalid command: {:?}", command),
+            Error::OversizedPayload { size, max } => write!(f,
+                "oversized payload: {} bytes, max {}", size, max),
+            Error::InvalidChecksum { ref expected, ref actual } => write!(f,
+                "invalid checksum: expected {:?}, actual {:?}", expected, actual),
+            Error::Payload(ref e) => write!(f, "invalid payload: {}", e),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Payload(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            Error::UnexpectedMagic { .. }
+            | Error::InvalidCommand(_)
+            | Error::OversizedPayload { .. }
+            | Error::InvalidChecksum { .. } => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::UnexpectedMagic { .. } => "unexpected network magic",
+            Error::InvalidCommand(_) => "invalid command",
+            Error::OversizedPayload { .. } => "oversized payload",
+            Error::InvalidChecksum { .. } => "invalid checksum",
+            Error::Payload(_) => "invalid payload",
+            Error::Io(_) => "I/O error",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Whether a command is printable ASCII padded with NUL bytes
+fn is_valid_command(command: &[u8]) -> bool {
+    let len = command.iter().position(|&b| b == 0).unwrap_or(command.len());
+    command[..len].iter().all(|&b| b >= 0x20 && b <= 0x7e) && command[len..].iter().all(|&b| b == 0)
+}
+
+/// Incremental decoder of a stream of messages
+#[derive(Clone, Debug)]
+pub struct MessageDecoder {
+    magic: u32,
+    max_payload_size: usize,
+    /// Received bytes, of which those before `offset` were decoded already
+    buffer: Vec<u8>,
+    offset: usize,
+}
+
+impl MessageDecoder {
+    /// Create a decoder of messages with the given magic bytes, with payloads
+    /// of up to `MAX_VEC_SIZE` bytes
+    pub fn new(magic: u32) -> MessageDecoder {
+        MessageDecoder::with_max_payload_size(magic, MAX_VEC_SIZE)
+    }
+
+    /// Create a decoder of messages with the given magic bytes and maximum payload size
+    pub fn with_max_payload_size(magic: u32, max_payload_size: usize) -> MessageDecoder {
+        MessageDecoder {
+            magic: magic,
+            max_payload_size: max_payload_size,
+            buffer: vec![],
+            offset: 0,
+        }
+    }
+
+    /// Append bytes received from the stream. The buffer only grows as bytes
+    /// arrive, not to the payload length announced in a header.
+    pub fn feed(&mut self, data: &[u8]) {
+        // Decoded bytes are dropped here once, rather than after each message
+        if self.offset > 0 {
+            self.buffer.drain(..self.offset);
+            self.offset = 0;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The number of bytes received but not decoded yet
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len() - self.offset
+    }
+
+    /// Decode the next message of the stream. Returns `None` if more bytes are
+    /// needed. After an error which is not recoverable, the same error is
+    /// returned again as the stream cannot be resynchronized.
+    pub fn decode_next(&mut self) -> Result<Option<RawNetworkMessage>, Error> {
+        let buffer = &self.buffer[self.offset..];
+        if buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let magic = endian::slice_to_u32_le(&buffer[0..4]);
+        if magic != self.magic {
+            return Err(Error::UnexpectedMagic { expected: self.magic, actual: magic });
+        }
+        if !is_valid_command(&buffer[4..16]) {
+            let mut command = [0u8; 12];
+            command.copy_from_slice(&buffer[4..16]);
+            return Err(Error::InvalidCommand(command));
+        }
+        let size = endian::slice_to_u32_le(&buffer[16..20]) as usize;
+        if size > self.max_payload_size {
+            return Err(Error::OversizedPayload { size: size, max: self.max_payload_size });
+        }
+
+        let frame_len = HEADER_SIZE + size;
+        if buffer.len() < frame_len {
+            return Ok(None);
+        }
+
+        let hash = sha256d::Hash::hash(&buffer[HEADER_SIZE..frame_len]);
+        let expected = [hash[0], hash[1], hash[2], hash[3]];
+        let mut actual = [0u8; 4];
+        actual.copy_from_slice(&buffer[20..24]);
+        let result = if expected != actual {
+            Err(Error::InvalidChecksum { expected: expected, actual: actual })
+        } else {
+            encode::deserialize(&buffer[..frame_len]).map(Some).map_err(Error::Payload)
+        };
+        self.offset += frame_len;
+        result
+    }
+}
+
+/// Reads and writes messages over an asynchronous byte stream
+#[cfg(feature = "async")]
+pub struct AsyncMessageStream<S> {
+    stream: S,
+    decoder: MessageDecoder,
+    read_buffer: Vec<u8>,
+    /// Serialized messages which were not written yet
+    write_buffer: Vec<u8>,
+}
+
+#[cfg(feature = "async")]
+impl<S> AsyncMessageStream<S> {
+    /// Wrap a stream of messages with the given magic bytes
+    pub fn new(stream: S, magic: u32) -> AsyncMessageStream<S> {
+        AsyncMessageStream {
+            stream: stream,
+            decoder: MessageDecoder::new(magic),
+            read_buffer: vec![0u8; 64 * 1024],
+            write_buffer: vec![],
+        }
+    }
+
+    /// The underlying stream
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: AsyncRead + Unpin> AsyncMessageStream<S> {
+    /// Poll for the next message, reading from the stream as needed. Returns
+    /// `None` when the stream ends between two messages.
+    pub fn poll_next_message(&mut self, cx: &mut Context) -> Poll<Result<Option<RawNetworkMessage>, Error>> {
+        loop {
+            match self.decoder.decode_next() {
+                Ok(None) => {},
+                result => return Poll::Ready(result),
+            }
+            let count = match Pin::new(&mut self.stream).poll_read(cx, &mut self.read_buffer) {
+                Poll::Ready(Ok(count)) => count,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Pending => return Poll::Pending,
+            };
+            if count == 0 {
+                return Poll::Ready(if self.decoder.buffered_len() == 0 {
+                    Ok(None)
+                } else {
+                    Err(Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)))
+                });
+            }
+            self.decoder.feed(&self.read_buffer[..count]);
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: AsyncWrite + Unpin> AsyncMessageStream<S> {
+    /// Queue a message, to be written by `poll_flush`
+    pub fn queue_message(&mut self, message: &RawNetworkMessage) {
+        self.write_buffer.extend(encode::serialize(message));
+    }
+
+    /// Write the queued messages to the stream and flush it
+    pub fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while !self.write_buffer.is_empty() {
+            match Pin::new(&mut self.stream).poll_write(cx, &self.write_buffer) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero))),
+                Poll::Ready(Ok(count)) => { self.write_buffer.drain(..count); },
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use consensus::encode::{self, serialize};
+    use network::codec::{Error, MessageDecoder};
+    use network::constants::Network;
+    use network::message::{NetworkMessage, RawNetworkMessage};
+
+    fn message(payload: NetworkMessage) -> RawNetworkMessage {
+        RawNetworkMessage { magic: Network::Testnet.magic(), payload: payload }
+    }
+
+    #[test]
+    fn decode_chunks_test() {
+        let messages = vec![
+            message(NetworkMessage::Verack),
+            message(NetworkMessage::Ping(100)),
+            message(NetworkMessage::Headers(vec![])),
+            message(NetworkMessage::Pong(100)),
+        ];
+        let bytes: Vec<u8> = messages.iter().flat_map(|m| serialize(m)).collect();
+
+        for chunk_size in 1..bytes.len() + 1 {
+            let mut decoder = MessageDecoder::new(Network::Testnet.magic());
+            let mut decoded = vec![];
+            for chunk in bytes.chunks(chunk_size) {
+                decoder.feed(chunk);
+                while let Some(message) = decoder.decode_next().unwrap() {
+                    decoded.push(message);
+                }
+            }
+            assert_eq!(decoded, messages);
+            assert_eq!(decoder.buffered_len(), 0);
+        }
+    }
+
+    #[test]
+    fn decode_buffer_test() {
+        let ping = serialize(&message(NetworkMessage::Ping(100)));
+
+        // the payload announced in a header is not allocated before it arrives
+        let mut header = ping[..24].to_vec();
+        header[16..20].copy_from_slice(&[0x40, 0x42, 0x0f, 0x00]);
+        let mut decoder = MessageDecoder::new(Network::Testnet.magic());
+        decoder.feed(&header);
+        assert!(decoder.decode_next().unwrap().is_none());
+        assert!(decoder.buffer.capacity() < 1_000_000);
+
+        // decoded messages are dropped when more bytes are fed
+        let mut decoder = MessageDecoder::new(Network::Testnet.magic());
+        decoder.feed(&ping);
+        decoder.feed(&ping);
+        assert!(decoder.decode_next().unwrap().is_some());
+        assert!(decoder.decode_next().unwrap().is_some());
+        assert_eq!(decoder.buffered_len(), 0);
+        decoder.feed(&ping[..10]);
+        assert_eq!(decoder.buffer.len(), 10);
+        assert_eq!(decoder.buffered_len(), 10);
+    }
+
+    #[test]
+    fn decode_error_test() {
+        let ping = serialize(&message(NetworkMessage::Ping(100)));
+        let magic = Network::Testnet.magic();
+
+        // another network
+        let mut decoder = MessageDecoder::new(Network::Regtest.magic());
+        decoder.feed(&ping[..23]);
+        assert!(decoder.decode_next().unwrap().is_none());
+        decoder.feed(&ping[23..]);
+        for _ in 0..2 {
+            match decoder.decode_next() {
+                Err(Error::UnexpectedMagic { expected, actual }) => {
+                    assert_eq!(expected, Network::Regtest.magic());
+                    assert_eq!(actual, magic);
+                }
+                x => panic!("expected UnexpectedMagic, got {:?}", x),
+            }
+        }
+
+        // malformed command
+        let mut bytes = ping.clone();
+        bytes[6] = 0;
+        let mut decoder = MessageDecoder::new(magic);
+        decoder.feed(&bytes);
+        match decoder.decode_next() {
+            Err(ref e @ Error::InvalidCommand(_)) => assert!(!e.is_recoverable()),
+            x => panic!("expected InvalidCommand, got {:?}", x),
+        }
+
+        // the length is checked before the payload arrives
+        let mut decoder = MessageDecoder::with_max_payload_size(magic, 7);
+        decoder.feed(&ping[..24]);
+        match decoder.decode_next() {
+            Err(Error::OversizedPayload { size: 8, max: 7 }) => {},
+            x => panic!("expected OversizedPayload, got {:?}", x),
+        }
+
+        // bad checksum and unknown command, after which the stream continues
+        let mut bad_checksum = ping.clone();
+        bad_checksum[20] ^= 0xff;
+        let mut unknown = ping.clone();
+        unknown[4..8].copy_from_slice(b"pang");
+        let mut decoder = MessageDecoder::new(magic);
+        decoder.feed(&bad_checksum);
+        decoder.feed(&unknown);
+        decoder.feed(&ping);
+        match decoder.decode_next() {
+            Err(ref e @ Error::InvalidChecksum { .. }) => assert!(e.is_recoverable()),
+            x => panic!("expected InvalidChecksum, got {:?}", x),
+        }
+        match decoder.decode_next() {
+            Err(Error::Payload(encode::Error::UnrecognizedNetworkCommand(ref command))) => assert_eq!(command, "pang"),
+            x => panic!("expected UnrecognizedNetworkCommand, got {:?}", x),
+        }
+        assert_eq!(decoder.decode_next().unwrap(), Some(message(NetworkMessage::Ping(100))));
+        assert!(decoder.decode_next().unwrap().is_none());
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod async_tests {
+    use std::collections::VecDeque;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures_io::{AsyncRead, AsyncWrite};
+    use futures_task::noop_waker;
+
+    use consensus::encode::serialize;
+    use network::codec::{AsyncMessageStream, Error};
+    use network::constants::Network;
+    use network::message::{NetworkMessage, RawNetworkMessage};
+
+    /// A stream which yields one chunk per read, or `Pending` for `None`, then EOF
+    struct Reader {
+        chunks: VecDeque<Option<Vec<u8>>>,
+    }
+
+    impl AsyncRead for Reader {
+        fn poll_read(self: Pin<&mut Self>, _cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            match this.chunks.pop_front() {
+                Some(Some(mut chunk)) => {
+                    let count = chunk.len().min(buf.len());
+                    buf[..count].copy_from_slice(&chunk[..count]);
+                    if count < chunk.len() {
+                        this.chunks.push_front(Some(chunk.split_off(count)));
+                    }
+                    Poll::Ready(Ok(count))
+                }
+                Some(None) => Poll::Pending,
+                None => Poll::Ready(Ok(0)),
+            }
+        }
+    }
+
+    /// A stream which accepts at most the given number of bytes per write, or
+    /// is `Pending` for `None`, then accepts everything
+    struct Writer {
+        limits: VecDeque<Option<usize>>,
+        written: Vec<u8>,
+        flushed: bool,
+    }
+
+    impl AsyncWrite for Writer {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let count = match this.limits.pop_front() {
+                Some(Some(limit)) => limit.min(buf.len()),
+                Some(None) => return Poll::Pending,
+                None => buf.len(),
+            };
+            this.written.extend_from_slice(&buf[..count]);
+            Poll::Ready(Ok(count))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            self.get_mut().flushed = true;
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn message(payload: NetworkMessage) -> RawNetworkMessage {
+        RawNetworkMessage { magic: Network::Testnet.magic(), payload: payload }
+    }
+
+    fn writer(limits: Vec<Option<usize>>) -> Writer {
+        Writer { limits: limits.into_iter().collect(), written: vec![], flushed: false }
+    }
+
+    #[test]
+    fn read_messages_test() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let messages = vec![
+            message(NetworkMessage::Verack),
+            message(NetworkMessage::Ping(100)),
+            message(NetworkMessage::Pong(100)),
+        ];
+        let bytes: Vec<u8> = messages.iter().flat_map(|m| serialize(m)).collect();
+
+        // partial reads, each followed by a pending one
+        let mut chunks = VecDeque::new();
+        for chunk in bytes.chunks(7) {
+            chunks.push_back(Some(chunk.to_vec()));
+            chunks.push_back(None);
+        }
+        let mut stream = AsyncMessageStream::new(Reader { chunks: chunks }, Network::Testnet.magic());
+        let mut decoded = vec![];
+        let mut pending = 0;
+        loop {
+            match stream.poll_next_message(&mut cx) {
+                Poll::Ready(Ok(Some(message))) => decoded.push(message),
+                Poll::Ready(Ok(None)) => break,
+                Poll::Ready(Err(e)) => panic!("unexpected error: {}", e),
+                Poll::Pending => pending += 1,
+            }
+        }
+        assert_eq!(decoded, messages);
+        assert_eq!(pending, (bytes.len() + 6) / 7);
+
+        // the stream ends in the middle of a message
+        let chunks = vec![Some(bytes[..30].to_vec())].into_iter().collect();
+        let mut stream = AsyncMessageStream::new(Reader { chunks: chunks }, Network::Testnet.magic());
+        match stream.poll_next_message(&mut cx) {
+            Poll::Ready(Ok(Some(ref message))) => assert_eq!(*message, messages[0]),
+            x => panic!("expected verack, got {:?}", x),
+        }
+        match stream.poll_next_message(&mut cx) {
+            Poll::Ready(Err(Error::Io(ref e))) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            x => panic!("expected UnexpectedEof, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn write_messages_test() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let ping = message(NetworkMessage::Ping(100));
+        let verack = message(NetworkMessage::Verack);
+        let mut bytes = serialize(&ping);
+        bytes.extend(serialize(&verack));
+
+        // partial writes, interrupted by a pending one
+        let mut stream = AsyncMessageStream::new(writer(vec![Some(5), None, Some(3)]), Network::Testnet.magic());
+        stream.queue_message(&ping);
+        stream.queue_message(&verack);
+        assert!(stream.poll_flush(&mut cx).is_pending());
+        match stream.poll_flush(&mut cx) {
+            Poll::Ready(Ok(())) => {},
+            x => panic!("expected flush, got {:?}", x),
+        }
+        let sink = stream.into_inner();
+        assert_eq!(sink.written, bytes);
+        assert!(sink.flushed);
+
+        // a stream which accepts no more bytes
+        let mut stream = AsyncMessageStream::new(writer(vec![Some(4), Some(0)]), Network::Testnet.magic());
+        stream.queue_message(&ping);
+        match stream.poll_flush(&mut cx) {
+            Poll::Ready(Err(ref e)) => assert_eq!(e.kind(), io::ErrorKind::WriteZero),
+            x => panic!("expected WriteZero, got {:?}", x),
+        }
+        assert!(!stream.into_inner().flushed);
+    }
+}
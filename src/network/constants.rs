@@ -43,6 +43,7 @@
 //! ```
 
 use std::{fmt, io, ops};
+use std::str::FromStr;
 
 use consensus::encode::{self, Encodable, Decodable};
 
@@ -53,23 +54,70 @@ pub const SERVICES: u64 = 0;
 /// User agent as it appears in the version message
 pub const USER_AGENT: &'static str = "tapyrus-rust v0.1";
 
-user_enum! {
-    /// The cryptocurrency to act on
-    #[derive(Copy, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
-    pub enum Network {
-        /// Classic Bitcoin
-        Bitcoin <-> "bitcoin",
-        /// Bitcoin's testnet
-        Testnet <-> "testnet",
-        /// Bitcoin's regtest
-        Regtest <-> "regtest",
-        /// Paradium
-        Paradium <-> "paradium"
+/// Identifier of a Tapyrus network. The magic bytes of the network are derived from it.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct NetworkId(u32);
+
+impl NetworkId {
+    /// The value added to the network id to get the magic bytes, in big-endian order
+    pub const MAGIC_BASE: u32 = 33550335;
+
+    /// Return the network magic bytes, which should be encoded little-endian
+    /// at the start of every message
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use tapyrus::network::constants::NetworkId;
+    ///
+    /// assert_eq!(NetworkId::from(1).magic(), 0x00F0FF01);
+    /// ```
+    pub fn magic(&self) -> u32 {
+        NetworkId::MAGIC_BASE.wrapping_add(self.0).swap_bytes()
+    }
+
+    /// Creates a `NetworkId` from the magic bytes.
+    pub fn from_magic(magic: u32) -> NetworkId {
+        NetworkId(magic.swap_bytes().wrapping_sub(NetworkId::MAGIC_BASE))
+    }
+
+    /// Returns the network id as a number
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for NetworkId {
+    fn from(id: u32) -> Self {
+        NetworkId(id)
+    }
+}
+
+impl fmt::Display for NetworkId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
     }
 }
 
+/// The cryptocurrency to act on
+#[derive(Copy, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub enum Network {
+    /// Classic Bitcoin
+    Bitcoin,
+    /// Bitcoin's testnet
+    Testnet,
+    /// Bitcoin's regtest
+    Regtest,
+    /// Paradium
+    Paradium,
+    /// A Tapyrus network identified by its network id, e.g. a private chain.
+    /// It uses the address prefixes of the testnet.
+    Custom(NetworkId),
+}
+serde_string_impl!(Network, "a network name or id");
+
 impl Network {
-    /// Creates a `Network` from the magic bytes.
+    /// Creates a `Network` from the magic bytes of one of the predefined networks.
     ///
     /// # Examples
     ///
@@ -80,13 +128,42 @@ impl Network {
     /// assert_eq!(None, Network::from_magic(0xFFFFFFFF));
     /// ```
     pub fn from_magic(magic: u32) -> Option<Network> {
-        // Note: any new entries here must be added to `magic` below
-        match magic {
-            0x00F0FF01 => Some(Network::Bitcoin),
-            0x74839A75 => Some(Network::Testnet),
-            0x74979A73 => Some(Network::Regtest),
-            0x64F0FF01 => Some(Network::Paradium),
-            _ => None,
+        match Network::from_network_id(NetworkId::from_magic(magic)) {
+            Network::Custom(_) => None,
+            network => Some(network),
+        }
+    }
+
+    /// Creates a `Network` from a network id. The id of a predefined network
+    /// returns that network, any other id a `Network::Custom`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use tapyrus::network::constants::{Network, NetworkId};
+    ///
+    /// assert_eq!(Network::Bitcoin, Network::from_network_id(NetworkId::from(1)));
+    /// assert_eq!(Network::Custom(NetworkId::from(1234)), Network::from_network_id(NetworkId::from(1234)));
+    /// ```
+    pub fn from_network_id(id: NetworkId) -> Network {
+        match id.as_u32() {
+            1 => Network::Bitcoin,
+            1939510133 => Network::Testnet,
+            1905960821 => Network::Regtest,
+            101 => Network::Paradium,
+            _ => Network::Custom(id),
+        }
+    }
+
+    /// Return the network id
+    pub fn network_id(&self) -> NetworkId {
+        // Note: any new entries here must be added to `from_network_id` above
+        match *self {
+            Network::Bitcoin => NetworkId(1),
+            Network::Testnet => NetworkId(1939510133),
+            Network::Regtest => NetworkId(1905960821),
+            Network::Paradium => NetworkId(101),
+            Network::Custom(id) => id,
         }
     }
 
@@ -102,12 +179,45 @@ impl Network {
     /// assert_eq!(network.magic(), 0x00F0FF01);
     /// ```
     pub fn magic(&self) -> u32 {
-        // Note: any new entries here must be added to `from_magic` above
+        self.network_id().magic()
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Network::Bitcoin => 0x00F0FF01,
-            Network::Testnet => 0x74839A75,
-            Network::Regtest => 0x74979A73,
-            Network::Paradium => 0x64F0FF01,
+            Network::Bitcoin => f.pad("bitcoin"),
+            Network::Testnet => f.pad("testnet"),
+            Network::Regtest => f.pad("regtest"),
+            Network::Paradium => f.pad("paradium"),
+            Network::Custom(ref id) => f.pad(&id.to_string()),
+        }
+    }
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Network {
+    type Err = io::Error;
+
+    /// Parses a network name, or a network id in decimal
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bitcoin" => Ok(Network::Bitcoin),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            "paradium" => Ok(Network::Paradium),
+            _ => match s.parse::<u32>() {
+                Ok(id) => Ok(Network::from_network_id(NetworkId(id))),
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown network (type {})", s),
+                )),
+            },
         }
     }
 }
@@ -286,7 +396,7 @@ impl Decodable for ServiceFlags {
 
 #[cfg(test)]
 mod tests {
-    use super::{Network, ServiceFlags};
+    use super::{Network, NetworkId, ServiceFlags};
     use consensus::encode::{deserialize, serialize};
 
     #[test]
@@ -338,6 +448,26 @@ mod tests {
         assert_eq!("regtest".parse::<Network>().unwrap(), Network::Regtest);
         assert_eq!("paradium".parse::<Network>().unwrap(), Network::Paradium);
         assert!("fakenet".parse::<Network>().is_err());
+
+        let custom = Network::Custom(NetworkId::from(1234));
+        assert_eq!(custom.to_string(), "1234");
+        assert_eq!("1234".parse::<Network>().unwrap(), custom);
+        assert_eq!("1".parse::<Network>().unwrap(), Network::Bitcoin);
+    }
+
+    #[test]
+    fn network_id_test() {
+        for &network in &[Network::Bitcoin, Network::Testnet, Network::Regtest, Network::Paradium] {
+            assert_eq!(Network::from_network_id(network.network_id()), network);
+            assert_eq!(NetworkId::from_magic(network.magic()), network.network_id());
+            assert_eq!(Network::from_magic(network.magic()), Some(network));
+        }
+
+        let custom = Network::Custom(NetworkId::from(1234));
+        assert_eq!(serialize(&custom.magic()), &[0x01, 0xff, 0xf4, 0xd1]);
+        assert_eq!(NetworkId::from_magic(custom.magic()), NetworkId::from(1234));
+        assert_eq!(Network::from_magic(custom.magic()), None);
+        assert_eq!(Network::Testnet.network_id().as_u32(), 1939510133);
     }
 
     #[test]
@@ -0,0 +1,594 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Header-first synchronization
+//!
+//! This module defines `HeaderSync`, a driver which downloads the headers of
+//! the best chain from peers with `getheaders` messages, and validates them in
+//! a `HeaderChain`. Like `PeerConnection` it does no I/O: it is fed the
+//! `headers` messages received from peers, and produces the requests to send
+//! to them and events for the application. Peers are identified by any id the
+//! application chooses.
+//!
+//! Headers are requested from one peer at a time, in batches of up to 2000,
+//! starting from a block locator of the chain. A peer which does not answer in
+//! time is considered stalling and another peer is asked. Headers branching
+//! off the chain are collected as a fork, which replaces the chain once it is
+//! longer.
+//!
+
+use std::collections::VecDeque;
+
+use blockdata::block::BlockHeader;
+use hash_types::BlockHash;
+use network::message::NetworkMessage;
+use network::message_blockdata::GetHeadersMessage;
+use util::hash::BitcoinHash;
+use util::headerchain::HeaderChain;
+
+/// The maximum number of headers in a `headers` message
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+
+/// An event of the synchronization, for the application
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event<P> {
+    /// The chain has a new tip
+    TipChanged {
+        /// The hash of the tip
+        hash: BlockHash,
+        /// The height of the tip
+        height: u32,
+    },
+    /// The chain switched to a longer fork
+    Reorganized {
+        /// The height of the last block common to both branches
+        fork_height: u32,
+        /// The headers which were disconnected from the chain, in order
+        disconnected: Vec<BlockHeader>,
+    },
+    /// The peer has no more headers to send after the tip of the chain
+    Synced {
+        /// The peer
+        peer: P,
+        /// The height of the tip
+        height: u32,
+    },
+    /// The peer sent invalid headers
+    Misbehaved {
+        /// The peer
+        peer: P,
+        /// What the peer did
+        reason: String,
+    },
+    /// The peer did not answer a request in time, and is not asked any more
+    Stalled(P),
+}
+
+/// A peer to download headers from
+struct Peer<P> {
+    id: P,
+    /// The height of the best chain of the peer, as far as we know
+    height: u32,
+    stalled: bool,
+}
+
+/// A branch of the chain downloaded from a peer
+struct Fork<P> {
+    peer: P,
+    /// The height of the last block common to the fork and the chain
+    height: u32,
+    /// The headers of the branch only, starting from the block at `height`
+    chain: HeaderChain,
+}
+
+/// Synchronizes a chain of headers with peers
+pub struct HeaderSync<P> {
+    chain: HeaderChain,
+    fork: Option<Fork<P>>,
+    peers: Vec<Peer<P>>,
+    /// The peer which was sent a `getheaders` message, and when
+    request: Option<(P, u64)>,
+    stall_timeout: u64,
+    requests: VecDeque<(P, NetworkMessage)>,
+    events: VecDeque<Event<P>>,
+}
+
+impl<P: Clone + PartialEq> HeaderSync<P> {
+    /// Create a driver which extends `chain`. A peer which does not answer a
+    /// request within `stall_timeout` seconds is considered stalling.
+    pub fn new(chain: HeaderChain, stall_timeout: u64) -> HeaderSync<P> {
+        HeaderSync {
+            chain: chain,
+            fork: None,
+            peers: vec![],
+            request: None,
+            stall_timeout: stall_timeout,
+            requests: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// The chain of validated headers
+    pub fn chain(&self) -> &HeaderChain {
+        &self.chain
+    }
+
+    /// The hash and height of the tip of the chain
+    pub fn best_tip(&self) -> (BlockHash, u32) {
+        (self.chain.tip(), self.chain.height())
+    }
+
+    /// Add a peer whose best chain has the given height, e.g. the start
+    /// height of its version message. Headers are requested from it if no
+    /// other peer is asked and its chain is longer.
+    pub fn add_peer(&mut self, peer: P, height: u32, now: u64) {
+        if !self.peers.iter().any(|p| p.id == peer) {
+            self.peers.push(Peer { id: peer, height: height, stalled: false });
+        }
+        self.request_next(now);
+    }
+
+    /// Remove a disconnected peer. If it was asked for headers, another peer is.
+    pub fn remove_peer(&mut self, peer: &P, now: u64) {
+        self.peers.retain(|p| p.id != *peer);
+        if self.fork.as_ref().map_or(false, |fork| fork.peer == *peer) {
+            self.fork = None;
+        }
+        if self.request.as_ref().map_or(false, |&(ref p, _)| p == peer) {
+            self.request = None;
+        }
+        self.request_next(now);
+    }
+
+    /// Process the content of a `headers` message received from the peer,
+    /// either in answer to a request or announcing new blocks.
+    pub fn receive_headers(&mut self, peer: &P, headers: Vec<BlockHeader>, now: u64) {
+        let answered = self.request.as_ref().map_or(false, |&(ref p, _)| p == peer);
+        if answered {
+            self.request = None;
+        }
+        self.process_headers(peer, headers, answered, now);
+        self.request_next(now);
+    }
+
+    /// Update the driver to the time `now`: a peer which did not answer the
+    /// request in time is marked as stalling and another peer is asked.
+    pub fn tick(&mut self, now: u64) {
+        let stalled = match self.request {
+            Some((ref peer, sent_at)) if now >= sent_at.saturating_add(self.stall_timeout) => peer.clone(),
+            _ => return,
+        };
+        self.request = None;
+        for p in self.peers.iter_mut().filter(|p| p.id == stalled) {
+            p.stalled = true;
+        }
+        if self.fork.as_ref().map_or(false, |fork| fork.peer == stalled) {
+            self.fork = None;
+        }
+        self.events.push_back(Event::Stalled(stalled));
+        self.request_next(now);
+    }
+
+    /// The next message to send, with the peer to send it to
+    pub fn poll_request(&mut self) -> Option<(P, NetworkMessage)> {
+        self.requests.pop_front()
+    }
+
+    /// The next event of the synchronization
+    pub fn poll_event(&mut self) -> Option<Event<P>> {
+        self.events.pop_front()
+    }
+
+    fn process_headers(&mut self, peer: &P, mut headers: Vec<BlockHeader>, answered: bool, now: u64) {
+        if headers.len() > MAX_HEADERS_RESULTS {
+            let reason = format!("{} headers in a message", headers.len());
+            self.misbehaved(peer, reason);
+            return;
+        }
+        let full = headers.len() == MAX_HEADERS_RESULTS;
+        for i in 1..headers.len() {
+            if headers[i].prev_blockhash != headers[i - 1].bitcoin_hash() {
+                self.misbehaved(peer, "headers are not linked".to_owned());
+                return;
+            }
+        }
+
+        // The peer may send headers we have, when its chain forks off below our tip
+        let known = headers.iter().take_while(|h| self.chain.get_height(&h.bitcoin_hash()).is_some()).count();
+        let headers = headers.split_off(known);
+        let prev_blockhash = match headers.first() {
+            Some(header) => header.prev_blockhash,
+            None => {
+                if answered {
+                    self.synced(peer);
+                }
+                return;
+            }
+        };
+        let extends_fork = self.fork.as_ref().map_or(false, |fork| {
+            fork.peer == *peer && fork.chain.tip() == prev_blockhash
+        });
+        let tip = self.chain.tip();
+
+        let (result, locator) = if prev_blockhash == tip {
+            let result = self.chain.connect_headers(headers);
+            let height = self.chain.height();
+            self.update_peer_height(peer, height);
+            (result, self.chain.locator_hashes())
+        } else if extends_fork || self.chain.get_height(&prev_blockhash).is_some() {
+            let mut fork = if extends_fork {
+                self.fork.take().expect("fork extended")
+            } else {
+                // The first header is verified against the key active at its
+                // height in the chain, before anything is kept for the fork
+                let height = self.chain.get_height(&prev_blockhash).expect("known header");
+                let aggregated_public_key = *self.chain.aggregated_public_key_at(height + 1).expect("height within the chain");
+                let chain = HeaderChain::from_checkpoint(prev_blockhash, height, aggregated_public_key)
+                    .expect("height below the tip");
+                Fork { peer: peer.clone(), height: height, chain: chain }
+            };
+            let result = fork.chain.connect_headers(headers);
+            let height = fork.chain.height();
+            self.update_peer_height(peer, height);
+            if height > self.chain.height() {
+                let disconnected = self.chain.truncate(fork.height).expect("height within the chain");
+                self.chain.append(fork.chain).expect("fork starts at the fork height");
+                self.fork = None;
+                self.events.push_back(Event::Reorganized { fork_height: fork.height, disconnected: disconnected });
+                (result, self.chain.locator_hashes())
+            } else {
+                let locator = fork.chain.locator_hashes();
+                if result.is_ok() && full {
+                    self.fork = Some(fork);
+                }
+                (result, locator)
+            }
+        } else {
+            if answered {
+                // An answer starts from a hash of the locator we sent
+                let reason = format!("headers do not connect to block {}", prev_blockhash);
+                self.misbehaved(peer, reason);
+            } else if self.request.is_none() {
+                // An announcement of blocks whose parents we miss
+                let locator = self.chain.locator_hashes();
+                self.send_request(peer.clone(), locator, now);
+            }
+            return;
+        };
+
+        if self.chain.tip() != tip {
+            let event = Event::TipChanged { hash: self.chain.tip(), height: self.chain.height() };
+            self.events.push_back(event);
+        }
+        match result {
+            Err(e) => self.misbehaved(peer, e.to_string()),
+            Ok(()) => {
+                // A peer which stalled before is asked again once it delivers
+                for p in self.peers.iter_mut().filter(|p| p.id == *peer) {
+                    p.stalled = false;
+                }
+                if full {
+                    // Unless another peer was asked meanwhile
+                    if self.request.is_none() {
+                        self.send_request(peer.clone(), locator, now);
+                    }
+                } else if answered {
+                    self.synced(peer);
+                }
+            }
+        }
+    }
+
+    /// Ask the peer with the longest chain for headers, unless a peer is asked already
+    fn request_next(&mut self, now: u64) {
+        if self.request.is_some() {
+            return;
+        }
+        let height = self.chain.height();
+        let peer = self.peers.iter()
+            .filter(|p| !p.stalled && p.height > height)
+            .max_by_key(|p| p.height)
+            .map(|p| p.id.clone());
+        if let Some(peer) = peer {
+            let locator = self.chain.locator_hashes();
+            self.send_request(peer, locator, now);
+        }
+    }
+
+    fn send_request(&mut self, peer: P, locator_hashes: Vec<BlockHash>, now: u64) {
+        let message = GetHeadersMessage::new(locator_hashes, BlockHash::default());
+        self.request = Some((peer.clone(), now));
+        self.requests.push_back((peer, NetworkMessage::GetHeaders(message)));
+    }
+
+    fn update_peer_height(&mut self, peer: &P, height: u32) {
+        for p in self.peers.iter_mut().filter(|p| p.id == *peer && p.height < height) {
+            p.height = height;
+        }
+    }
+
+    fn synced(&mut self, peer: &P) {
+        self.events.push_back(Event::Synced { peer: peer.clone(), height: self.chain.height() });
+    }
+
+    fn misbehaved(&mut self, peer: &P, reason: String) {
+        self.events.push_back(Event::Misbehaved { peer: peer.clone(), reason: reason });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use blockdata::block::{BlockHeader, XField};
+    use hash_types::{BlockHash, TxMerkleNode};
+    use network::header_sync::{Event, HeaderSync};
+    use network::message::NetworkMessage;
+    use network::message_blockdata::GetHeadersMessage;
+    use util::hash::BitcoinHash;
+    use util::headerchain::HeaderChain;
+    use util::key::{PrivateKey, PublicKey};
+
+    fn key() -> PrivateKey {
+        PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap()
+    }
+
+    fn genesis() -> BlockHeader {
+        let pk = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        BlockHeader {
+            version: 1,
+            prev_blockhash: BlockHash::default(),
+            merkle_root: TxMerkleNode::default(),
+            im_merkle_root: TxMerkleNode::default(),
+            time: 1000,
+            xfield: XField::AggregatePublicKey(pk),
+            proof: None,
+        }
+    }
+
+    /// `count` headers signed by `key` extending `prev`, the first at `time`
+    fn headers(prev: &BlockHeader, count: usize, time: u32, key: &PrivateKey) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        for i in 0..count {
+            let mut header = {
+                let prev = headers.last().unwrap_or(prev);
+                BlockHeader {
+                    version: 1,
+                    prev_blockhash: prev.bitcoin_hash(),
+                    merkle_root: TxMerkleNode::default(),
+                    im_merkle_root: TxMerkleNode::default(),
+                    time: time + i as u32,
+                    xfield: XField::None,
+                    proof: None,
+                }
+            };
+            header.sign(key).unwrap();
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn getheaders(locator: &[&BlockHeader]) -> NetworkMessage {
+        let locator_hashes = locator.iter().map(|h| h.bitcoin_hash()).collect();
+        NetworkMessage::GetHeaders(GetHeadersMessage::new(locator_hashes, BlockHash::default()))
+    }
+
+    fn requests(sync: &mut HeaderSync<u32>) -> Vec<(u32, NetworkMessage)> {
+        let mut requests = vec![];
+        while let Some(request) = sync.poll_request() {
+            requests.push(request);
+        }
+        requests
+    }
+
+    fn events(sync: &mut HeaderSync<u32>) -> Vec<Event<u32>> {
+        let mut events = vec![];
+        while let Some(event) = sync.poll_event() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn sync_test() {
+        let genesis = genesis();
+        let main = headers(&genesis, 2102, 1001, &key());
+        let mut sync = HeaderSync::new(HeaderChain::new(genesis.clone()).unwrap(), 60);
+
+        // peers with no more blocks than us are not asked
+        sync.add_peer(1, 0, 0);
+        assert!(requests(&mut sync).is_empty());
+        sync.add_peer(2, 2100, 0);
+        assert_eq!(requests(&mut sync), vec![(2, getheaders(&[&genesis]))]);
+
+        // a full batch is followed by a request for the next one
+        sync.receive_headers(&2, main[..2000].to_vec(), 1);
+        assert_eq!(events(&mut sync), vec![
+            Event::TipChanged { hash: main[1999].bitcoin_hash(), height: 2000 },
+        ]);
+        match requests(&mut sync).as_slice() {
+            [(2, NetworkMessage::GetHeaders(ref message))] => {
+                assert_eq!(message.locator_hashes, sync.chain().locator_hashes());
+                assert_eq!(message.locator_hashes[0], main[1999].bitcoin_hash());
+            }
+            x => panic!("expected getheaders, got {:?}", x),
+        }
+
+        sync.receive_headers(&2, main[2000..2100].to_vec(), 2);
+        assert_eq!(events(&mut sync), vec![
+            Event::TipChanged { hash: main[2099].bitcoin_hash(), height: 2100 },
+            Event::Synced { peer: 2, height: 2100 },
+        ]);
+        assert!(requests(&mut sync).is_empty());
+        assert_eq!(sync.best_tip(), (main[2099].bitcoin_hash(), 2100));
+
+        // a new block announced by a peer
+        sync.receive_headers(&1, vec![main[2100].clone()], 3);
+        assert_eq!(events(&mut sync), vec![
+            Event::TipChanged { hash: main[2100].bitcoin_hash(), height: 2101 },
+        ]);
+        assert!(requests(&mut sync).is_empty());
+
+        // an announced block whose parent we miss
+        let mut announced = headers(&main[2101], 1, 4000, &key());
+        let tip = announced[0].bitcoin_hash();
+        sync.receive_headers(&1, announced.clone(), 4);
+        assert!(events(&mut sync).is_empty());
+        match requests(&mut sync).as_slice() {
+            [(1, NetworkMessage::GetHeaders(ref message))] => {
+                assert_eq!(message.locator_hashes[0], main[2100].bitcoin_hash());
+            }
+            x => panic!("expected getheaders, got {:?}", x),
+        }
+        announced.insert(0, main[2101].clone());
+        sync.receive_headers(&1, announced, 5);
+        assert_eq!(events(&mut sync), vec![
+            Event::TipChanged { hash: tip, height: 2103 },
+            Event::Synced { peer: 1, height: 2103 },
+        ]);
+    }
+
+    #[test]
+    fn fork_test() {
+        let genesis = genesis();
+        let main = headers(&genesis, 5, 1001, &key());
+        let mut sync = HeaderSync::new(HeaderChain::new(genesis.clone()).unwrap(), 60);
+        sync.add_peer(1, 5, 0);
+        requests(&mut sync);
+        sync.receive_headers(&1, main.clone(), 1);
+        assert_eq!(events(&mut sync).len(), 2);
+
+        // a fork branching off at height 3, shorter than the chain
+        let fork = headers(&main[2], 3, 2000, &key());
+        sync.add_peer(2, 0, 2);
+        let mut announced = main[..3].to_vec();
+        announced.extend_from_slice(&fork[..2]);
+        sync.receive_headers(&2, announced, 2);
+        assert!(events(&mut sync).is_empty());
+        assert!(requests(&mut sync).is_empty());
+        assert_eq!(sync.best_tip(), (main[4].bitcoin_hash(), 5));
+
+        // the fork grows longer than the chain
+        sync.receive_headers(&2, vec![fork[2].clone()], 3);
+        assert_eq!(requests(&mut sync), vec![(2, getheaders(&[&main[4], &main[3], &main[2], &main[1], &main[0], &genesis]))]);
+        sync.receive_headers(&2, fork.clone(), 4);
+        assert_eq!(events(&mut sync), vec![
+            Event::Reorganized { fork_height: 3, disconnected: main[3..].to_vec() },
+            Event::TipChanged { hash: fork[2].bitcoin_hash(), height: 6 },
+            Event::Synced { peer: 2, height: 6 },
+        ]);
+        assert_eq!(sync.chain().get_height(&main[4].bitcoin_hash()), None);
+        assert_eq!(sync.chain().get_height(&fork[0].bitcoin_hash()), Some(4));
+
+        // an unsigned header branching off the chain is not kept as a fork
+        let mut unsigned = headers(&main[1], 1, 3000, &key());
+        unsigned[0].proof = None;
+        sync.receive_headers(&2, unsigned, 5);
+        match events(&mut sync).as_slice() {
+            [Event::Misbehaved { peer: 2, .. }] => {},
+            x => panic!("expected misbehaviour, got {:?}", x),
+        }
+        assert_eq!(sync.best_tip(), (fork[2].bitcoin_hash(), 6));
+    }
+
+    #[test]
+    fn misbehaviour_test() {
+        let genesis = genesis();
+        let main = headers(&genesis, 3, 1001, &key());
+        let mut sync = HeaderSync::new(HeaderChain::new(genesis.clone()).unwrap(), 60);
+
+        sync.receive_headers(&1, vec![main[0].clone(), main[2].clone()], 0);
+        assert_eq!(events(&mut sync), vec![
+            Event::Misbehaved { peer: 1, reason: "headers are not linked".to_owned() },
+        ]);
+
+        sync.receive_headers(&1, vec![main[0].clone(); 2001], 0);
+        assert_eq!(events(&mut sync), vec![
+            Event::Misbehaved { peer: 1, reason: "2001 headers in a message".to_owned() },
+        ]);
+
+        // the first header is valid, the second is signed by another key
+        let other_key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let mut invalid = main[..2].to_vec();
+        invalid[1].sign(&other_key).unwrap();
+        sync.receive_headers(&1, invalid, 0);
+        match events(&mut sync).as_slice() {
+            [Event::TipChanged { height: 1, .. }, Event::Misbehaved { peer: 1, .. }] => {},
+            x => panic!("expected tip change and misbehaviour, got {:?}", x),
+        }
+        assert_eq!(sync.best_tip(), (main[0].bitcoin_hash(), 1));
+
+        // an answer which does not start from the locator
+        sync.add_peer(2, 3, 0);
+        assert_eq!(requests(&mut sync).len(), 1);
+        let mut other_genesis = genesis.clone();
+        other_genesis.time += 1;
+        sync.receive_headers(&2, headers(&other_genesis, 2, 1001, &key()), 1);
+        match events(&mut sync).as_slice() {
+            [Event::Misbehaved { peer: 2, ref reason }] => {
+                assert_eq!(*reason, format!("headers do not connect to block {}", other_genesis.bitcoin_hash()));
+            }
+            x => panic!("expected misbehaviour, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn announcement_test() {
+        let genesis = genesis();
+        let main = headers(&genesis, 2001, 1001, &key());
+        let mut sync = HeaderSync::new(HeaderChain::new(genesis.clone()).unwrap(), 60);
+        sync.add_peer(1, 2001, 0);
+        sync.add_peer(2, 0, 0);
+        assert_eq!(requests(&mut sync), vec![(1, getheaders(&[&genesis]))]);
+
+        // a full batch announced while another peer is asked
+        sync.receive_headers(&2, main[..2000].to_vec(), 1);
+        assert_eq!(events(&mut sync), vec![
+            Event::TipChanged { hash: main[1999].bitcoin_hash(), height: 2000 },
+        ]);
+        assert!(requests(&mut sync).is_empty());
+
+        // the peer which was asked still answers the request
+        sync.receive_headers(&1, main.clone(), 2);
+        assert_eq!(events(&mut sync), vec![
+            Event::TipChanged { hash: main[2000].bitcoin_hash(), height: 2001 },
+            Event::Synced { peer: 1, height: 2001 },
+        ]);
+    }
+
+    #[test]
+    fn stall_test() {
+        let main = headers(&genesis(), 1, 1001, &key());
+        let mut sync = HeaderSync::new(HeaderChain::new(genesis()).unwrap(), 60);
+        sync.add_peer(1, 10, 0);
+        sync.add_peer(2, 8, 0);
+        assert_eq!(requests(&mut sync), vec![(1, getheaders(&[&genesis()]))]);
+
+        sync.tick(59);
+        assert!(events(&mut sync).is_empty());
+        sync.tick(60);
+        assert_eq!(events(&mut sync), vec![Event::Stalled(1)]);
+        assert_eq!(requests(&mut sync), vec![(2, getheaders(&[&genesis()]))]);
+
+        // the stalling peer is not asked again
+        sync.remove_peer(&2, 61);
+        assert!(requests(&mut sync).is_empty());
+        sync.tick(200);
+        assert!(events(&mut sync).is_empty());
+
+        // until it delivers headers
+        sync.receive_headers(&1, main.clone(), 201);
+        assert_eq!(events(&mut sync), vec![
+            Event::TipChanged { hash: main[0].bitcoin_hash(), height: 1 },
+        ]);
+        assert_eq!(requests(&mut sync), vec![(1, getheaders(&[&main[0], &genesis()]))]);
+
+        // a timeout which would overflow never expires
+        let mut sync = HeaderSync::new(HeaderChain::new(genesis()).unwrap(), u64::max_value());
+        sync.add_peer(1, 10, 10);
+        assert_eq!(requests(&mut sync).len(), 1);
+        sync.tick(u64::max_value() - 1);
+        assert!(events(&mut sync).is_empty());
+    }
+}
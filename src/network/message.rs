@@ -29,6 +29,9 @@ use network::address::Address;
 use network::message_network;
 use network::message_blockdata;
 use network::message_filter;
+use network::message_bloom;
+use network::message_compact_blocks;
+use util::merkleblock::MerkleBlock;
 use consensus::encode::{CheckedData, Decodable, Encodable, VarInt};
 use consensus::{encode, serialize};
 use consensus::encode::MAX_VEC_SIZE;
@@ -140,7 +143,22 @@ pub enum NetworkMessage {
     Ping(u64),
     /// `pong`
     Pong(u64),
-    // TODO: bloom filtering
+    /// BIP37 `filterload`
+    FilterLoad(message_bloom::FilterLoad),
+    /// BIP37 `filteradd`
+    FilterAdd(message_bloom::FilterAdd),
+    /// BIP37 `filterclear`
+    FilterClear,
+    /// BIP37 `merkleblock`
+    MerkleBlock(MerkleBlock),
+    /// BIP152 `sendcmpct`
+    SendCmpct(message_compact_blocks::SendCmpct),
+    /// BIP152 `cmpctblock`
+    CmpctBlock(message_compact_blocks::CmpctBlock),
+    /// BIP152 `getblocktxn`
+    GetBlockTxn(message_compact_blocks::GetBlockTxn),
+    /// BIP152 `blocktxn`
+    BlockTxn(message_compact_blocks::BlockTxn),
     /// BIP157 getcfilters
     GetCFilters(message_filter::GetCFilters),
     /// BIP157 cfilter
@@ -179,6 +197,14 @@ impl NetworkMessage {
             NetworkMessage::GetAddr    => "getaddr",
             NetworkMessage::Ping(_)    => "ping",
             NetworkMessage::Pong(_)    => "pong",
+            NetworkMessage::FilterLoad(_) => "filterload",
+            NetworkMessage::FilterAdd(_) => "filteradd",
+            NetworkMessage::FilterClear => "filterclear",
+            NetworkMessage::MerkleBlock(_) => "merkleblock",
+            NetworkMessage::SendCmpct(_) => "sendcmpct",
+            NetworkMessage::CmpctBlock(_) => "cmpctblock",
+            NetworkMessage::GetBlockTxn(_) => "getblocktxn",
+            NetworkMessage::BlockTxn(_) => "blocktxn",
             NetworkMessage::GetCFilters(_) => "getcfilters",
             NetworkMessage::CFilter(_) => "cfilter",
             NetworkMessage::GetCFHeaders(_) => "getcfheaders",
@@ -247,6 +273,13 @@ impl Encodable for RawNetworkMessage {
             NetworkMessage::Headers(ref dat) => serialize(&HeaderSerializationWrapper(dat)),
             NetworkMessage::Ping(ref dat)    => serialize(dat),
             NetworkMessage::Pong(ref dat)    => serialize(dat),
+            NetworkMessage::FilterLoad(ref dat) => serialize(dat),
+            NetworkMessage::FilterAdd(ref dat) => serialize(dat),
+            NetworkMessage::MerkleBlock(ref dat) => serialize(dat),
+            NetworkMessage::SendCmpct(ref dat) => serialize(dat),
+            NetworkMessage::CmpctBlock(ref dat) => serialize(dat),
+            NetworkMessage::GetBlockTxn(ref dat) => serialize(dat),
+            NetworkMessage::BlockTxn(ref dat) => serialize(dat),
             NetworkMessage::GetCFilters(ref dat) => serialize(dat),
             NetworkMessage::CFilter(ref dat) => serialize(dat),
             NetworkMessage::GetCFHeaders(ref dat) => serialize(dat),
@@ -258,7 +291,8 @@ impl Encodable for RawNetworkMessage {
             NetworkMessage::Verack
             | NetworkMessage::SendHeaders
             | NetworkMessage::MemPool
-            | NetworkMessage::GetAddr => vec![],
+            | NetworkMessage::GetAddr
+            | NetworkMessage::FilterClear => vec![],
         }).consensus_encode(&mut s)?;
         Ok(len)
     }
@@ -304,7 +338,7 @@ impl Decodable for RawNetworkMessage {
             "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode(&mut mem_d)?),
             "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode(&mut mem_d)?),
             "mempool" => NetworkMessage::MemPool,
-            "block"   => NetworkMessage::Block(Decodable::consensus_decode(&mut mem_d)?),
+            "block"   => NetworkMessage::Block(block::Block::consensus_decode_strict(&mut mem_d)?),
             "headers" => NetworkMessage::Headers(
                 HeaderDeserializationWrapper::consensus_decode(&mut mem_d)?.0
             ),
@@ -312,7 +346,15 @@ impl Decodable for RawNetworkMessage {
             "getaddr" => NetworkMessage::GetAddr,
             "ping"    => NetworkMessage::Ping(Decodable::consensus_decode(&mut mem_d)?),
             "pong"    => NetworkMessage::Pong(Decodable::consensus_decode(&mut mem_d)?),
-            "tx"      => NetworkMessage::Tx(Decodable::consensus_decode(&mut mem_d)?),
+            "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode(&mut mem_d)?),
+            "filteradd" => NetworkMessage::FilterAdd(Decodable::consensus_decode(&mut mem_d)?),
+            "filterclear" => NetworkMessage::FilterClear,
+            "merkleblock" => NetworkMessage::MerkleBlock(Decodable::consensus_decode(&mut mem_d)?),
+            "sendcmpct" => NetworkMessage::SendCmpct(Decodable::consensus_decode(&mut mem_d)?),
+            "cmpctblock" => NetworkMessage::CmpctBlock(Decodable::consensus_decode(&mut mem_d)?),
+            "getblocktxn" => NetworkMessage::GetBlockTxn(Decodable::consensus_decode(&mut mem_d)?),
+            "blocktxn" => NetworkMessage::BlockTxn(Decodable::consensus_decode(&mut mem_d)?),
+            "tx"      => NetworkMessage::Tx(transaction::Transaction::consensus_decode_strict(&mut mem_d)?),
             "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode(&mut mem_d)?),
             "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode(&mut mem_d)?),
             "getcfheaders" => NetworkMessage::GetCFHeaders(Decodable::consensus_decode(&mut mem_d)?),
@@ -335,7 +377,7 @@ mod test {
     use std::io;
     use super::{RawNetworkMessage, NetworkMessage, CommandString};
     use network::constants::ServiceFlags;
-    use consensus::encode::{Encodable, deserialize, deserialize_partial, serialize};
+    use consensus::encode::{self, Encodable, deserialize, deserialize_partial, serialize};
     use hex::decode as hex_decode;
     use hashes::sha256d::Hash;
     use hashes::Hash as HashTrait;
@@ -344,7 +386,11 @@ mod test {
     use network::message_blockdata::{Inventory, GetBlocksMessage, GetHeadersMessage};
     use blockdata::block::{Block, BlockHeader};
     use network::message_filter::{GetCFilters, CFilter, GetCFHeaders, CFHeaders, GetCFCheckpt, CFCheckpt};
+    use network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+    use network::message_compact_blocks::{SendCmpct, CmpctBlock, GetBlockTxn, BlockTxn};
+    use util::bip152::{HeaderAndShortIds, BlockTransactionsRequest, BlockTransactions};
     use blockdata::transaction::Transaction;
+    use util::merkleblock::MerkleBlock;
 
     fn hash(slice: [u8;32]) -> Hash {
         Hash::from_slice(&slice).unwrap()
@@ -355,8 +401,11 @@ mod test {
         // TODO: Impl Rand traits here to easily generate random values.
         let version_msg: VersionMessage = deserialize(&hex_decode("721101000100000000000000e6e0845300000000010000000000000000000000000000000000ffff0000000000000100000000000000fd87d87eeb4364f22cf54dca59412db7208d47d920cffce83ee8102f5361746f7368693a302e392e39392f2c9f040001").unwrap()).unwrap();
         let tx: Transaction = deserialize(&hex_decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
-        let block: Block = deserialize(&hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e4921032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap()).unwrap();
-        let header: BlockHeader = deserialize(&hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e4921032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af00").unwrap()).unwrap();
+        let block: Block = deserialize(&hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap()).unwrap();
+        let merkle_block = MerkleBlock::from_block(&block, &vec![block.txdata[1].txid()].into_iter().collect());
+        let compact_block = HeaderAndShortIds::from_block(&block, 5, &[]).unwrap();
+        let block_txs = vec![block.txdata[1].clone()];
+        let header: BlockHeader = deserialize(&hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af00").unwrap()).unwrap();
 
         let msgs = vec![
             NetworkMessage::Version(version_msg),
@@ -381,6 +430,14 @@ mod test {
             NetworkMessage::CFHeaders(CFHeaders{filter_type: 13, stop_hash: hash([53u8; 32]).into(), previous_filter: hash([12u8; 32]).into(), filter_hashes: vec![hash([4u8; 32]).into(), hash([12u8; 32]).into()]}),
             NetworkMessage::GetCFCheckpt(GetCFCheckpt{filter_type: 17, stop_hash: hash([25u8; 32]).into()}),
             NetworkMessage::CFCheckpt(CFCheckpt{filter_type: 27, stop_hash: hash([77u8; 32]).into(), filter_headers: vec![hash([3u8; 32]).into(), hash([99u8; 32]).into()]}),
+            NetworkMessage::FilterLoad(FilterLoad{filter: vec![1,2,3], hash_funcs: 5, tweak: 7, flags: BloomFlags::All}),
+            NetworkMessage::FilterAdd(FilterAdd{data: vec![4,5,6]}),
+            NetworkMessage::FilterClear,
+            NetworkMessage::MerkleBlock(merkle_block),
+            NetworkMessage::SendCmpct(SendCmpct{send_compact: true, version: 1}),
+            NetworkMessage::CmpctBlock(CmpctBlock{compact_block: compact_block}),
+            NetworkMessage::GetBlockTxn(GetBlockTxn{txs_request: BlockTransactionsRequest{block_hash: hash([2u8; 32]).into(), indexes: vec![1, 4, 5]}}),
+            NetworkMessage::BlockTxn(BlockTxn{transactions: BlockTransactions{block_hash: hash([3u8; 32]).into(), transactions: block_txs}}),
             NetworkMessage::Alert(vec![45,66,3,2,6,8,9,12,3,130]),
             NetworkMessage::Reject(Reject{message: "Test reject".into(), ccode: RejectReason::Duplicate, reason: "Cause".into(), hash: hash([255u8; 32])}),
         ];
@@ -392,6 +449,16 @@ mod test {
 
     }
 
+    #[test]
+    fn deserialize_segwit_tx_test() {
+        let tx: Transaction = deserialize(&hex_decode("02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000").unwrap()).unwrap();
+        let raw_msg = RawNetworkMessage {magic: 57, payload: NetworkMessage::Tx(tx)};
+        match deserialize::<RawNetworkMessage>(&serialize(&raw_msg)) {
+            Err(encode::Error::WitnessNotSupported) => {},
+            x => panic!("expected WitnessNotSupported, got {:?}", x),
+        }
+    }
+
     #[test]
     fn serialize_commandstring_test() {
         let cs = CommandString("Andrew".into());
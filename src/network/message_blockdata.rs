@@ -22,17 +22,18 @@ use std::io;
 
 use hashes::sha256d;
 
+use blockdata::transaction::Transaction;
 use network::constants;
 use consensus::encode::{self, Decodable, Encodable};
-use hash_types::{BlockHash, Txid, Wtxid};
+use hash_types::{BlockHash, MalFixTxid, Wtxid};
 
 /// An inventory item.
 #[derive(PartialEq, Eq, Clone, Debug, Copy, Hash)]
 pub enum Inventory {
     /// Error --- these inventories can be ignored
     Error,
-    /// Transaction
-    Transaction(Txid),
+    /// Transaction, by its immutable id
+    Transaction(MalFixTxid),
     /// Block
     Block(BlockHash),
     /// Witness Transaction
@@ -78,6 +79,22 @@ impl Decodable for Inventory {
     }
 }
 
+impl Inventory {
+    /// The inventory of a transaction. Tapyrus peers announce and request
+    /// transactions by their immutable id, not by their txid.
+    pub fn transaction(tx: &Transaction) -> Inventory {
+        Inventory::Transaction(tx.malfix_txid())
+    }
+
+    /// The immutable id of the transaction, if this is a transaction inventory
+    pub fn malfix_txid(&self) -> Option<MalFixTxid> {
+        match *self {
+            Inventory::Transaction(ref txid) => Some(*txid),
+            _ => None,
+        }
+    }
+}
+
 // Some simple messages
 
 /// The `getblocks` message
@@ -134,13 +151,27 @@ impl_consensus_encoding!(GetHeadersMessage, version, locator_hashes, stop_hash);
 
 #[cfg(test)]
 mod tests {
-    use super::{GetHeadersMessage, GetBlocksMessage};
+    use super::{GetHeadersMessage, GetBlocksMessage, Inventory};
 
     use hex::decode as hex_decode;
 
+    use blockdata::transaction::Transaction;
     use consensus::encode::{deserialize, serialize};
     use std::default::Default;
 
+    #[test]
+    fn inventory_test() {
+        let tx: Transaction = deserialize(&hex_decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        let inv = Inventory::transaction(&tx);
+        assert_eq!(inv, Inventory::Transaction(tx.malfix_txid()));
+        assert_eq!(inv.malfix_txid(), Some(tx.malfix_txid()));
+        assert_eq!(Inventory::Block(Default::default()).malfix_txid(), None);
+
+        let encoded = serialize(&inv);
+        assert_eq!(hex_decode("01000000c5c6b1e0c075bd991e5210a270c8afc23dd80341de341bbb835ce22a0676c686").unwrap(), encoded);
+        assert_eq!(deserialize::<Inventory>(&encoded).unwrap(), inv);
+    }
+
     #[test]
     fn getblocks_message_test() {
         let from_sat = hex_decode("72110100014a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b0000000000000000000000000000000000000000000000000000000000000000").unwrap();
@@ -0,0 +1,97 @@
+//!
+//! BIP37 Connection Bloom filtering network messages
+//!
+
+use std::io;
+
+use consensus::encode::{self, Decodable, Encodable};
+
+/// How the filter is updated when a transaction output matches
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BloomFlags {
+    /// The filter is not updated
+    None,
+    /// The outpoint of every matched output is added to the filter
+    All,
+    /// The outpoint of a matched output is added only for pay-to-pubkey and bare multisig outputs
+    PubkeyOnly,
+}
+
+impl Encodable for BloomFlags {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        let flags: u8 = match *self {
+            BloomFlags::None => 0,
+            BloomFlags::All => 1,
+            BloomFlags::PubkeyOnly => 2,
+        };
+        flags.consensus_encode(s)
+    }
+}
+
+/// The bits of the flags which select how the filter is updated
+const BLOOM_UPDATE_MASK: u8 = 3;
+
+impl Decodable for BloomFlags {
+    /// Like Bitcoin Core, only the bits of `BLOOM_UPDATE_MASK` are considered,
+    /// and the unassigned value 3 does not update the filter.
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        match u8::consensus_decode(d)? & BLOOM_UPDATE_MASK {
+            1 => Ok(BloomFlags::All),
+            2 => Ok(BloomFlags::PubkeyOnly),
+            _ => Ok(BloomFlags::None),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// filterload message
+pub struct FilterLoad {
+    /// The bit field of the filter
+    pub filter: Vec<u8>,
+    /// The number of hash functions
+    pub hash_funcs: u32,
+    /// A random value added to the seed of the hash functions
+    pub tweak: u32,
+    /// How the filter is updated on matches
+    pub flags: BloomFlags,
+}
+impl_consensus_encoding!(FilterLoad, filter, hash_funcs, tweak, flags);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// filteradd message
+pub struct FilterAdd {
+    /// The data element to add to the filter
+    pub data: Vec<u8>,
+}
+impl_consensus_encoding!(FilterAdd, data);
+
+#[cfg(test)]
+mod tests {
+    use hex::decode as hex_decode;
+
+    use consensus::encode::{deserialize, serialize};
+    use network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+
+    #[test]
+    fn filterload_test() {
+        let bytes = hex_decode("03614e9b050000000000000001").unwrap();
+        let filterload: FilterLoad = deserialize(&bytes).unwrap();
+        assert_eq!(filterload, FilterLoad {
+            filter: vec![0x61, 0x4e, 0x9b],
+            hash_funcs: 5,
+            tweak: 0,
+            flags: BloomFlags::All,
+        });
+        assert_eq!(serialize(&filterload), bytes);
+
+        // bits outside of the update mask are ignored
+        let filterload: FilterLoad = deserialize(&hex_decode("03614e9b050000000000000082").unwrap()).unwrap();
+        assert_eq!(filterload.flags, BloomFlags::PubkeyOnly);
+        let filterload: FilterLoad = deserialize(&hex_decode("03614e9b050000000000000003").unwrap()).unwrap();
+        assert_eq!(filterload.flags, BloomFlags::None);
+
+        let filteradd = FilterAdd { data: vec![1, 2, 3] };
+        assert_eq!(serialize(&filteradd), vec![3, 1, 2, 3]);
+        assert_eq!(deserialize::<FilterAdd>(&[3, 1, 2, 3]).unwrap(), filteradd);
+    }
+}
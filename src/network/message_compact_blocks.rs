@@ -0,0 +1,53 @@
+//!
+//! BIP152 Compact Blocks network messages
+//!
+
+use util::bip152;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// sendcmpct message
+pub struct SendCmpct {
+    /// Whether new blocks should be announced with `cmpctblock` messages
+    pub send_compact: bool,
+    /// The version of compact blocks
+    pub version: u64,
+}
+impl_consensus_encoding!(SendCmpct, send_compact, version);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// cmpctblock message
+pub struct CmpctBlock {
+    /// The compact block
+    pub compact_block: bip152::HeaderAndShortIds,
+}
+impl_consensus_encoding!(CmpctBlock, compact_block);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// getblocktxn message
+pub struct GetBlockTxn {
+    /// The transactions which are requested
+    pub txs_request: bip152::BlockTransactionsRequest,
+}
+impl_consensus_encoding!(GetBlockTxn, txs_request);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+/// blocktxn message
+pub struct BlockTxn {
+    /// The requested transactions
+    pub transactions: bip152::BlockTransactions,
+}
+impl_consensus_encoding!(BlockTxn, transactions);
+
+#[cfg(test)]
+mod tests {
+    use consensus::encode::{deserialize, serialize};
+    use network::message_compact_blocks::SendCmpct;
+
+    #[test]
+    fn sendcmpct_test() {
+        let sendcmpct = SendCmpct { send_compact: true, version: 1 };
+        let bytes = serialize(&sendcmpct);
+        assert_eq!(bytes, vec![1, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(deserialize::<SendCmpct>(&bytes).unwrap(), sendcmpct);
+    }
+}
@@ -26,10 +26,15 @@ pub mod constants;
 
 pub mod address;
 pub use self::address::Address;
+pub mod codec;
+pub mod header_sync;
 pub mod message;
 pub mod message_blockdata;
+pub mod message_bloom;
+pub mod message_compact_blocks;
 pub mod message_filter;
 pub mod message_network;
+pub mod peer;
 pub mod stream_reader;
 
 /// Network error
@@ -0,0 +1,687 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Peer connection
+//!
+//! This module defines `PeerConnection`, a state machine for a connection to a
+//! Tapyrus peer which does no I/O itself. It is fed the bytes received from
+//! the peer along with the current time, and produces the messages to send to
+//! the peer and events for the application. It performs the version handshake,
+//! rejects peers of other networks by their magic bytes, keeps the connection
+//! alive with pings, negotiates `sendheaders` and scores misbehaviour.
+//!
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+
+use consensus::encode;
+use network::address::Address;
+use network::codec::{self, MessageDecoder};
+use network::constants::{Network, ServiceFlags, PROTOCOL_VERSION, USER_AGENT};
+use network::message::{NetworkMessage, RawNetworkMessage};
+use network::message_network::VersionMessage;
+
+/// The misbehaviour score of a message which cannot be decoded
+const MALFORMED_MESSAGE_SCORE: u32 = 10;
+
+/// The misbehaviour score of a message which violates the handshake
+const HANDSHAKE_VIOLATION_SCORE: u32 = 1;
+
+/// The side which opened the connection
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// The peer connected to us
+    Inbound,
+    /// We connected to the peer
+    Outbound,
+}
+
+/// The state of a connection
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum State {
+    /// The version handshake is in progress
+    Handshaking,
+    /// The handshake completed
+    Established,
+    /// The connection should be closed
+    Disconnected,
+}
+
+/// The reason for closing a connection
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DisconnectReason {
+    /// The peer sent a message with the magic bytes of another network
+    WrongMagic(u32),
+    /// The protocol version of the peer is below the minimum
+    ObsoleteVersion(u32),
+    /// The peer is ourselves, as it sent the nonce of our version message
+    SelfConnection,
+    /// The peer announced a message larger than allowed
+    OversizedMessage(usize),
+    /// The peer sent a message header with a malformed command
+    InvalidCommand,
+    /// The handshake did not complete in time
+    HandshakeTimeout,
+    /// The peer did not answer a ping in time
+    PingTimeout,
+    /// The misbehaviour score of the peer reached the ban threshold
+    Misbehaviour(u32),
+    /// The application closed the connection
+    Requested,
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DisconnectReason::WrongMagic(magic) => write!(f, "wrong magic bytes: {:#010x}", magic),
+            DisconnectReason::ObsoleteVersion(version) => write!(f, "obsolete protocol version: {}", version),
+            DisconnectReason::SelfConnection => f.write_str("connected to self"),
+            DisconnectReason::OversizedMessage(len) => write!(f, "oversized message: {} bytes", len),
+            DisconnectReason::InvalidCommand => f.write_str("invalid command"),
+            DisconnectReason::HandshakeTimeout => f.write_str("handshake timeout"),
+            DisconnectReason::PingTimeout => f.write_str("ping timeout"),
+            DisconnectReason::Misbehaviour(score) => write!(f, "misbehaviour score {}", score),
+            DisconnectReason::Requested => f.write_str("disconnect requested"),
+        }
+    }
+}
+
+/// An event of a connection, for the application
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// The handshake completed; the peer sent this version message
+    Connected(VersionMessage),
+    /// The peer sent a message which is not handled by the connection itself
+    Message(NetworkMessage),
+    /// The peer misbehaved
+    Misbehaved {
+        /// The misbehaviour score of the peer, including this misbehaviour
+        score: u32,
+        /// What the peer did
+        reason: String,
+    },
+    /// The connection should be closed
+    Disconnected(DisconnectReason),
+}
+
+/// The configuration of a connection. Times are in seconds.
+#[derive(Clone, Debug)]
+pub struct PeerConfig {
+    /// The network to connect to; messages with other magic bytes are rejected
+    pub network: Network,
+    /// The nonce of our version message, which must be random. A peer sending
+    /// it back is ourselves.
+    pub nonce: u64,
+    /// The services we provide
+    pub services: ServiceFlags,
+    /// Our user agent
+    pub user_agent: String,
+    /// The height of our best chain
+    pub start_height: i32,
+    /// Whether the peer should relay transactions to us
+    pub relay: bool,
+    /// Whether to ask the peer to announce blocks with `headers` messages
+    pub send_headers: bool,
+    /// The minimum protocol version of the peer
+    pub min_version: u32,
+    /// The time for the handshake to complete
+    pub handshake_timeout: u64,
+    /// The time between pings
+    pub ping_interval: u64,
+    /// The time for the peer to answer a ping
+    pub ping_timeout: u64,
+    /// The misbehaviour score at which the peer is disconnected
+    pub ban_threshold: u32,
+}
+
+impl PeerConfig {
+    /// Create a configuration with the default values
+    pub fn new(network: Network, nonce: u64) -> PeerConfig {
+        PeerConfig {
+            network: network,
+            nonce: nonce,
+            services: ServiceFlags::NONE,
+            user_agent: USER_AGENT.to_owned(),
+            start_height: 0,
+            relay: false,
+            send_headers: true,
+            min_version: PROTOCOL_VERSION,
+            handshake_timeout: 60,
+            ping_interval: 2 * 60,
+            ping_timeout: 20 * 60,
+            ban_threshold: 100,
+        }
+    }
+}
+
+/// A connection to a peer
+pub struct PeerConnection {
+    config: PeerConfig,
+    direction: Direction,
+    peer_address: SocketAddr,
+    state: State,
+    decoder: MessageDecoder,
+    outbound: VecDeque<RawNetworkMessage>,
+    events: VecDeque<Event>,
+    version_sent: bool,
+    peer_version: Option<VersionMessage>,
+    verack_received: bool,
+    peer_prefers_headers: bool,
+    opened_at: u64,
+    /// The time of the last ping sent, or of the handshake completion
+    last_ping: u64,
+    ping_nonce: Option<u64>,
+    ping_count: u64,
+    latency: Option<u64>,
+    misbehaviour: u32,
+}
+
+impl PeerConnection {
+    /// Create a connection to the peer at `peer_address`, opened at `now`. An
+    /// outbound connection sends its version message right away.
+    pub fn new(config: PeerConfig, direction: Direction, peer_address: SocketAddr, now: u64) -> PeerConnection {
+        let decoder = MessageDecoder::new(config.network.magic());
+        let mut connection = PeerConnection {
+            config: config,
+            direction: direction,
+            peer_address: peer_address,
+            state: State::Handshaking,
+            decoder: decoder,
+            outbound: VecDeque::new(),
+            events: VecDeque::new(),
+            version_sent: false,
+            peer_version: None,
+            verack_received: false,
+            peer_prefers_headers: false,
+            opened_at: now,
+            last_ping: now,
+            ping_nonce: None,
+            ping_count: 0,
+            latency: None,
+            misbehaviour: 0,
+        };
+        if direction == Direction::Outbound {
+            connection.send_version(now);
+        }
+        connection
+    }
+
+    /// The side which opened the connection
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The address of the peer
+    pub fn peer_address(&self) -> &SocketAddr {
+        &self.peer_address
+    }
+
+    /// The state of the connection
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// The version message of the peer, once received
+    pub fn peer_version(&self) -> Option<&VersionMessage> {
+        self.peer_version.as_ref()
+    }
+
+    /// Whether the peer asked for blocks to be announced with `headers` messages
+    pub fn peer_prefers_headers(&self) -> bool {
+        self.peer_prefers_headers
+    }
+
+    /// The misbehaviour score of the peer
+    pub fn misbehaviour(&self) -> u32 {
+        self.misbehaviour
+    }
+
+    /// The round-trip time of the last answered ping
+    pub fn latency(&self) -> Option<u64> {
+        self.latency
+    }
+
+    /// Process bytes received from the peer. Complete messages are handled;
+    /// the rest is kept until more bytes arrive.
+    pub fn receive_bytes(&mut self, data: &[u8], now: u64) {
+        if self.state == State::Disconnected {
+            return;
+        }
+        self.decoder.feed(data);
+        while self.state != State::Disconnected {
+            match self.decoder.decode_next() {
+                Ok(Some(message)) => self.receive(message, now),
+                Ok(None) => return,
+                // Messages of newer protocol versions are ignored
+                Err(codec::Error::Payload(encode::Error::UnrecognizedNetworkCommand(_))) => {},
+                Err(codec::Error::UnexpectedMagic { actual, .. }) => self.close(DisconnectReason::WrongMagic(actual)),
+                Err(codec::Error::InvalidCommand(_)) => self.close(DisconnectReason::InvalidCommand),
+                Err(codec::Error::OversizedPayload { size, .. }) => self.close(DisconnectReason::OversizedMessage(size)),
+                Err(e) => self.misbehaving(MALFORMED_MESSAGE_SCORE, format!("malformed message: {}", e)),
+            }
+        }
+    }
+
+    /// Process a message received from the peer
+    pub fn receive(&mut self, message: RawNetworkMessage, now: u64) {
+        if self.state == State::Disconnected {
+            return;
+        }
+        if message.magic != self.config.network.magic() {
+            self.close(DisconnectReason::WrongMagic(message.magic));
+            return;
+        }
+        match message.payload {
+            NetworkMessage::Version(version) => self.receive_version(version, now),
+            NetworkMessage::Verack => self.receive_verack(now),
+            payload => {
+                if self.state == State::Established {
+                    self.receive_payload(payload, now);
+                } else {
+                    let reason = format!("{} message before handshake", payload.cmd());
+                    self.misbehaving(HANDSHAKE_VIOLATION_SCORE, reason);
+                }
+            }
+        }
+    }
+
+    /// Update the connection to the time `now`: close it if the handshake or
+    /// a ping timed out, and send a ping when due.
+    pub fn tick(&mut self, now: u64) {
+        match self.state {
+            State::Handshaking => {
+                if now >= self.opened_at.saturating_add(self.config.handshake_timeout) {
+                    self.close(DisconnectReason::HandshakeTimeout);
+                }
+            }
+            State::Established => {
+                if self.ping_nonce.is_some() {
+                    if now >= self.last_ping.saturating_add(self.config.ping_timeout) {
+                        self.close(DisconnectReason::PingTimeout);
+                    }
+                } else if now >= self.last_ping.saturating_add(self.config.ping_interval) {
+                    self.ping_count += 1;
+                    let nonce = self.config.nonce.wrapping_add(self.ping_count);
+                    self.ping_nonce = Some(nonce);
+                    self.last_ping = now;
+                    self.queue(NetworkMessage::Ping(nonce));
+                }
+            }
+            State::Disconnected => {}
+        }
+    }
+
+    /// Send a message to the peer. Returns false, dropping the message, if
+    /// the handshake has not completed or the connection is closed.
+    pub fn send(&mut self, payload: NetworkMessage) -> bool {
+        if self.state != State::Established {
+            return false;
+        }
+        self.queue(payload);
+        true
+    }
+
+    /// Increase the misbehaviour score of the peer, e.g. for sending invalid
+    /// data, and close the connection when it reaches the ban threshold
+    pub fn misbehaving(&mut self, score: u32, reason: String) {
+        if self.state == State::Disconnected {
+            return;
+        }
+        self.misbehaviour = self.misbehaviour.saturating_add(score);
+        self.events.push_back(Event::Misbehaved { score: self.misbehaviour, reason: reason });
+        if self.misbehaviour >= self.config.ban_threshold {
+            let score = self.misbehaviour;
+            self.close(DisconnectReason::Misbehaviour(score));
+        }
+    }
+
+    /// Close the connection
+    pub fn disconnect(&mut self) {
+        self.close(DisconnectReason::Requested);
+    }
+
+    /// The next message to send to the peer
+    pub fn poll_message(&mut self) -> Option<RawNetworkMessage> {
+        self.outbound.pop_front()
+    }
+
+    /// The serialized messages to send to the peer
+    pub fn outbound_bytes(&mut self) -> Vec<u8> {
+        let mut bytes = vec![];
+        while let Some(message) = self.outbound.pop_front() {
+            bytes.extend(encode::serialize(&message));
+        }
+        bytes
+    }
+
+    /// The next event of the connection
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn queue(&mut self, payload: NetworkMessage) {
+        self.outbound.push_back(RawNetworkMessage {
+            magic: self.config.network.magic(),
+            payload: payload,
+        });
+    }
+
+    fn close(&mut self, reason: DisconnectReason) {
+        if self.state != State::Disconnected {
+            self.state = State::Disconnected;
+            self.events.push_back(Event::Disconnected(reason));
+        }
+    }
+
+    fn send_version(&mut self, now: u64) {
+        let unspecified: SocketAddr = ([0, 0, 0, 0], 0).into();
+        let version = VersionMessage {
+            version: PROTOCOL_VERSION,
+            services: self.config.services,
+            timestamp: now as i64,
+            receiver: Address::new(&self.peer_address, ServiceFlags::NONE),
+            sender: Address::new(&unspecified, self.config.services),
+            nonce: self.config.nonce,
+            user_agent: self.config.user_agent.clone(),
+            start_height: self.config.start_height,
+            relay: self.config.relay,
+        };
+        self.version_sent = true;
+        self.queue(NetworkMessage::Version(version));
+    }
+
+    fn receive_version(&mut self, version: VersionMessage, now: u64) {
+        if self.peer_version.is_some() {
+            self.misbehaving(HANDSHAKE_VIOLATION_SCORE, "duplicate version message".to_owned());
+            return;
+        }
+        if version.nonce == self.config.nonce {
+            self.close(DisconnectReason::SelfConnection);
+            return;
+        }
+        if version.version < self.config.min_version {
+            self.close(DisconnectReason::ObsoleteVersion(version.version));
+            return;
+        }
+        if !self.version_sent {
+            self.send_version(now);
+        }
+        self.queue(NetworkMessage::Verack);
+        self.peer_version = Some(version);
+        self.check_established(now);
+    }
+
+    fn receive_verack(&mut self, now: u64) {
+        if self.peer_version.is_none() {
+            self.misbehaving(HANDSHAKE_VIOLATION_SCORE, "verack message before version".to_owned());
+        } else if !self.verack_received {
+            self.verack_received = true;
+            self.check_established(now);
+        }
+    }
+
+    fn check_established(&mut self, now: u64) {
+        if self.state != State::Handshaking || !self.verack_received {
+            return;
+        }
+        let version = match self.peer_version {
+            Some(ref version) => version.clone(),
+            None => return,
+        };
+        self.state = State::Established;
+        self.last_ping = now;
+        if self.config.send_headers {
+            self.queue(NetworkMessage::SendHeaders);
+        }
+        self.events.push_back(Event::Connected(version));
+    }
+
+    fn receive_payload(&mut self, payload: NetworkMessage, now: u64) {
+        match payload {
+            NetworkMessage::Ping(nonce) => self.queue(NetworkMessage::Pong(nonce)),
+            NetworkMessage::Pong(nonce) => {
+                if self.ping_nonce == Some(nonce) {
+                    self.ping_nonce = None;
+                    self.latency = Some(now.saturating_sub(self.last_ping));
+                }
+            }
+            NetworkMessage::SendHeaders => self.peer_prefers_headers = true,
+            payload => self.events.push_back(Event::Message(payload)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use consensus::encode::serialize;
+    use network::constants::{Network, PROTOCOL_VERSION};
+    use network::message::{NetworkMessage, RawNetworkMessage};
+    use network::message_blockdata::GetHeadersMessage;
+    use network::peer::{DisconnectReason, Direction, Event, PeerConfig, PeerConnection, State};
+
+    fn address() -> SocketAddr {
+        ([127, 0, 0, 1], 2357).into()
+    }
+
+    fn pair(now: u64) -> (PeerConnection, PeerConnection) {
+        let outbound = PeerConnection::new(PeerConfig::new(Network::Testnet, 1), Direction::Outbound, address(), now);
+        let inbound = PeerConnection::new(PeerConfig::new(Network::Testnet, 2), Direction::Inbound, address(), now);
+        (outbound, inbound)
+    }
+
+    /// Exchange bytes between the connections until neither has anything to send
+    fn exchange(a: &mut PeerConnection, b: &mut PeerConnection, now: u64) {
+        loop {
+            let bytes = a.outbound_bytes();
+            b.receive_bytes(&bytes, now);
+            let reply = b.outbound_bytes();
+            a.receive_bytes(&reply, now);
+            if bytes.is_empty() && reply.is_empty() {
+                break;
+            }
+        }
+    }
+
+    fn events(connection: &mut PeerConnection) -> Vec<Event> {
+        let mut events = vec![];
+        while let Some(event) = connection.poll_event() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn handshake_test() {
+        let (mut a, mut b) = pair(1000);
+        assert_eq!(a.state(), State::Handshaking);
+        assert_eq!(b.state(), State::Handshaking);
+        assert!(b.poll_message().is_none());
+        assert!(!a.send(NetworkMessage::GetAddr));
+
+        match a.poll_message() {
+            Some(RawNetworkMessage { magic, payload: NetworkMessage::Version(ref version) }) => {
+                assert_eq!(magic, Network::Testnet.magic());
+                assert_eq!(version.version, PROTOCOL_VERSION);
+                assert_eq!(version.nonce, 1);
+                assert_eq!(version.timestamp, 1000);
+                b.receive(RawNetworkMessage { magic: magic, payload: NetworkMessage::Version(version.clone()) }, 1000);
+            }
+            x => panic!("expected version, got {:?}", x),
+        }
+
+        exchange(&mut a, &mut b, 1000);
+        assert_eq!(a.state(), State::Established);
+        assert_eq!(b.state(), State::Established);
+        assert_eq!(a.peer_version().unwrap().nonce, 2);
+        assert_eq!(b.peer_version().unwrap().nonce, 1);
+        assert!(a.peer_prefers_headers());
+        assert!(b.peer_prefers_headers());
+        match events(&mut a).as_slice() {
+            [Event::Connected(ref version)] => assert_eq!(version.nonce, 2),
+            x => panic!("expected connected, got {:?}", x),
+        }
+        assert_eq!(events(&mut b).len(), 1);
+
+        // messages not handled by the connection are passed to the application
+        assert!(a.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(vec![], Default::default()))));
+        exchange(&mut a, &mut b, 1000);
+        match events(&mut b).as_slice() {
+            [Event::Message(NetworkMessage::GetHeaders(_))] => {},
+            x => panic!("expected getheaders, got {:?}", x),
+        }
+        assert_eq!(a.misbehaviour(), 0);
+        assert_eq!(b.misbehaviour(), 0);
+    }
+
+    #[test]
+    fn partial_bytes_test() {
+        let (mut a, mut b) = pair(0);
+        let mut config = PeerConfig::new(Network::Testnet, 3);
+        config.send_headers = false;
+        let mut c = PeerConnection::new(config, Direction::Inbound, address(), 0);
+
+        // deliver the version message one byte at a time
+        let bytes = a.outbound_bytes();
+        for byte in bytes.iter() {
+            c.receive_bytes(&[*byte], 0);
+        }
+        assert!(c.peer_version().is_some());
+        exchange(&mut a, &mut c, 0);
+        assert_eq!(c.state(), State::Established);
+        assert!(!a.peer_prefers_headers());
+
+        // several messages in a single chunk
+        let mut bytes = serialize(&RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Version(c.peer_version().unwrap().clone()) });
+        bytes.extend(serialize(&RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Verack }));
+        b.receive_bytes(&bytes, 0);
+        assert_eq!(b.state(), State::Established);
+    }
+
+    #[test]
+    fn ping_test() {
+        let (mut a, mut b) = pair(0);
+        exchange(&mut a, &mut b, 0);
+        events(&mut a);
+
+        a.tick(119);
+        assert!(a.poll_message().is_none());
+        a.tick(120);
+        match a.poll_message() {
+            Some(RawNetworkMessage { payload: NetworkMessage::Ping(nonce), .. }) => {
+                b.receive(RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Ping(nonce) }, 125);
+            }
+            x => panic!("expected ping, got {:?}", x),
+        }
+        // a pong with another nonce is ignored
+        a.receive(RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Pong(0) }, 123);
+        assert_eq!(a.latency(), None);
+        exchange(&mut b, &mut a, 125);
+        assert_eq!(a.latency(), Some(5));
+        assert!(events(&mut a).is_empty());
+
+        // the next ping is not answered
+        a.tick(245);
+        assert!(a.poll_message().is_some());
+        a.tick(245 + 1199);
+        assert_eq!(a.state(), State::Established);
+        a.tick(245 + 1200);
+        assert_eq!(a.state(), State::Disconnected);
+        assert_eq!(events(&mut a), vec![Event::Disconnected(DisconnectReason::PingTimeout)]);
+    }
+
+    #[test]
+    fn handshake_failure_test() {
+        // timeout
+        let (mut a, _) = pair(0);
+        a.tick(59);
+        assert_eq!(a.state(), State::Handshaking);
+        a.tick(60);
+        assert_eq!(events(&mut a), vec![Event::Disconnected(DisconnectReason::HandshakeTimeout)]);
+
+        // a timeout of u64::MAX disables the check
+        let mut config = PeerConfig::new(Network::Testnet, 3);
+        config.handshake_timeout = u64::max_value();
+        let mut c = PeerConnection::new(config, Direction::Outbound, address(), 10);
+        c.tick(u64::max_value() - 1);
+        assert_eq!(c.state(), State::Handshaking);
+
+        // peer of another network
+        let (mut a, _) = pair(0);
+        let mut c = PeerConnection::new(PeerConfig::new(Network::Regtest, 3), Direction::Inbound, address(), 0);
+        c.receive_bytes(&a.outbound_bytes(), 0);
+        assert_eq!(c.state(), State::Disconnected);
+        assert_eq!(events(&mut c), vec![Event::Disconnected(DisconnectReason::WrongMagic(Network::Testnet.magic()))]);
+        assert!(c.poll_message().is_none());
+
+        // connection to self
+        let (mut a, _) = pair(0);
+        let mut c = PeerConnection::new(PeerConfig::new(Network::Testnet, 1), Direction::Inbound, address(), 0);
+        c.receive_bytes(&a.outbound_bytes(), 0);
+        assert_eq!(events(&mut c), vec![Event::Disconnected(DisconnectReason::SelfConnection)]);
+
+        // obsolete version
+        let (mut a, _) = pair(0);
+        let mut config = PeerConfig::new(Network::Testnet, 3);
+        config.min_version = PROTOCOL_VERSION + 1;
+        let mut c = PeerConnection::new(config, Direction::Inbound, address(), 0);
+        c.receive_bytes(&a.outbound_bytes(), 0);
+        assert_eq!(events(&mut c), vec![Event::Disconnected(DisconnectReason::ObsoleteVersion(PROTOCOL_VERSION))]);
+    }
+
+    #[test]
+    fn misbehaviour_test() {
+        let (mut a, mut b) = pair(0);
+
+        // messages before the version message
+        b.receive(RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Verack }, 0);
+        b.receive(RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Ping(1) }, 0);
+        assert_eq!(b.misbehaviour(), 2);
+        assert_eq!(events(&mut b), vec![
+            Event::Misbehaved { score: 1, reason: "verack message before version".to_owned() },
+            Event::Misbehaved { score: 2, reason: "ping message before handshake".to_owned() },
+        ]);
+        assert!(b.poll_message().is_none());
+
+        exchange(&mut a, &mut b, 0);
+        assert_eq!(b.state(), State::Established);
+        events(&mut b);
+
+        // duplicate version
+        let version = b.peer_version().unwrap().clone();
+        b.receive(RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Version(version) }, 0);
+        assert_eq!(b.misbehaviour(), 3);
+
+        // bad checksum
+        let mut bytes = serialize(&RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Ping(7) });
+        bytes[20] ^= 0xff;
+        b.receive_bytes(&bytes, 0);
+        assert_eq!(b.misbehaviour(), 13);
+        assert!(b.poll_message().is_none());
+
+        // unknown commands are ignored
+        let mut bytes = serialize(&RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Ping(7) });
+        bytes[4..8].copy_from_slice(b"pang");
+        b.receive_bytes(&bytes, 0);
+        assert_eq!(b.misbehaviour(), 13);
+
+        // oversized message
+        let mut bytes = serialize(&RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Ping(7) });
+        bytes[16..20].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        let (_, mut c) = pair(0);
+        c.receive_bytes(&bytes, 0);
+        assert_eq!(events(&mut c), vec![Event::Disconnected(DisconnectReason::OversizedMessage(0xffffffff))]);
+
+        events(&mut b);
+        b.misbehaving(87, "invalid header".to_owned());
+        assert_eq!(b.state(), State::Disconnected);
+        assert_eq!(events(&mut b), vec![
+            Event::Misbehaved { score: 100, reason: "invalid header".to_owned() },
+            Event::Disconnected(DisconnectReason::Misbehaviour(100)),
+        ]);
+        assert!(!b.send(NetworkMessage::Ping(1)));
+    }
+}
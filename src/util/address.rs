@@ -52,6 +52,7 @@ use bech32;
 use hashes::Hash;
 
 use hash_types::{PubkeyHash, WPubkeyHash, ScriptHash, WScriptHash};
+use blockdata::color::{self, ColorIdentifier, COLOR_IDENTIFIER_SIZE};
 use blockdata::opcodes;
 use blockdata::script;
 use network::constants::Network;
@@ -73,6 +74,8 @@ pub enum Error {
     InvalidWitnessProgramLength(usize),
     /// A v0 witness program must be either of length 20 or 32.
     InvalidSegwitV0ProgramLength(usize),
+    /// The color identifier of a colored address is invalid
+    InvalidColorIdentifier(color::Error),
 }
 
 impl fmt::Display for Error {
@@ -92,6 +95,7 @@ impl fmt::Display for Error {
                 "a v0 witness program must be either of length 20 or 32 bytes: length={}",
                 l
             ),
+            Error::InvalidColorIdentifier(ref e) => write!(f, "invalid color identifier: {}", e),
         }
     }
 }
@@ -101,6 +105,7 @@ impl ::std::error::Error for Error {
         match *self {
             Error::Base58(ref e) => Some(e),
             Error::Bech32(ref e) => Some(e),
+            Error::InvalidColorIdentifier(ref e) => Some(e),
             _ => None,
         }
     }
@@ -135,6 +140,10 @@ pub enum AddressType {
     P2wpkh,
     /// pay-to-witness-script-hash
     P2wsh,
+    /// colored pay-to-pubkey-hash
+    Cp2pkh,
+    /// colored pay-to-script-hash
+    Cp2sh,
 }
 
 impl fmt::Display for AddressType {
@@ -144,6 +153,8 @@ impl fmt::Display for AddressType {
             AddressType::P2sh => "p2sh",
             AddressType::P2wpkh => "p2wpkh",
             AddressType::P2wsh => "p2wsh",
+            AddressType::Cp2pkh => "cp2pkh",
+            AddressType::Cp2sh => "cp2sh",
         })
     }
 }
@@ -156,6 +167,8 @@ impl FromStr for AddressType {
             "p2sh" => Ok(AddressType::P2sh),
             "p2wpkh" => Ok(AddressType::P2wpkh),
             "p2wsh" => Ok(AddressType::P2wsh),
+            "cp2pkh" => Ok(AddressType::Cp2pkh),
+            "cp2sh" => Ok(AddressType::Cp2sh),
             _ => Err(()),
         }
     }
@@ -175,12 +188,22 @@ pub enum Payload {
         /// The witness program
         program: Vec<u8>,
     },
+    /// colored pay-to-pkhash address
+    ColoredPubkeyHash(ColorIdentifier, PubkeyHash),
+    /// colored P2SH address
+    ColoredScriptHash(ColorIdentifier, ScriptHash),
 }
 
 impl Payload {
     /// Get a [Payload] from an output script (scriptPubkey).
     pub fn from_script(script: &script::Script) -> Option<Payload> {
-        Some(if script.is_p2pkh() {
+        Some(if script.is_cp2pkh() {
+            let color_id = script.color_id().expect("checked before");
+            Payload::ColoredPubkeyHash(color_id, PubkeyHash::from_slice(&script.as_bytes()[38..58]).unwrap())
+        } else if script.is_cp2sh() {
+            let color_id = script.color_id().expect("checked before");
+            Payload::ColoredScriptHash(color_id, ScriptHash::from_slice(&script.as_bytes()[37..57]).unwrap())
+        } else if script.is_p2pkh() {
             Payload::PubkeyHash(PubkeyHash::from_slice(&script.as_bytes()[3..23]).unwrap())
         } else if script.is_p2sh() {
             Payload::ScriptHash(ScriptHash::from_slice(&script.as_bytes()[2..22]).unwrap())
@@ -228,9 +251,32 @@ impl Payload {
                 }
                 script::Builder::new().push_opcode(verop.into()).push_slice(&prog)
             }
+            Payload::ColoredPubkeyHash(ref color_id, ref hash) => script::Builder::new()
+                .push_slice(&color_id.to_bytes()[..])
+                .push_opcode(opcodes::all::OP_COLOR)
+                .push_opcode(opcodes::all::OP_DUP)
+                .push_opcode(opcodes::all::OP_HASH160)
+                .push_slice(&hash[..])
+                .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                .push_opcode(opcodes::all::OP_CHECKSIG),
+            Payload::ColoredScriptHash(ref color_id, ref hash) => script::Builder::new()
+                .push_slice(&color_id.to_bytes()[..])
+                .push_opcode(opcodes::all::OP_COLOR)
+                .push_opcode(opcodes::all::OP_HASH160)
+                .push_slice(&hash[..])
+                .push_opcode(opcodes::all::OP_EQUAL),
         }
         .into_script()
     }
+
+    /// The color identifier of a colored payload
+    pub fn color_id(&self) -> Option<&ColorIdentifier> {
+        match *self {
+            Payload::ColoredPubkeyHash(ref color_id, _) |
+            Payload::ColoredScriptHash(ref color_id, _) => Some(color_id),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -323,12 +369,35 @@ impl Address {
         }
     }
 
+    /// Creates a colored pay to public key hash address, to which tokens of
+    /// `color_id` are sent
+    pub fn cp2pkh(color_id: &ColorIdentifier, pk: &key::PublicKey, network: Network) -> Address {
+        let mut hash_engine = PubkeyHash::engine();
+        pk.write_into(&mut hash_engine);
+
+        Address {
+            network: network,
+            payload: Payload::ColoredPubkeyHash(*color_id, PubkeyHash::from_engine(hash_engine)),
+        }
+    }
+
+    /// Creates a colored pay to script hash address, to which tokens of
+    /// `color_id` are sent
+    pub fn cp2sh(color_id: &ColorIdentifier, script: &script::Script, network: Network) -> Address {
+        Address {
+            network: network,
+            payload: Payload::ColoredScriptHash(*color_id, ScriptHash::hash(&script[..])),
+        }
+    }
+
     /// Get the address type of the address.
     /// None if unknown or non-standard.
     pub fn address_type(&self) -> Option<AddressType> {
         match self.payload {
             Payload::PubkeyHash(_) => Some(AddressType::P2pkh),
             Payload::ScriptHash(_) => Some(AddressType::P2sh),
+            Payload::ColoredPubkeyHash(..) => Some(AddressType::Cp2pkh),
+            Payload::ColoredScriptHash(..) => Some(AddressType::Cp2sh),
             Payload::WitnessProgram {
                 version: ver,
                 program: ref prog,
@@ -367,6 +436,11 @@ impl Address {
     pub fn script_pubkey(&self) -> script::Script {
         self.payload.script_pubkey()
     }
+
+    /// The color identifier of a colored address
+    pub fn color_id(&self) -> Option<&ColorIdentifier> {
+        self.payload.color_id()
+    }
 }
 
 impl Display for Address {
@@ -376,7 +450,7 @@ impl Display for Address {
                 let mut prefixed = [0; 21];
                 prefixed[0] = match self.network {
                     Network::Bitcoin | Network::Paradium => 0,
-                    Network::Testnet | Network::Regtest => 111,
+                    Network::Testnet | Network::Regtest | Network::Custom(_) => 111,
                 };
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
@@ -385,18 +459,38 @@ impl Display for Address {
                 let mut prefixed = [0; 21];
                 prefixed[0] = match self.network {
                     Network::Bitcoin | Network::Paradium => 5,
-                    Network::Testnet | Network::Regtest => 196,
+                    Network::Testnet | Network::Regtest | Network::Custom(_) => 196,
                 };
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
             }
+            Payload::ColoredPubkeyHash(ref color_id, ref hash) => {
+                let mut prefixed = [0; COLORED_ADDRESS_SIZE];
+                prefixed[0] = match self.network {
+                    Network::Bitcoin | Network::Paradium => 1,
+                    Network::Testnet | Network::Regtest | Network::Custom(_) => 112,
+                };
+                prefixed[1..COLOR_IDENTIFIER_SIZE + 1].copy_from_slice(&color_id.to_bytes()[..]);
+                prefixed[COLOR_IDENTIFIER_SIZE + 1..].copy_from_slice(&hash[..]);
+                base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
+            }
+            Payload::ColoredScriptHash(ref color_id, ref hash) => {
+                let mut prefixed = [0; COLORED_ADDRESS_SIZE];
+                prefixed[0] = match self.network {
+                    Network::Bitcoin | Network::Paradium => 6,
+                    Network::Testnet | Network::Regtest | Network::Custom(_) => 197,
+                };
+                prefixed[1..COLOR_IDENTIFIER_SIZE + 1].copy_from_slice(&color_id.to_bytes()[..]);
+                prefixed[COLOR_IDENTIFIER_SIZE + 1..].copy_from_slice(&hash[..]);
+                base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
+            }
             Payload::WitnessProgram {
                 version: ver,
                 program: ref prog,
             } => {
                 let hrp = match self.network {
                     Network::Bitcoin => "bc",
-                    Network::Testnet => "tb",
+                    Network::Testnet | Network::Custom(_) => "tb",
                     Network::Regtest => "bcrt",
                     Network::Paradium => "prdm",
                 };
@@ -408,6 +502,10 @@ impl Display for Address {
     }
 }
 
+/// The size of the decoded base58 data of a colored address: version byte,
+/// color identifier and hash
+const COLORED_ADDRESS_SIZE: usize = 1 + COLOR_IDENTIFIER_SIZE + 20;
+
 /// Extract the bech32 prefix.
 /// Returns the same slice when no prefix is found.
 fn find_bech32_prefix(bech32: &str) -> &str {
@@ -466,10 +564,38 @@ impl FromStr for Address {
         }
 
         // Base58
-        if s.len() > 50 {
+        if s.len() > 80 {
             return Err(Error::Base58(base58::Error::InvalidLength(s.len() * 11 / 15)));
         }
         let data = base58::from_check(s)?;
+        if data.len() == COLORED_ADDRESS_SIZE {
+            let color_id = ColorIdentifier::from_slice(&data[1..COLOR_IDENTIFIER_SIZE + 1])
+                .map_err(Error::InvalidColorIdentifier)?;
+            let hash = &data[COLOR_IDENTIFIER_SIZE + 1..];
+            let (network, payload) = match data[0] {
+                1 => (
+                    Network::Bitcoin,
+                    Payload::ColoredPubkeyHash(color_id, PubkeyHash::from_slice(hash).unwrap()),
+                ),
+                6 => (
+                    Network::Bitcoin,
+                    Payload::ColoredScriptHash(color_id, ScriptHash::from_slice(hash).unwrap()),
+                ),
+                112 => (
+                    Network::Testnet,
+                    Payload::ColoredPubkeyHash(color_id, PubkeyHash::from_slice(hash).unwrap()),
+                ),
+                197 => (
+                    Network::Testnet,
+                    Payload::ColoredScriptHash(color_id, ScriptHash::from_slice(hash).unwrap()),
+                ),
+                x => return Err(Error::Base58(base58::Error::InvalidVersion(vec![x]))),
+            };
+            return Ok(Address {
+                network: network,
+                payload: payload,
+            });
+        }
         if data.len() != 21 {
             return Err(Error::Base58(base58::Error::InvalidLength(data.len())));
         }
@@ -517,6 +643,7 @@ mod tests {
 
     use blockdata::script::Script;
     use network::constants::Network::{Bitcoin, Testnet};
+    use network::constants::NetworkId;
     use util::key::PublicKey;
 
     use super::*;
@@ -570,6 +697,10 @@ mod tests {
         assert_eq!(&addr.to_string(), "mqkhEMH6NCeYjFybv7pvFC22MFeaNT9AQC");
         assert_eq!(addr.address_type(), Some(AddressType::P2pkh));
         roundtrips(&addr);
+
+        // custom networks use the testnet prefixes
+        let addr = Address::p2pkh(&key, Network::Custom(NetworkId::from(1234)));
+        assert_eq!(&addr.to_string(), "mqkhEMH6NCeYjFybv7pvFC22MFeaNT9AQC");
     }
 
     #[test]
@@ -598,6 +729,70 @@ mod tests {
         roundtrips(&addr);
     }
 
+    #[test]
+    fn test_cp2pkh_address_58() {
+        let color_id = ColorIdentifier::from_str("c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167").unwrap();
+        let addr = Address {
+            network: Bitcoin,
+            payload: Payload::ColoredPubkeyHash(color_id, hex_pubkeyhash!("162c5ea71c0b23f5b9022ef047c4a86470a5b070")),
+        };
+
+        assert_eq!(
+            addr.script_pubkey(),
+            hex_script!("21c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167bc76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac")
+        );
+        assert_eq!(&addr.to_string(), "vkcxMvSPvUCpjRGFeDhTyPKziJAaLf27fS46vTCcY31phpLSYzoESwT8DY4WtXpL8dXdAjhDCoGvi2");
+        assert_eq!(addr.address_type(), Some(AddressType::Cp2pkh));
+        assert_eq!(addr.color_id(), Some(&color_id));
+        roundtrips(&addr);
+
+        let addr = Address { network: Testnet, ..addr };
+        assert_eq!(&addr.to_string(), "22VMv5qRNJX91dzZH1JR2yacwd6yiuQhdcTdTkvWDj4GtG6hmMcUpdQmWSWPmovzUxuQU6SMgdxB1Mgq");
+        roundtrips(&addr);
+
+        let key = hex_key!(&"03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f");
+        let addr = Address::cp2pkh(&color_id, &key, Testnet);
+        assert_eq!(addr.script_pubkey().remove_color(), Address::p2pkh(&key, Testnet).script_pubkey());
+        roundtrips(&addr);
+    }
+
+    #[test]
+    fn test_cp2sh_address_58() {
+        let color_id = ColorIdentifier::from_str("c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167").unwrap();
+        let addr = Address {
+            network: Bitcoin,
+            payload: Payload::ColoredScriptHash(color_id, hex_scripthash!("162c5ea71c0b23f5b9022ef047c4a86470a5b070")),
+        };
+
+        assert_eq!(
+            addr.script_pubkey(),
+            hex_script!("21c1b07a5f6cd95e3a9085345e6b6f8977355d1f8c43cd0da43b8584708c8bdd9167bca914162c5ea71c0b23f5b9022ef047c4a86470a5b07087")
+        );
+        assert_eq!(&addr.to_string(), "4ZkeFRFWPgMbJzD5qUy4CawHwecfj35B2gzSbHMb8cXrbjtk2pJnFUw19ZdSEQx1UagbePyrbCNLBL7");
+        assert_eq!(addr.address_type(), Some(AddressType::Cp2sh));
+        roundtrips(&addr);
+
+        let addr = Address { network: Testnet, ..addr };
+        assert_eq!(&addr.to_string(), "2oLNJ5p1YEPCbvKzDvb87TzzLj2QGTFnF9v8453rpYNjBThzgQ6kXvzdtqPy8yr5kSYJeTKWS6HSziWt");
+        roundtrips(&addr);
+
+        let script = hex_script!("552103a765fc35b3f210b95223846b36ef62a4e53e34e2925270c2c7906b92c9f718eb2103c327511374246759ec8d0b89fa6c6b23b33e11f92c5bc155409d86de0c79180121038cae7406af1f12f4786d820a1466eec7bc5785a1b5e4a387eca6d797753ef6db2103252bfb9dcaab0cd00353f2ac328954d791270203d66c2be8b430f115f451b8a12103e79412d42372c55dd336f2eb6eb639ef9d74a22041ba79382c74da2338fe58ad21035049459a4ebc00e876a9eef02e72a3e70202d3d1f591fc0dd542f93f642021f82102016f682920d9723c61b27f562eb530c926c00106004798b6471e8c52c60ee02057ae");
+        let addr = Address::cp2sh(&color_id, &script, Testnet);
+        assert_eq!(addr.script_pubkey().remove_color(), Address::p2sh(&script, Testnet).script_pubkey());
+        roundtrips(&addr);
+    }
+
+    #[test]
+    fn test_colored_address_invalid_color_id() {
+        // the address of test_cp2pkh_address_58 with the token type 0xc4
+        let mut data = base58::from_check("vkcxMvSPvUCpjRGFeDhTyPKziJAaLf27fS46vTCcY31phpLSYzoESwT8DY4WtXpL8dXdAjhDCoGvi2").unwrap();
+        data[1] = 0xc4;
+        assert_eq!(
+            Address::from_str(&base58::check_encode_slice(&data)),
+            Err(Error::InvalidColorIdentifier(color::Error::UnknownTokenType(0xc4)))
+        );
+    }
+
     #[test]
     fn test_p2wpkh() {
         // stolen from Bitcoin transaction: b3c8c2b6cfc335abbcb2c7823a8453f55d64b2b5125a9a61e8737230cdb8ce20
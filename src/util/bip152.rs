@@ -0,0 +1,638 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! BIP152 Compact Blocks
+//!
+//! A block is relayed as its header and the short ids of its transactions,
+//! which the receiving peer looks up in its mempool, so that only the
+//! transactions it is missing are sent in full.
+//!
+//! The SipHash keys of the short ids are derived from the serialized header,
+//! which includes the `xfield` and proof of Tapyrus. Short ids are computed
+//! over the txids, which commit to the whole transaction as the merkle root
+//! of the header does.
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::{error, fmt, io, mem, u16};
+
+use hashes::{sha256, siphash24, Hash};
+use blockdata::block::{Block, BlockHeader};
+use blockdata::transaction::Transaction;
+use consensus::encode::{self, Decodable, Encodable, VarInt, MAX_VEC_SIZE};
+use hash_types::{BlockHash, Txid};
+use util::endian;
+use util::hash::BitcoinHash;
+
+/// The maximum number of transactions of a compact block, whose positions
+/// must fit in 16 bits
+pub const MAX_TRANSACTIONS: usize = u16::MAX as usize;
+
+/// An error in reconstructing a block from a compact block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The block has no transaction
+    EmptyBlock,
+    /// The block has more than `MAX_TRANSACTIONS` transactions
+    TooManyTransactions(usize),
+    /// A prefilled transaction is out of the block, or at the position of another one
+    InvalidPrefilledIndex(usize),
+    /// Two transactions of the compact block have the same short id
+    ShortIdCollision,
+    /// The transactions were sent for another block
+    UnexpectedBlock(BlockHash),
+    /// The number of transactions does not match the number of missing ones
+    TransactionCountMismatch {
+        /// The number of missing transactions
+        expected: usize,
+        /// The number of transactions received
+        actual: usize,
+    },
+    /// The reconstructed block does not match the merkle roots of its header
+    MerkleRootMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EmptyBlock => f.write_str("block has no transaction"),
+            Error::TooManyTransactions(count) => write!(f,
+                "too many transactions: {}, max {}", count, MAX_TRANSACTIONS),
+            Error::InvalidPrefilledIndex(index) => write!(f, "invalid prefilled transaction index: {}", index),
+            Error::ShortIdCollision => f.write_str("short id collision"),
+            Error::UnexpectedBlock(ref hash) => write!(f, "transactions of unexpected block: {}", hash),
+            Error::TransactionCountMismatch { expected, actual } => write!(f,
+                "transaction count mismatch: expected {}, got {}", expected, actual),
+            Error::MerkleRootMismatch => f.write_str("merkle root mismatch"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::EmptyBlock => "block has no transaction",
+            Error::TooManyTransactions(_) => "too many transactions",
+            Error::InvalidPrefilledIndex(_) => "invalid prefilled transaction index",
+            Error::ShortIdCollision => "short id collision",
+            Error::UnexpectedBlock(_) => "transactions of unexpected block",
+            Error::TransactionCountMismatch { .. } => "transaction count mismatch",
+            Error::MerkleRootMismatch => "merkle root mismatch",
+        }
+    }
+}
+
+/// A short transaction id: the lower 6 bytes of the SipHash-2-4 of the txid
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ShortId(pub [u8; 6]);
+
+impl ShortId {
+    /// Compute the short id of a txid with the SipHash keys of a compact block
+    pub fn with_siphash_keys(txid: &Txid, (k0, k1): (u64, u64)) -> ShortId {
+        let hash = siphash24::Hash::hash_to_u64_with_keys(k0, k1, &txid[..]);
+        let mut id = [0u8; 6];
+        id.copy_from_slice(&endian::u64_to_array_le(hash)[0..6]);
+        ShortId(id)
+    }
+}
+
+impl Encodable for ShortId {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for ShortId {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(ShortId(Decodable::consensus_decode(d)?))
+    }
+}
+
+/// A transaction sent in full in a compact block
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PrefilledTransaction {
+    /// The position of the transaction in the block
+    pub index: u16,
+    /// The transaction
+    pub tx: Transaction,
+}
+
+/// Encode the next of increasing indexes, as the difference to the previous
+/// index plus one
+fn encode_index<S: io::Write>(index: u16, next: &mut u64, s: S) -> Result<usize, encode::Error> {
+    let index = index as u64;
+    if index < *next {
+        return Err(encode::Error::ParseFailed("indexes are not increasing"));
+    }
+    let len = VarInt(index - *next).consensus_encode(s)?;
+    *next = index + 1;
+    Ok(len)
+}
+
+/// Decode the next of differentially encoded indexes
+fn decode_index<D: io::Read>(next: &mut u64, d: D) -> Result<u16, encode::Error> {
+    let index = next.checked_add(VarInt::consensus_decode(d)?.0)
+        .ok_or(encode::Error::ParseFailed("index overflow"))?;
+    if index > u16::MAX as u64 {
+        return Err(encode::Error::ParseFailed("index overflow"));
+    }
+    *next = index + 1;
+    Ok(index as u16)
+}
+
+/// Decode the number of items of a vector of a compact block message, which
+/// may not exceed `MAX_TRANSACTIONS`
+fn decode_count<D: io::Read>(d: D) -> Result<usize, encode::Error> {
+    let count = VarInt::consensus_decode(d)?.0;
+    if count > MAX_TRANSACTIONS as u64 {
+        return Err(encode::Error::ParseFailed("indexes overflowed 16 bits"));
+    }
+    Ok(count as usize)
+}
+
+/// A block relayed as its header, the short ids of its transactions and the
+/// transactions sent in full, at least the coinbase
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct HeaderAndShortIds {
+    /// The block header
+    pub header: BlockHeader,
+    /// The nonce mixed into the SipHash keys
+    pub nonce: u64,
+    /// The short ids of the transactions which are not prefilled, in block order
+    pub short_ids: Vec<ShortId>,
+    /// The transactions sent in full, by increasing index
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+impl HeaderAndShortIds {
+    /// Create a compact block of a block. The coinbase and the transactions at
+    /// the positions `prefill` are sent in full. `nonce` should be random.
+    /// Fails if the block is empty or has more than `MAX_TRANSACTIONS`
+    /// transactions.
+    pub fn from_block(block: &Block, nonce: u64, prefill: &[usize]) -> Result<HeaderAndShortIds, Error> {
+        if block.txdata.is_empty() {
+            return Err(Error::EmptyBlock);
+        }
+        if block.txdata.len() > MAX_TRANSACTIONS {
+            return Err(Error::TooManyTransactions(block.txdata.len()));
+        }
+
+        let mut compact = HeaderAndShortIds {
+            header: block.header.clone(),
+            nonce: nonce,
+            short_ids: Vec::with_capacity(block.txdata.len()),
+            prefilled_txs: vec![],
+        };
+        let keys = compact.siphash_keys();
+        for (index, tx) in block.txdata.iter().enumerate() {
+            if index == 0 || prefill.contains(&index) {
+                compact.prefilled_txs.push(PrefilledTransaction { index: index as u16, tx: tx.clone() });
+            } else {
+                compact.short_ids.push(ShortId::with_siphash_keys(&tx.txid(), keys));
+            }
+        }
+        Ok(compact)
+    }
+
+    /// The SipHash keys of the short ids: the first two little-endian 64-bit
+    /// integers of the SHA256 of the header and the nonce
+    pub fn siphash_keys(&self) -> (u64, u64) {
+        let mut engine = sha256::Hash::engine();
+        self.header.consensus_encode(&mut engine).unwrap();
+        self.nonce.consensus_encode(&mut engine).unwrap();
+        let hash = sha256::Hash::from_engine(engine);
+        (endian::slice_to_u64_le(&hash[0..8]), endian::slice_to_u64_le(&hash[8..16]))
+    }
+
+    /// The short id of a transaction in this compact block
+    pub fn short_id(&self, txid: &Txid) -> ShortId {
+        ShortId::with_siphash_keys(txid, self.siphash_keys())
+    }
+}
+
+impl Encodable for HeaderAndShortIds {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.header.consensus_encode(&mut s)?;
+        len += self.nonce.consensus_encode(&mut s)?;
+        len += VarInt(self.short_ids.len() as u64).consensus_encode(&mut s)?;
+        for short_id in &self.short_ids {
+            len += short_id.consensus_encode(&mut s)?;
+        }
+        len += VarInt(self.prefilled_txs.len() as u64).consensus_encode(&mut s)?;
+        let mut next = 0;
+        for prefilled in &self.prefilled_txs {
+            len += encode_index(prefilled.index, &mut next, &mut s)?;
+            len += prefilled.tx.consensus_encode(&mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for HeaderAndShortIds {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let header = Decodable::consensus_decode(&mut d)?;
+        let nonce = Decodable::consensus_decode(&mut d)?;
+        let count = decode_count(&mut d)?;
+        let mut short_ids = Vec::with_capacity(count);
+        for _ in 0..count {
+            short_ids.push(ShortId::consensus_decode(&mut d)?);
+        }
+        let count = decode_count(&mut d)?;
+        if short_ids.len() + count > MAX_TRANSACTIONS {
+            return Err(encode::Error::ParseFailed("indexes overflowed 16 bits"));
+        }
+        let mut prefilled_txs = vec![];
+        let mut next = 0;
+        for _ in 0..count {
+            let index = decode_index(&mut next, &mut d)?;
+            let tx = Transaction::consensus_decode_strict(&mut d)?;
+            prefilled_txs.push(PrefilledTransaction { index: index, tx: tx });
+        }
+        Ok(HeaderAndShortIds {
+            header: header,
+            nonce: nonce,
+            short_ids: short_ids,
+            prefilled_txs: prefilled_txs,
+        })
+    }
+}
+
+/// A request for the transactions of a block which could not be found from
+/// their short ids
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTransactionsRequest {
+    /// The hash of the block
+    pub block_hash: BlockHash,
+    /// The positions of the requested transactions in the block, increasing
+    pub indexes: Vec<u16>,
+}
+
+impl Encodable for BlockTransactionsRequest {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.block_hash.consensus_encode(&mut s)?;
+        len += VarInt(self.indexes.len() as u64).consensus_encode(&mut s)?;
+        let mut next = 0;
+        for index in &self.indexes {
+            len += encode_index(*index, &mut next, &mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockTransactionsRequest {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let block_hash = Decodable::consensus_decode(&mut d)?;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        let mut indexes = vec![];
+        let mut next = 0;
+        for _ in 0..count {
+            indexes.push(decode_index(&mut next, &mut d)?);
+        }
+        Ok(BlockTransactionsRequest {
+            block_hash: block_hash,
+            indexes: indexes,
+        })
+    }
+}
+
+/// The transactions of a block sent in response to a `BlockTransactionsRequest`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTransactions {
+    /// The hash of the block
+    pub block_hash: BlockHash,
+    /// The requested transactions, in the order of the request
+    pub transactions: Vec<Transaction>,
+}
+
+impl Encodable for BlockTransactions {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.block_hash.consensus_encode(&mut s)?;
+        len += self.transactions.consensus_encode(&mut s)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockTransactions {
+    /// Transactions are decoded with `Transaction::consensus_decode_strict`,
+    /// as those of a `block` message are.
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let block_hash = Decodable::consensus_decode(&mut d)?;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        let byte_size = (count as usize)
+            .checked_mul(mem::size_of::<Transaction>())
+            .ok_or(encode::Error::ParseFailed("Invalid length"))?;
+        if byte_size > MAX_VEC_SIZE {
+            return Err(encode::Error::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE });
+        }
+        let mut transactions = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            transactions.push(Transaction::consensus_decode_strict(&mut d)?);
+        }
+        Ok(BlockTransactions {
+            block_hash: block_hash,
+            transactions: transactions,
+        })
+    }
+}
+
+/// A block being reconstructed from a compact block and the transactions of
+/// the mempool
+#[derive(Clone, Debug)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txdata: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Start reconstructing the block of a compact block, looking up the
+    /// transactions which are not prefilled in `mempool`. A transaction is
+    /// left missing if several mempool transactions match its short id.
+    pub fn new<'a, I>(compact: &HeaderAndShortIds, mempool: I) -> Result<PartialBlock, Error>
+        where I: IntoIterator<Item = &'a Transaction>
+    {
+        let count = compact.short_ids.len() + compact.prefilled_txs.len();
+        if count == 0 {
+            return Err(Error::EmptyBlock);
+        }
+        if count > MAX_TRANSACTIONS {
+            return Err(Error::TooManyTransactions(count));
+        }
+        let mut txdata: Vec<Option<Transaction>> = vec![None; count];
+        for prefilled in &compact.prefilled_txs {
+            let index = prefilled.index as usize;
+            if index >= count || txdata[index].is_some() {
+                return Err(Error::InvalidPrefilledIndex(index));
+            }
+            txdata[index] = Some(prefilled.tx.clone());
+        }
+
+        // the remaining positions are those of the short ids, in order
+        let mut positions = HashMap::with_capacity(compact.short_ids.len());
+        let free: Vec<usize> = txdata.iter().enumerate()
+            .filter(|&(_, tx)| tx.is_none())
+            .map(|(index, _)| index)
+            .collect();
+        for (short_id, &index) in compact.short_ids.iter().zip(free.iter()) {
+            if positions.insert(*short_id, index).is_some() {
+                return Err(Error::ShortIdCollision);
+            }
+        }
+
+        let keys = compact.siphash_keys();
+        let mut ambiguous = HashSet::new();
+        for tx in mempool {
+            let txid = tx.txid();
+            if let Some(&index) = positions.get(&ShortId::with_siphash_keys(&txid, keys)) {
+                let collides = match txdata[index] {
+                    Some(ref found) => found.txid() != txid,
+                    None => false,
+                };
+                if collides {
+                    ambiguous.insert(index);
+                } else {
+                    txdata[index] = Some(tx.clone());
+                }
+            }
+        }
+        for index in ambiguous {
+            txdata[index] = None;
+        }
+
+        Ok(PartialBlock {
+            header: compact.header.clone(),
+            txdata: txdata,
+        })
+    }
+
+    /// The header of the block
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// The positions of the transactions which are still missing
+    pub fn missing(&self) -> Vec<u16> {
+        // `new` bounds the number of transactions to `MAX_TRANSACTIONS`
+        self.txdata.iter().enumerate()
+            .filter(|&(_, tx)| tx.is_none())
+            .map(|(index, _)| index as u16)
+            .collect()
+    }
+
+    /// The request for the missing transactions, to be sent in a `getblocktxn`
+    /// message to the peer which sent the compact block
+    pub fn request(&self) -> BlockTransactionsRequest {
+        BlockTransactionsRequest {
+            block_hash: self.header.bitcoin_hash(),
+            indexes: self.missing(),
+        }
+    }
+
+    /// Complete the block with the missing transactions received in a
+    /// `blocktxn` message
+    pub fn fill(self, response: BlockTransactions) -> Result<Block, Error> {
+        let block_hash = self.header.bitcoin_hash();
+        if response.block_hash != block_hash {
+            return Err(Error::UnexpectedBlock(response.block_hash));
+        }
+        self.complete(response.transactions)
+    }
+
+    /// The block, when no transaction is missing
+    pub fn into_block(self) -> Result<Block, Error> {
+        self.complete(vec![])
+    }
+
+    fn complete(self, transactions: Vec<Transaction>) -> Result<Block, Error> {
+        let expected = self.txdata.iter().filter(|tx| tx.is_none()).count();
+        if transactions.len() != expected {
+            return Err(Error::TransactionCountMismatch { expected: expected, actual: transactions.len() });
+        }
+
+        let mut transactions = transactions.into_iter();
+        let block = Block {
+            header: self.header,
+            txdata: self.txdata.into_iter()
+                .map(|tx| tx.or_else(|| transactions.next()).expect("counted missing transactions"))
+                .collect(),
+        };
+        // a short id matching another transaction is only found out here
+        if !block.check_merkle_root() {
+            return Err(Error::MerkleRootMismatch);
+        }
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use hex::decode as hex_decode;
+
+    use blockdata::block::Block;
+    use consensus::encode::{self, deserialize, serialize, Encodable};
+    use hashes::Hash;
+    use hash_types::BlockHash;
+    use util::bip152::{BlockTransactions, BlockTransactionsRequest, Error, HeaderAndShortIds, PartialBlock, ShortId, MAX_TRANSACTIONS};
+    use util::hash::BitcoinHash;
+
+    fn get_block() -> Block {
+        let block_hex =
+            "010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae\
+            34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594a\
+            ca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8\
+            156bebd2ffd1af00020100000001000000000000000000000000000000000000000000000000000000000000\
+            0000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a424\
+            6c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a1\
+            9a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c9246\
+            64889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067\
+            fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79\
+            cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957c\
+            dd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f\
+            88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b485\
+            6ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c\
+            6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5\
+            ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc4\
+            7c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000";
+        deserialize(&hex_decode(block_hex).unwrap()).unwrap()
+    }
+
+    /// A transaction in the segwit format
+    fn segwit_tx() -> Vec<u8> {
+        hex_decode(
+            "02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c01000000\
+            00ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022\
+            100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd271\
+            0e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed89410\
+            55d3bcb8627d085e94553e62f057dcc00000000"
+        ).unwrap()
+    }
+
+    #[test]
+    fn header_and_short_ids_test() {
+        let block = get_block();
+        let compact = HeaderAndShortIds::from_block(&block, 0x0102030405060708, &[]).unwrap();
+        assert_eq!(compact.short_ids, vec![ShortId([0xf9, 0xba, 0x7d, 0x2c, 0xcb, 0x40])]);
+        assert_eq!(compact.short_id(&block.txdata[1].txid()), compact.short_ids[0]);
+        assert_eq!(compact.prefilled_txs.len(), 1);
+        assert_eq!(compact.prefilled_txs[0].index, 0);
+        assert_eq!(compact.prefilled_txs[0].tx, block.txdata[0]);
+        assert_eq!(deserialize::<HeaderAndShortIds>(&serialize(&compact)).unwrap(), compact);
+
+        let compact = HeaderAndShortIds::from_block(&block, 0, &[1]).unwrap();
+        assert!(compact.short_ids.is_empty());
+        assert_eq!(compact.prefilled_txs.iter().map(|p| p.index).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(deserialize::<HeaderAndShortIds>(&serialize(&compact)).unwrap(), compact);
+
+        // blocks which cannot be relayed as compact blocks
+        let mut empty = block.clone();
+        empty.txdata.clear();
+        assert_eq!(HeaderAndShortIds::from_block(&empty, 0, &[]), Err(Error::EmptyBlock));
+        let mut large = block.clone();
+        large.txdata = vec![block.txdata[1].clone(); MAX_TRANSACTIONS + 1];
+        assert_eq!(HeaderAndShortIds::from_block(&large, 0, &[]), Err(Error::TooManyTransactions(MAX_TRANSACTIONS + 1)));
+
+        // more short ids than 16-bit indexes can address
+        let mut bytes = serialize(&block.header);
+        bytes.extend(serialize(&0u64));
+        let mut overflow = bytes.clone();
+        overflow.extend_from_slice(&[0xfe, 0x00, 0x00, 0x01, 0x00]);
+        assert!(deserialize::<HeaderAndShortIds>(&overflow).is_err());
+
+        // a prefilled transaction in the segwit format
+        bytes.extend_from_slice(&[0, 1, 0]);
+        bytes.extend(segwit_tx());
+        match deserialize::<HeaderAndShortIds>(&bytes) {
+            Err(encode::Error::WitnessNotSupported) => {},
+            x => panic!("expected WitnessNotSupported, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn block_transactions_request_test() {
+        let block_hash = BlockHash::hash(&[1, 2, 3]);
+        let request = BlockTransactionsRequest { block_hash: block_hash, indexes: vec![1, 2, 5] };
+        let bytes = serialize(&request);
+        assert_eq!(&bytes[..32], &block_hash[..]);
+        assert_eq!(&bytes[32..], &[3, 1, 0, 2]);
+        assert_eq!(deserialize::<BlockTransactionsRequest>(&bytes).unwrap(), request);
+
+        // indexes must be increasing
+        let request = BlockTransactionsRequest { block_hash: block_hash, indexes: vec![2, 2] };
+        assert!(request.consensus_encode(io::sink()).is_err());
+
+        // and fit in 16 bits
+        let mut bytes = serialize(&block_hash);
+        bytes.extend_from_slice(&[2, 0xfd, 0xff, 0xff, 0]);
+        assert!(deserialize::<BlockTransactionsRequest>(&bytes).is_err());
+    }
+
+    #[test]
+    fn block_transactions_test() {
+        let block = get_block();
+        let response = BlockTransactions { block_hash: block.bitcoin_hash(), transactions: block.txdata.clone() };
+        assert_eq!(deserialize::<BlockTransactions>(&serialize(&response)).unwrap(), response);
+
+        let mut bytes = serialize(&block.bitcoin_hash());
+        bytes.push(1);
+        bytes.extend(segwit_tx());
+        match deserialize::<BlockTransactions>(&bytes) {
+            Err(encode::Error::WitnessNotSupported) => {},
+            x => panic!("expected WitnessNotSupported, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn reconstruct_test() {
+        let mut block = get_block();
+        block.header.merkle_root = block.merkle_root();
+        block.header.im_merkle_root = block.immutable_merkle_root();
+        let compact = HeaderAndShortIds::from_block(&block, 42, &[]).unwrap();
+
+        // all transactions are in the mempool
+        let partial = PartialBlock::new(&compact, &block.txdata).unwrap();
+        assert!(partial.missing().is_empty());
+        assert_eq!(partial.into_block().unwrap(), block);
+
+        // the transactions missing from the mempool are requested
+        let partial = PartialBlock::new(&compact, &block.txdata[..1]).unwrap();
+        assert_eq!(partial.missing(), vec![1]);
+        assert_eq!(partial.request(), BlockTransactionsRequest { block_hash: block.bitcoin_hash(), indexes: vec![1] });
+        assert_eq!(partial.clone().into_block(), Err(Error::TransactionCountMismatch { expected: 1, actual: 0 }));
+
+        let response = BlockTransactions { block_hash: block.bitcoin_hash(), transactions: vec![block.txdata[1].clone()] };
+        assert_eq!(partial.clone().fill(response).unwrap(), block);
+
+        let response = BlockTransactions { block_hash: BlockHash::default(), transactions: vec![block.txdata[1].clone()] };
+        assert_eq!(partial.clone().fill(response), Err(Error::UnexpectedBlock(BlockHash::default())));
+
+        let response = BlockTransactions { block_hash: block.bitcoin_hash(), transactions: vec![block.txdata[0].clone()] };
+        assert_eq!(partial.fill(response), Err(Error::MerkleRootMismatch));
+
+        // invalid compact blocks
+        let mut invalid = compact.clone();
+        invalid.short_ids.push(compact.short_ids[0]);
+        assert_eq!(PartialBlock::new(&invalid, &block.txdata).unwrap_err(), Error::ShortIdCollision);
+
+        let mut invalid = compact.clone();
+        invalid.prefilled_txs[0].index = 2;
+        assert_eq!(PartialBlock::new(&invalid, &block.txdata).unwrap_err(), Error::InvalidPrefilledIndex(2));
+
+        let mut invalid = compact.clone();
+        invalid.short_ids.clear();
+        invalid.prefilled_txs.clear();
+        assert_eq!(PartialBlock::new(&invalid, &block.txdata).unwrap_err(), Error::EmptyBlock);
+
+        let mut invalid = compact.clone();
+        invalid.short_ids = vec![compact.short_ids[0]; MAX_TRANSACTIONS];
+        assert_eq!(PartialBlock::new(&invalid, &block.txdata).unwrap_err(), Error::TooManyTransactions(MAX_TRANSACTIONS + 1));
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! BIP37 Bloom filters
+//!
+//! A probabilistic set of data elements which a light client loads into its
+//! peer with a `filterload` message, so that the peer only relays the
+//! transactions matching the filter, and merkle blocks proving them.
+//!
+//! A transaction matches if its immutable id, a data push of one of its
+//! output scripts, an outpoint it spends or a data push of one of its input
+//! scripts is in the filter. As outpoints refer to transactions by their
+//! immutable id, so does the filter.
+//!
+
+use std::{cmp, error, fmt};
+
+use blockdata::script::{Instruction, Script};
+use blockdata::transaction::{OutPoint, Transaction};
+use consensus::encode::serialize;
+use network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+use util::endian;
+
+/// The maximum size of a filter, in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// The maximum number of hash functions of a filter
+pub const MAX_HASH_FUNCS: u32 = 50;
+/// The maximum size of a data element added with a `filteradd` message
+pub const MAX_FILTER_ADD_SIZE: usize = 520;
+
+/// An error in applying a `filterload` or `filteradd` message of a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The filter is larger than `MAX_BLOOM_FILTER_SIZE` or has more than `MAX_HASH_FUNCS` hash functions
+    ExceedsSizeConstraints {
+        /// The size of the filter, in bytes
+        size: usize,
+        /// The number of hash functions
+        hash_funcs: u32,
+    },
+    /// The data element is larger than `MAX_FILTER_ADD_SIZE`
+    OversizedElement(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ExceedsSizeConstraints { size, hash_funcs } => write!(f,
+                "filter exceeds size constraints: {} bytes, {} hash functions", size, hash_funcs),
+            Error::OversizedElement(size) => write!(f, "oversized data element: {} bytes", size),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::ExceedsSizeConstraints { .. } => "filter exceeds size constraints",
+            Error::OversizedElement(_) => "oversized data element",
+        }
+    }
+}
+
+const LN2SQUARED: f64 = 0.480_453_013_918_201_4;
+const LN2: f64 = 0.693_147_180_559_945_3;
+
+/// MurmurHash3 (x86, 32-bit) of `data`
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut h1 = seed;
+    for chunk in data.chunks(4) {
+        let mut k1 = if chunk.len() == 4 {
+            endian::slice_to_u32_le(chunk)
+        } else {
+            chunk.iter().rev().fold(0, |k, &b| k << 8 | b as u32)
+        };
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        if chunk.len() == 4 {
+            h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+        }
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85eb_ca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2_ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+/// A BIP37 Bloom filter
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: BloomFlags,
+    /// Whether all bits are set, so that everything matches
+    full: bool,
+    /// Whether no bit is set, so that nothing matches
+    empty: bool,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized for `elements` elements with a false
+    /// positive rate of `fp_rate`, within the size limits of BIP37. `tweak`
+    /// should be random.
+    pub fn new(elements: u32, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let elements = cmp::max(elements, 1);
+        let bits = (-1.0 / LN2SQUARED * elements as f64 * fp_rate.ln()) as usize;
+        let size = cmp::min(bits, MAX_BLOOM_FILTER_SIZE * 8) / 8;
+        let hash_funcs = ((size * 8 / elements as usize) as f64 * LN2) as u32;
+        BloomFilter {
+            data: vec![0; size],
+            hash_funcs: cmp::min(hash_funcs, MAX_HASH_FUNCS),
+            tweak: tweak,
+            flags: flags,
+            full: false,
+            empty: true,
+        }
+    }
+
+    /// Load the filter of a peer's `filterload` message. As in Bitcoin Core, a
+    /// filter which is not within the size constraints of BIP37 is rejected.
+    pub fn from_filter_load(message: FilterLoad) -> Result<BloomFilter, Error> {
+        let full = message.filter.iter().all(|&b| b == 0xff);
+        let empty = message.filter.iter().all(|&b| b == 0);
+        let filter = BloomFilter {
+            data: message.filter,
+            hash_funcs: message.hash_funcs,
+            tweak: message.tweak,
+            flags: message.flags,
+            full: full,
+            empty: empty,
+        };
+        if !filter.is_within_size_constraints() {
+            return Err(Error::ExceedsSizeConstraints { size: filter.data.len(), hash_funcs: filter.hash_funcs });
+        }
+        Ok(filter)
+    }
+
+    /// Whether the size and number of hash functions are within the limits of BIP37
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.data.len() <= MAX_BLOOM_FILTER_SIZE && self.hash_funcs <= MAX_HASH_FUNCS
+    }
+
+    /// The `filterload` message to load this filter into a peer
+    pub fn filter_load(&self) -> FilterLoad {
+        FilterLoad {
+            filter: self.data.clone(),
+            hash_funcs: self.hash_funcs,
+            tweak: self.tweak,
+            flags: self.flags,
+        }
+    }
+
+    fn bit_index(&self, n: u32, data: &[u8]) -> usize {
+        let seed = n.wrapping_mul(0xfba4_c795).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.data.len() * 8)
+    }
+
+    /// Add a data element to the filter
+    pub fn insert(&mut self, data: &[u8]) {
+        if self.full || self.data.is_empty() {
+            return;
+        }
+        for n in 0..self.hash_funcs {
+            let index = self.bit_index(n, data);
+            self.data[index >> 3] |= 1 << (index & 7);
+        }
+        self.empty = false;
+    }
+
+    /// Add the data element of a peer's `filteradd` message, which may not
+    /// be larger than `MAX_FILTER_ADD_SIZE`
+    pub fn filter_add(&mut self, message: &FilterAdd) -> Result<(), Error> {
+        if message.data.len() > MAX_FILTER_ADD_SIZE {
+            return Err(Error::OversizedElement(message.data.len()));
+        }
+        self.insert(&message.data);
+        Ok(())
+    }
+
+    /// Whether a data element may be in the filter
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.full {
+            return true;
+        }
+        if self.empty {
+            return false;
+        }
+        (0..self.hash_funcs).all(|n| {
+            let index = self.bit_index(n, data);
+            self.data[index >> 3] & (1 << (index & 7)) != 0
+        })
+    }
+
+    /// Add an outpoint to the filter
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize(outpoint));
+    }
+
+    /// Whether an outpoint may be in the filter
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+
+    /// Whether the transaction matches the filter. When an output matches,
+    /// its outpoint is added to the filter according to the update flags, so
+    /// that transactions spending it match too.
+    pub fn is_relevant_and_update(&mut self, tx: &Transaction) -> bool {
+        if self.full {
+            return true;
+        }
+        if self.empty {
+            return false;
+        }
+
+        let malfix_txid = tx.malfix_txid();
+        let mut found = self.contains(&malfix_txid[..]);
+        for (vout, output) in tx.output.iter().enumerate() {
+            if self.contains_push(&output.script_pubkey) {
+                found = true;
+                let update = match self.flags {
+                    BloomFlags::None => false,
+                    BloomFlags::All => true,
+                    BloomFlags::PubkeyOnly => output.script_pubkey.is_p2pk() || output.script_pubkey.is_multisig(),
+                };
+                if update {
+                    self.insert_outpoint(&OutPoint::new(malfix_txid, vout as u32));
+                }
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.input.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output) || self.contains_push(&input.script_sig)
+        })
+    }
+
+    /// Whether a data push of the script is in the filter
+    fn contains_push(&self, script: &Script) -> bool {
+        script.iter(false).any(|instruction| match instruction {
+            Instruction::PushBytes(data) => !data.is_empty() && self.contains(data),
+            _ => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex::decode as hex_decode;
+
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+    use util::bloom::{murmur3, BloomFilter, Error};
+
+    #[test]
+    fn murmur3_test() {
+        // Test vectors from Bitcoin Core
+        let vectors: &[(u32, u32, &str)] = &[
+            (0x00000000, 0x00000000, ""),
+            (0x6a396f08, 0xfba4c795, ""),
+            (0x81f16f39, 0xffffffff, ""),
+            (0x514e28b7, 0x00000000, "00"),
+            (0xea3f0b17, 0xfba4c795, "00"),
+            (0x16c6b7ab, 0x00000000, "0011"),
+            (0x8eb51c3d, 0x00000000, "001122"),
+            (0xb4471bf8, 0x00000000, "00112233"),
+            (0xe2301fa8, 0x00000000, "0011223344"),
+            (0xb074502c, 0x00000000, "00112233445566"),
+            (0x8034d2a0, 0x00000000, "0011223344556677"),
+        ];
+        for &(expected, seed, data) in vectors {
+            assert_eq!(murmur3(seed, &hex_decode(data).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn bloom_filter_test() {
+        // Test vectors from Bitcoin Core
+        for &(tweak, expected) in &[(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")] {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::All);
+            let element = hex_decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+            assert!(!filter.contains(&element));
+            filter.insert(&element);
+            assert!(filter.contains(&element));
+            assert!(!filter.contains(&hex_decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap()));
+            filter.insert(&hex_decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+            filter.insert(&hex_decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+            assert!(filter.is_within_size_constraints());
+
+            let filter_load = filter.filter_load();
+            assert_eq!(serialize(&filter_load), hex_decode(expected).unwrap());
+            assert_eq!(BloomFilter::from_filter_load(filter_load).unwrap(), filter);
+        }
+
+        let full = BloomFilter::from_filter_load(FilterLoad { filter: vec![0xff], hash_funcs: 1, tweak: 0, flags: BloomFlags::None }).unwrap();
+        assert!(full.contains(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn peer_filter_test() {
+        let load = |size, hash_funcs| BloomFilter::from_filter_load(FilterLoad {
+            filter: vec![0; size],
+            hash_funcs: hash_funcs,
+            tweak: 0,
+            flags: BloomFlags::None,
+        });
+        assert!(load(36000, 50).is_ok());
+        assert_eq!(load(36001, 1), Err(Error::ExceedsSizeConstraints { size: 36001, hash_funcs: 1 }));
+        assert_eq!(load(1, 0xffffffff), Err(Error::ExceedsSizeConstraints { size: 1, hash_funcs: 0xffffffff }));
+
+        let mut filter = load(100, 10).unwrap();
+        let element = FilterAdd { data: vec![1; 520] };
+        filter.filter_add(&element).unwrap();
+        assert!(filter.contains(&element.data));
+        let oversized = FilterAdd { data: vec![2; 521] };
+        assert_eq!(filter.filter_add(&oversized), Err(Error::OversizedElement(521)));
+        assert!(!filter.contains(&oversized.data));
+    }
+
+    #[test]
+    fn relevant_transaction_test() {
+        let tx: Transaction = deserialize(&hex_decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        let spending = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(tx.malfix_txid(), 0),
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 1000, script_pubkey: Script::new() }],
+        };
+        let filter = || BloomFilter::new(10, 0.000001, 0, BloomFlags::All);
+
+        // the immutable id
+        let mut f = filter();
+        f.insert(&tx.malfix_txid()[..]);
+        assert!(f.is_relevant_and_update(&tx));
+        let mut f = filter();
+        f.insert(&tx.txid()[..]);
+        assert!(!f.is_relevant_and_update(&tx));
+
+        // the pubkey hash of the output, whose outpoint is added to the filter
+        let pubkey_hash = hex_decode("0389035a9225b3839e2bbf32d826a1e222031fd8").unwrap();
+        let mut f = filter();
+        f.insert(&pubkey_hash);
+        assert!(!f.is_relevant_and_update(&spending));
+        assert!(f.is_relevant_and_update(&tx));
+        assert!(f.contains_outpoint(&OutPoint::new(tx.malfix_txid(), 0)));
+        assert!(f.is_relevant_and_update(&spending));
+
+        // the output is not pay-to-pubkey
+        for &flags in &[BloomFlags::None, BloomFlags::PubkeyOnly] {
+            let mut f = BloomFilter::new(10, 0.000001, 0, flags);
+            f.insert(&pubkey_hash);
+            assert!(f.is_relevant_and_update(&tx));
+            assert!(!f.is_relevant_and_update(&spending));
+        }
+
+        // the spent outpoint and the pubkey of the input
+        let mut f = filter();
+        f.insert_outpoint(&tx.input[0].previous_output);
+        assert!(f.is_relevant_and_update(&tx));
+        let mut f = filter();
+        f.insert(&hex_decode("033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52").unwrap());
+        assert!(f.is_relevant_and_update(&tx));
+
+        let mut f = filter();
+        f.insert(&hex_decode("00").unwrap());
+        assert!(!f.is_relevant_and_update(&tx));
+    }
+}
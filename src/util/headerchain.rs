@@ -0,0 +1,477 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Header chain
+//!
+//! Validation of a chain of block headers, such as those received in a
+//! `headers` message. Each header must extend the current tip and carry a
+//! proof signed by the aggregated public key of the federation which is
+//! active at its height. A header carrying a new aggregated public key
+//! rotates the active key from the next block on.
+//!
+
+use std::collections::HashMap;
+use std::{error, fmt};
+
+use blockdata::block::{BlockHeader, ProofError};
+use hash_types::BlockHash;
+use util::hash::BitcoinHash;
+use util::key::PublicKey;
+
+/// An error in connecting a header to the chain
+#[derive(Debug)]
+pub enum Error {
+    /// The genesis header has no aggregated public key
+    NoAggregatedPublicKey,
+    /// The checkpoint is at the maximum height, so that no header can follow it
+    HeightOverflow,
+    /// The header does not extend the tip of the chain
+    PrevBlockHashMismatch {
+        /// The tip of the chain
+        expected: BlockHash,
+        /// The `prev_blockhash` of the header
+        actual: BlockHash,
+    },
+    /// The proof of the header could not be verified against the active aggregated public key
+    InvalidProof {
+        /// The hash of the header
+        hash: BlockHash,
+        /// The reason of the failure
+        error: ProofError,
+    },
+    /// The appended chain starts with another aggregated public key than the active one
+    AggregatedPublicKeyMismatch {
+        /// The active aggregated public key of the chain
+        expected: PublicKey,
+        /// The first aggregated public key of the appended chain
+        actual: PublicKey,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoAggregatedPublicKey | Error::HeightOverflow => f.write_str(error::Error::description(self)),
+            Error::PrevBlockHashMismatch { ref expected, ref actual } => write!(f,
+                "header does not extend the tip: expected prev_blockhash {}, actual {}", expected, actual),
+            Error::InvalidProof { ref hash, ref error } => write!(f,
+                "invalid proof for block {}: {}", hash, error),
+            Error::AggregatedPublicKeyMismatch { ref expected, ref actual } => write!(f,
+                "aggregated public key mismatch: expected {}, actual {}", expected, actual),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::InvalidProof { ref error, .. } => Some(error),
+            Error::NoAggregatedPublicKey
+            | Error::HeightOverflow
+            | Error::PrevBlockHashMismatch { .. }
+            | Error::AggregatedPublicKeyMismatch { .. } => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::NoAggregatedPublicKey => "genesis header has no aggregated public key",
+            Error::HeightOverflow => "checkpoint height overflow",
+            Error::PrevBlockHashMismatch { .. } => "header does not extend the tip",
+            Error::InvalidProof { .. } => "invalid block proof",
+            Error::AggregatedPublicKeyMismatch { .. } => "aggregated public key mismatch",
+        }
+    }
+}
+
+/// A chain of validated block headers
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    /// The height of the first header in `headers`
+    start_height: u32,
+    /// The headers connected to the chain, in order
+    headers: Vec<BlockHeader>,
+    /// Height of each connected header, indexed by block hash
+    heights: HashMap<BlockHash, u32>,
+    /// The hash of the tip of the chain
+    tip: BlockHash,
+    /// Aggregated public keys with the first height from which each is active
+    aggregated_public_keys: Vec<(u32, PublicKey)>,
+}
+
+impl HeaderChain {
+    /// Create a chain starting from the genesis header. The genesis header is
+    /// trusted as-is, and its aggregated public key is the first active key.
+    pub fn new(genesis: BlockHeader) -> Result<HeaderChain, Error> {
+        let aggregated_public_key = match genesis.xfield.aggregated_public_key() {
+            Some(pk) => *pk,
+            None => return Err(Error::NoAggregatedPublicKey),
+        };
+        let hash = genesis.bitcoin_hash();
+        let mut heights = HashMap::new();
+        heights.insert(hash, 0);
+
+        Ok(HeaderChain {
+            start_height: 0,
+            headers: vec![genesis],
+            heights: heights,
+            tip: hash,
+            aggregated_public_keys: vec![(0, aggregated_public_key)],
+        })
+    }
+
+    /// Create a chain starting from a trusted checkpoint. Headers connected to
+    /// it are verified against `aggregated_public_key` until the key is rotated.
+    /// Fails if `height` is `u32::MAX`, as no header could be connected.
+    pub fn from_checkpoint(hash: BlockHash, height: u32, aggregated_public_key: PublicKey) -> Result<HeaderChain, Error> {
+        let start_height = match height.checked_add(1) {
+            Some(h) => h,
+            None => return Err(Error::HeightOverflow),
+        };
+        Ok(HeaderChain {
+            start_height: start_height,
+            headers: vec![],
+            heights: HashMap::new(),
+            tip: hash,
+            aggregated_public_keys: vec![(start_height, aggregated_public_key)],
+        })
+    }
+
+    /// Validate a header and connect it to the tip of the chain
+    pub fn connect(&mut self, header: BlockHeader) -> Result<(), Error> {
+        if header.prev_blockhash != self.tip {
+            return Err(Error::PrevBlockHashMismatch {
+                expected: self.tip,
+                actual: header.prev_blockhash,
+            });
+        }
+
+        let hash = header.bitcoin_hash();
+        if let Err(e) = header.verify_proof(self.aggregated_public_key()) {
+            return Err(Error::InvalidProof { hash: hash, error: e });
+        }
+
+        let height = self.height() + 1;
+        if let Some(pk) = header.xfield.aggregated_public_key() {
+            if pk != self.aggregated_public_key() {
+                self.aggregated_public_keys.push((height + 1, *pk));
+            }
+        }
+
+        self.heights.insert(hash, height);
+        self.headers.push(header);
+        self.tip = hash;
+        Ok(())
+    }
+
+    /// Validate and connect a sequence of headers, e.g. the content of a
+    /// `headers` message. Headers are connected in order until the first one
+    /// which fails validation; those before it stay connected.
+    pub fn connect_headers<I>(&mut self, headers: I) -> Result<(), Error>
+        where I: IntoIterator<Item = BlockHeader>
+    {
+        for header in headers {
+            self.connect(header)?;
+        }
+        Ok(())
+    }
+
+    /// Connect the headers of `chain`, which must start from a checkpoint at
+    /// the tip of this chain, e.g. a fork built with `from_checkpoint`. Its
+    /// headers were validated when connected to it and are not verified again.
+    pub fn append(&mut self, chain: HeaderChain) -> Result<(), Error> {
+        let base = chain.start_height.checked_sub(1).and_then(|height| chain.hash_at(height));
+        if chain.start_height != self.height() + 1 || base != Some(self.tip) {
+            return Err(Error::PrevBlockHashMismatch {
+                expected: self.tip,
+                actual: base.unwrap_or_default(),
+            });
+        }
+        // The headers of `chain` were verified against its first key, which
+        // must be the one this chain would verify them against
+        let first = chain.aggregated_public_keys[0].1;
+        if first != *self.aggregated_public_key() {
+            return Err(Error::AggregatedPublicKeyMismatch {
+                expected: *self.aggregated_public_key(),
+                actual: first,
+            });
+        }
+
+        for (start, pk) in chain.aggregated_public_keys {
+            if pk != *self.aggregated_public_key() {
+                self.aggregated_public_keys.push((start, pk));
+            }
+        }
+        self.heights.extend(chain.heights);
+        self.headers.extend(chain.headers);
+        self.tip = chain.tip;
+        Ok(())
+    }
+
+    /// The hash of the tip of the chain
+    pub fn tip(&self) -> BlockHash {
+        self.tip
+    }
+
+    /// The height of the tip of the chain
+    pub fn height(&self) -> u32 {
+        self.start_height + self.headers.len() as u32 - 1
+    }
+
+    /// The aggregated public key which must sign the next block
+    pub fn aggregated_public_key(&self) -> &PublicKey {
+        &self.aggregated_public_keys.last().expect("at least one key").1
+    }
+
+    /// The aggregated public key which must sign the block at the given height,
+    /// or `None` if the height is below the start of the chain.
+    pub fn aggregated_public_key_at(&self, height: u32) -> Option<&PublicKey> {
+        self.aggregated_public_keys.iter()
+            .rev()
+            .find(|&&(start, _)| start <= height)
+            .map(|&(_, ref pk)| pk)
+    }
+
+    /// The connected header with the given hash
+    pub fn get_header(&self, hash: &BlockHash) -> Option<&BlockHeader> {
+        self.heights.get(hash).and_then(|&height| self.header_at(height))
+    }
+
+    /// The height of the connected header with the given hash
+    pub fn get_height(&self, hash: &BlockHash) -> Option<u32> {
+        self.heights.get(hash).cloned()
+    }
+
+    /// The connected header at the given height
+    pub fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+        if height < self.start_height {
+            return None;
+        }
+        self.headers.get((height - self.start_height) as usize)
+    }
+
+    /// The hash of the block at the given height, which may be the checkpoint
+    /// the chain starts from
+    pub fn hash_at(&self, height: u32) -> Option<BlockHash> {
+        if height == self.height() {
+            Some(self.tip)
+        } else if height < self.height() {
+            self.header_at(height + 1).map(|header| header.prev_blockhash)
+        } else {
+            None
+        }
+    }
+
+    /// Block locator of the tip of the chain, to be sent in a `getheaders`
+    /// message. Hashes are ordered from the tip back to the start of the
+    /// chain; the first ten are consecutive, then the step doubles.
+    pub fn locator_hashes(&self) -> Vec<BlockHash> {
+        let lowest = self.start_height.saturating_sub(1);
+        let mut hashes = vec![];
+        let mut height = self.height();
+        let mut step = 1;
+        loop {
+            hashes.push(self.hash_at(height).expect("height within the chain"));
+            if height == lowest {
+                return hashes;
+            }
+            if hashes.len() > 10 {
+                step *= 2;
+            }
+            height = if height - lowest > step { height - step } else { lowest };
+        }
+    }
+
+    /// Disconnect the headers above `height`, e.g. to switch to a fork which
+    /// branches off at that height, and return them in order. Returns `None`
+    /// if `height` is below the start of the chain.
+    pub fn truncate(&mut self, height: u32) -> Option<Vec<BlockHeader>> {
+        if height >= self.height() {
+            return Some(vec![]);
+        }
+        if height + 1 < self.start_height {
+            return None;
+        }
+        self.tip = self.hash_at(height).expect("height within the chain");
+        let disconnected = self.headers.split_off((height + 1 - self.start_height) as usize);
+        for header in &disconnected {
+            self.heights.remove(&header.bitcoin_hash());
+        }
+        self.aggregated_public_keys.retain(|&(start, _)| start <= height + 1);
+        Some(disconnected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+
+    use blockdata::block::{BlockHeader, ProofError, XField};
+    use hash_types::{BlockHash, TxMerkleNode};
+    use util::hash::BitcoinHash;
+    use util::headerchain::{Error, HeaderChain};
+    use util::key::{PrivateKey, PublicKey};
+    use util::signature;
+
+    fn signed_header(prev: &BlockHeader, time: u32, key: &PrivateKey, xfield: XField) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 1,
+            prev_blockhash: prev.bitcoin_hash(),
+            merkle_root: TxMerkleNode::default(),
+            im_merkle_root: TxMerkleNode::default(),
+            time: time,
+            xfield: xfield,
+            proof: None,
+        };
+        header.sign(key).unwrap();
+        header
+    }
+
+    #[test]
+    fn header_chain_test() {
+        let secp = Secp256k1::new();
+        let key1 = PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        let key2 = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pk1 = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        let pk2 = key2.public_key(&secp);
+
+        let genesis = BlockHeader {
+            version: 1,
+            prev_blockhash: BlockHash::default(),
+            merkle_root: TxMerkleNode::default(),
+            im_merkle_root: TxMerkleNode::default(),
+            time: 1000,
+            xfield: XField::AggregatePublicKey(pk1),
+            proof: None,
+        };
+        let mut chain = HeaderChain::new(genesis.clone()).unwrap();
+        assert_eq!(chain.height(), 0);
+        assert_eq!(chain.tip(), genesis.bitcoin_hash());
+
+        // block 2 rotates the key, so block 3 must be signed by key2
+        let h1 = signed_header(&genesis, 1001, &key1, XField::None);
+        let h2 = signed_header(&h1, 1002, &key1, XField::AggregatePublicKey(pk2));
+        let h3 = signed_header(&h2, 1003, &key2, XField::None);
+        chain.connect_headers(vec![h1.clone(), h2.clone(), h3.clone()]).unwrap();
+
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.tip(), h3.bitcoin_hash());
+        assert_eq!(chain.aggregated_public_key(), &pk2);
+        assert_eq!(chain.aggregated_public_key_at(2), Some(&pk1));
+        assert_eq!(chain.aggregated_public_key_at(3), Some(&pk2));
+        assert_eq!(chain.get_height(&h2.bitcoin_hash()), Some(2));
+        assert_eq!(chain.get_header(&h1.bitcoin_hash()), Some(&h1));
+        assert_eq!(chain.header_at(0), Some(&genesis));
+
+        // signed by the rotated-out key
+        let h4 = signed_header(&h3, 1004, &key1, XField::None);
+        match chain.connect(h4) {
+            Err(Error::InvalidProof { error: ProofError::BadSignature(signature::Error::InvalidSignature), .. }) => {},
+            x => panic!("expected InvalidProof, got {:?}", x),
+        }
+
+        // not extending the tip
+        let h4 = signed_header(&h2, 1004, &key2, XField::None);
+        match chain.connect(h4) {
+            Err(Error::PrevBlockHashMismatch { .. }) => {},
+            x => panic!("expected PrevBlockHashMismatch, got {:?}", x),
+        }
+
+        // unsigned
+        let mut h4 = signed_header(&h3, 1004, &key2, XField::None);
+        h4.proof = None;
+        match chain.connect(h4) {
+            Err(Error::InvalidProof { error: ProofError::MissingProof, .. }) => {},
+            x => panic!("expected InvalidProof, got {:?}", x),
+        }
+        assert_eq!(chain.height(), 3);
+
+        // a chain starting from a checkpoint
+        let mut chain = HeaderChain::from_checkpoint(h2.bitcoin_hash(), 2, pk2).unwrap();
+        assert_eq!(chain.aggregated_public_key_at(2), None);
+        chain.connect(h3.clone()).unwrap();
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.header_at(2), None);
+        assert_eq!(chain.header_at(3), Some(&h3));
+        assert_eq!(chain.hash_at(2), Some(h2.bitcoin_hash()));
+        assert_eq!(chain.truncate(1), None);
+        assert_eq!(chain.truncate(2), Some(vec![h3.clone()]));
+        assert_eq!(chain.tip(), h2.bitcoin_hash());
+        assert_eq!(chain.height(), 2);
+        assert_eq!(chain.locator_hashes(), vec![h2.bitcoin_hash()]);
+        chain.connect(h3.clone()).unwrap();
+        match HeaderChain::from_checkpoint(h2.bitcoin_hash(), u32::max_value(), pk2) {
+            Err(Error::HeightOverflow) => {},
+            x => panic!("expected HeightOverflow, got {:?}", x),
+        }
+
+        // disconnecting the header which rotated the key
+        let mut chain = HeaderChain::new(genesis.clone()).unwrap();
+        chain.connect_headers(vec![h1.clone(), h2.clone(), h3.clone()]).unwrap();
+        assert_eq!(chain.truncate(3), Some(vec![]));
+        assert_eq!(chain.truncate(1), Some(vec![h2.clone(), h3.clone()]));
+        assert_eq!(chain.tip(), h1.bitcoin_hash());
+        assert_eq!(chain.height(), 1);
+        assert_eq!(chain.get_height(&h2.bitcoin_hash()), None);
+        assert_eq!(chain.aggregated_public_key(), &pk1);
+        chain.connect_headers(vec![h2.clone(), h3.clone()]).unwrap();
+        assert_eq!(chain.aggregated_public_key(), &pk2);
+
+        // appending a branch built from a checkpoint
+        let mut chain = HeaderChain::new(genesis.clone()).unwrap();
+        chain.connect(h1.clone()).unwrap();
+        let mut branch = HeaderChain::from_checkpoint(h1.bitcoin_hash(), 1, pk1).unwrap();
+        branch.connect_headers(vec![h2.clone(), h3.clone()]).unwrap();
+        let mut other = HeaderChain::from_checkpoint(h2.bitcoin_hash(), 2, pk2).unwrap();
+        other.connect(h3.clone()).unwrap();
+        match chain.append(other) {
+            Err(Error::PrevBlockHashMismatch { .. }) => {},
+            x => panic!("expected PrevBlockHashMismatch, got {:?}", x),
+        }
+        let mut wrong_key = HeaderChain::from_checkpoint(h1.bitcoin_hash(), 1, pk2).unwrap();
+        wrong_key.connect(signed_header(&h1, 1002, &key2, XField::None)).unwrap();
+        match chain.append(wrong_key) {
+            Err(Error::AggregatedPublicKeyMismatch { ref expected, ref actual }) if *expected == pk1 && *actual == pk2 => {},
+            x => panic!("expected AggregatedPublicKeyMismatch, got {:?}", x),
+        }
+        assert_eq!(chain.height(), 1);
+        chain.append(branch).unwrap();
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.tip(), h3.bitcoin_hash());
+        assert_eq!(chain.get_height(&h2.bitcoin_hash()), Some(2));
+        assert_eq!(chain.header_at(3), Some(&h3));
+        assert_eq!(chain.aggregated_public_key_at(2), Some(&pk1));
+        assert_eq!(chain.aggregated_public_key(), &pk2);
+    }
+
+    #[test]
+    fn locator_hashes_test() {
+        let key = PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        let pk = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        let genesis = BlockHeader {
+            version: 1,
+            prev_blockhash: BlockHash::default(),
+            merkle_root: TxMerkleNode::default(),
+            im_merkle_root: TxMerkleNode::default(),
+            time: 1000,
+            xfield: XField::AggregatePublicKey(pk),
+            proof: None,
+        };
+        let mut chain = HeaderChain::new(genesis.clone()).unwrap();
+        assert_eq!(chain.locator_hashes(), vec![genesis.bitcoin_hash()]);
+
+        let mut headers = vec![genesis];
+        for i in 1..31 {
+            let header = signed_header(&headers[i - 1], 1000 + i as u32, &key, XField::None);
+            headers.push(header);
+        }
+        chain.connect_headers(headers[1..].iter().cloned()).unwrap();
+
+        let heights = [30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 18, 14, 6, 0];
+        let expected: Vec<BlockHash> = heights.iter().map(|&h| headers[h].bitcoin_hash()).collect();
+        assert_eq!(chain.locator_hashes(), expected);
+    }
+}
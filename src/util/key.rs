@@ -193,7 +193,7 @@ impl PrivateKey {
         let mut ret = [0; 34];
         ret[0] = match self.network {
             Network::Bitcoin | Network::Paradium => 128,
-            Network::Testnet | Network::Regtest => 239,
+            Network::Testnet | Network::Regtest | Network::Custom(_) => 239,
         };
         ret[1..33].copy_from_slice(&self.key[..]);
         let privkey = if self.compressed {
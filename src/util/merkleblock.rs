@@ -65,14 +65,15 @@
 //! ```
 
 use std::collections::HashSet;
-use std::io;
+use std::{error, fmt, io};
 
 use hashes::Hash;
-use hash_types::{Txid, TxMerkleNode};
+use hash_types::{MalFixTxid, Txid, TxMerkleNode};
 
 use blockdata::transaction::Transaction;
 use blockdata::constants::{MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT};
 use consensus::encode::{self, Decodable, Encodable};
+use util::bloom::BloomFilter;
 use util::merkleblock::MerkleBlockError::*;
 use {Block, BlockHeader};
 
@@ -81,6 +82,11 @@ use {Block, BlockHeader};
 pub enum MerkleBlockError {
     /// When header merkle root don't match to the root calculated from the partial merkle tree
     MerkleRootMismatch,
+    /// When header immutable merkle root don't match to the root calculated from the partial
+    /// merkle tree of immutable txids
+    ImMerkleRootMismatch,
+    /// When the partial merkle trees of txids and immutable txids match different transactions
+    MatchesMismatch,
     /// When partial merkle tree contains no transactions
     NoTransactions,
     /// When there are too many transactions
@@ -89,6 +95,30 @@ pub enum MerkleBlockError {
     BadFormat(String),
 }
 
+impl fmt::Display for MerkleBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BadFormat(ref s) => write!(f, "bad format: {}", s),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for MerkleBlockError {
+    fn cause(&self) -> Option<&error::Error> { None }
+
+    fn description(&self) -> &str {
+        match *self {
+            MerkleRootMismatch => "merkle root does not match the header",
+            ImMerkleRootMismatch => "immutable merkle root does not match the header",
+            MatchesMismatch => "partial merkle trees match different transactions",
+            NoTransactions => "partial merkle tree has no transactions",
+            TooManyTransactions => "partial merkle tree has too many transactions",
+            BadFormat(_) => "bad format",
+        }
+    }
+}
+
 /// Data structure that represents a partial merkle tree.
 ///
 /// It represents a subset of the txid's of a known block, in a way that
@@ -164,13 +194,32 @@ impl PartialMerkleTree {
     /// # }
     /// ```
     pub fn from_txids(txids: &[Txid], matches: &[bool]) -> Self {
+        let leaves: Vec<TxMerkleNode> = txids.iter()
+            .map(|txid| TxMerkleNode::from_inner(txid.into_inner()))
+            .collect();
+        PartialMerkleTree::from_leaves(&leaves, matches)
+    }
+
+    /// Construct a partial merkle tree over the immutable txids of the block,
+    /// whose root is the `im_merkle_root` of the block header.
+    /// The `matches` is the contains flags wherever a tx hash should be included in the proof.
+    ///
+    /// Panics when `malfix_txids` is empty or when `matches` has a different length
+    pub fn from_malfix_txids(malfix_txids: &[MalFixTxid], matches: &[bool]) -> Self {
+        let leaves: Vec<TxMerkleNode> = malfix_txids.iter()
+            .map(|txid| TxMerkleNode::from_inner(txid.into_inner()))
+            .collect();
+        PartialMerkleTree::from_leaves(&leaves, matches)
+    }
+
+    fn from_leaves(leaves: &[TxMerkleNode], matches: &[bool]) -> Self {
         // We can never have zero txs in a merkle block, we always need the coinbase tx
-        assert_ne!(txids.len(), 0);
-        assert_eq!(txids.len(), matches.len());
+        assert_ne!(leaves.len(), 0);
+        assert_eq!(leaves.len(), matches.len());
 
         let mut pmt = PartialMerkleTree {
-            num_transactions: txids.len() as u32,
-            bits: Vec::with_capacity(txids.len()),
+            num_transactions: leaves.len() as u32,
+            bits: Vec::with_capacity(leaves.len()),
             hashes: vec![],
         };
         // calculate height of tree
@@ -179,7 +228,7 @@ impl PartialMerkleTree {
             height += 1;
         }
         // traverse the partial tree
-        pmt.traverse_and_build(height, 0, txids, matches);
+        pmt.traverse_and_build(height, 0, leaves, matches);
         pmt
     }
 
@@ -190,6 +239,34 @@ impl PartialMerkleTree {
         &self,
         matches: &mut Vec<Txid>,
         indexes: &mut Vec<u32>,
+    ) -> Result<TxMerkleNode, MerkleBlockError> {
+        matches.clear();
+        let mut leaves = vec![];
+        let merkle_root = self.extract_leaves(&mut leaves, indexes)?;
+        matches.extend(leaves.into_iter().map(|leaf| Txid::from_inner(leaf.into_inner())));
+        Ok(merkle_root)
+    }
+
+    /// Extract the matching immutable txid's represented by this partial merkle
+    /// tree, which was built with `from_malfix_txids`, and their respective
+    /// indices within the partial tree.
+    /// returns the immutable merkle root, or error in case of failure
+    pub fn extract_malfix_matches(
+        &self,
+        matches: &mut Vec<MalFixTxid>,
+        indexes: &mut Vec<u32>,
+    ) -> Result<TxMerkleNode, MerkleBlockError> {
+        matches.clear();
+        let mut leaves = vec![];
+        let merkle_root = self.extract_leaves(&mut leaves, indexes)?;
+        matches.extend(leaves.into_iter().map(|leaf| MalFixTxid::from_inner(leaf.into_inner())));
+        Ok(merkle_root)
+    }
+
+    fn extract_leaves(
+        &self,
+        matches: &mut Vec<TxMerkleNode>,
+        indexes: &mut Vec<u32>,
     ) -> Result<TxMerkleNode, MerkleBlockError> {
         matches.clear();
         indexes.clear();
@@ -241,10 +318,10 @@ impl PartialMerkleTree {
     }
 
     /// Calculate the hash of a node in the merkle tree (at leaf level: the txid's themselves)
-    fn calc_hash(&self, height: u32, pos: u32, txids: &[Txid]) -> TxMerkleNode {
+    fn calc_hash(&self, height: u32, pos: u32, txids: &[TxMerkleNode]) -> TxMerkleNode {
         if height == 0 {
             // Hash at height 0 is the txid itself
-            TxMerkleNode::from_inner(txids[pos as usize].into_inner())
+            txids[pos as usize]
         } else {
             // Calculate left hash
             let left = self.calc_hash(height - 1, pos * 2, txids);
@@ -264,7 +341,7 @@ impl PartialMerkleTree {
         &mut self,
         height: u32,
         pos: u32,
-        txids: &[Txid],
+        txids: &[TxMerkleNode],
         matches: &[bool],
     ) {
         // Determine whether this node is the parent of at least one matched txid
@@ -280,7 +357,7 @@ impl PartialMerkleTree {
         if height == 0 || !parent_of_match {
             // If at height 0, or nothing interesting below, store hash and stop
             let hash = self.calc_hash(height, pos, txids);
-            self.hashes.push(hash.into());
+            self.hashes.push(hash);
         } else {
             // Otherwise, don't store any hash, but descend into the subtrees
             self.traverse_and_build(height - 1, pos * 2, txids, matches);
@@ -298,7 +375,7 @@ impl PartialMerkleTree {
         pos: u32,
         bits_used: &mut u32,
         hash_used: &mut u32,
-        matches: &mut Vec<Txid>,
+        matches: &mut Vec<TxMerkleNode>,
         indexes: &mut Vec<u32>,
     ) -> Result<TxMerkleNode, MerkleBlockError> {
         if *bits_used as usize >= self.bits.len() {
@@ -315,7 +392,7 @@ impl PartialMerkleTree {
             *hash_used += 1;
             if height == 0 && parent_of_match {
                 // in case of height 0, we have a matched txid
-                matches.push(Txid::from_inner(hash.into_inner()));
+                matches.push(hash);
                 indexes.push(pos);
             }
             Ok(hash)
@@ -449,13 +526,38 @@ impl MerkleBlock {
     /// # }
     /// ```
     pub fn from_block(block: &Block, match_txids: &HashSet<Txid>) -> Self {
+        MerkleBlock::from_block_matching(block, |_, txid| match_txids.contains(txid))
+    }
+
+    /// Create a MerkleBlock from a block, that should contain proofs for the
+    /// transactions whose immutable ids are in `match_malfix_txids`.
+    ///
+    /// The partial merkle tree is still built over the txids, so that it
+    /// authenticates against `header.merkle_root`, and `extract_matches`
+    /// returns the txids of the matched transactions.
+    pub fn from_block_with_malfix_txids(block: &Block, match_malfix_txids: &HashSet<MalFixTxid>) -> Self {
+        MerkleBlock::from_block_matching(block, |tx, _| match_malfix_txids.contains(&tx.malfix_txid()))
+    }
+
+    /// Create a MerkleBlock from a block, that should contain proofs for the
+    /// transactions matching a BIP37 Bloom filter, as sent to a peer which
+    /// loaded the filter. The filter is updated with the outpoints of matched
+    /// outputs according to its flags.
+    pub fn from_block_with_filter(block: &Block, filter: &mut BloomFilter) -> Self {
+        MerkleBlock::from_block_matching(block, |tx, _| filter.is_relevant_and_update(tx))
+    }
+
+    fn from_block_matching<F>(block: &Block, mut is_match: F) -> Self
+        where F: FnMut(&Transaction, &Txid) -> bool
+    {
         let header = block.header.clone();
 
         let mut matches: Vec<bool> = Vec::with_capacity(block.txdata.len());
         let mut hashes: Vec<Txid> = Vec::with_capacity(block.txdata.len());
 
-        for hash in block.txdata.iter().map(Transaction::txid) {
-            matches.push(match_txids.contains(&hash));
+        for tx in &block.txdata {
+            let hash = tx.txid();
+            matches.push(is_match(tx, &hash));
             hashes.push(hash);
         }
 
@@ -501,18 +603,109 @@ impl Decodable for MerkleBlock {
     }
 }
 
+/// Data structure that represents a block header paired to partial merkle trees
+/// over both the txids and the immutable txids of the block, which match the
+/// same transactions. The matches are authenticated against both
+/// `merkle_root` and `im_merkle_root` of the header.
+///
+/// NOTE: This assumes that the given Block has *at least* 1 transaction. If the Block has 0 txs,
+/// it will hit an assertion.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MalFixMerkleBlock {
+    /// The block header
+    pub header: BlockHeader,
+    /// Transactions making up a partial merkle tree of txids
+    pub txn: PartialMerkleTree,
+    /// Transactions making up a partial merkle tree of immutable txids
+    pub malfix_txn: PartialMerkleTree,
+}
+
+impl MalFixMerkleBlock {
+    /// Create a MalFixMerkleBlock from a block, that should contain proofs for
+    /// the transactions whose immutable ids are in `match_malfix_txids`.
+    pub fn from_block(block: &Block, match_malfix_txids: &HashSet<MalFixTxid>) -> Self {
+        let mut matches: Vec<bool> = Vec::with_capacity(block.txdata.len());
+        let mut txids: Vec<Txid> = Vec::with_capacity(block.txdata.len());
+        let mut malfix_txids: Vec<MalFixTxid> = Vec::with_capacity(block.txdata.len());
+
+        for tx in &block.txdata {
+            let malfix_txid = tx.malfix_txid();
+            matches.push(match_malfix_txids.contains(&malfix_txid));
+            txids.push(tx.txid());
+            malfix_txids.push(malfix_txid);
+        }
+
+        MalFixMerkleBlock {
+            header: block.header.clone(),
+            txn: PartialMerkleTree::from_txids(&txids, &matches),
+            malfix_txn: PartialMerkleTree::from_malfix_txids(&malfix_txids, &matches),
+        }
+    }
+
+    /// Extract the matching immutable txid's represented by this merkle block
+    /// and their respective indices within the partial tree.
+    /// returns Ok(()) if both partial trees match the same transactions and
+    /// their roots match the header, or error in case of failure
+    pub fn extract_matches(
+        &self,
+        matches: &mut Vec<MalFixTxid>,
+        indexes: &mut Vec<u32>,
+    ) -> Result<(), MerkleBlockError> {
+        let mut txid_indexes = vec![];
+        let merkle_root = self.txn.extract_matches(&mut vec![], &mut txid_indexes)?;
+        if merkle_root != self.header.merkle_root {
+            return Err(MerkleRootMismatch);
+        }
+
+        let im_merkle_root = self.malfix_txn.extract_malfix_matches(matches, indexes)?;
+        if im_merkle_root != self.header.im_merkle_root {
+            return Err(ImMerkleRootMismatch);
+        }
+
+        if self.txn.num_transactions != self.malfix_txn.num_transactions || txid_indexes != *indexes {
+            return Err(MatchesMismatch);
+        }
+        Ok(())
+    }
+}
+
+impl Encodable for MalFixMerkleBlock {
+    fn consensus_encode<S: io::Write>(
+        &self,
+        mut s: S,
+    ) -> Result<usize, encode::Error> {
+        let len = self.header.consensus_encode(&mut s)?
+            + self.txn.consensus_encode(&mut s)?
+            + self.malfix_txn.consensus_encode(s)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for MalFixMerkleBlock {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        Ok(MalFixMerkleBlock {
+            header: Decodable::consensus_decode(&mut d)?,
+            txn: Decodable::consensus_decode(&mut d)?,
+            malfix_txn: Decodable::consensus_decode(d)?,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::cmp::min;
+    use std::collections::HashSet;
 
     use hashes::Hash;
     use hashes::hex::{FromHex, ToHex};
-    use hash_types::{Txid, TxMerkleNode};
+    use hash_types::{MalFixTxid, Txid, TxMerkleNode};
     use secp256k1::rand::prelude::*;
 
     use consensus::encode::{deserialize, serialize};
+    use network::message_bloom::BloomFlags;
+    use util::bloom::BloomFilter;
     use util::hash::{bitcoin_merkle_root, BitcoinHash};
-    use util::merkleblock::{MerkleBlock, PartialMerkleTree};
+    use util::merkleblock::{MalFixMerkleBlock, MerkleBlock, MerkleBlockError, PartialMerkleTree};
     use {hex, Block};
 
     #[test]
@@ -612,6 +805,24 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn pmt_malfix_txids() {
+        let malfix_txids: Vec<MalFixTxid> = (1..8)
+            .map(|i| MalFixTxid::from_hex(&format!("{:064x}", i)).unwrap())
+            .collect();
+        let im_merkle_root: TxMerkleNode = bitcoin_merkle_root(malfix_txids.iter().map(|t| t.as_hash())).into();
+
+        let matches = vec![false, true, false, false, true, false, false];
+        let tree = PartialMerkleTree::from_malfix_txids(&malfix_txids, &matches);
+        let tree: PartialMerkleTree = deserialize(&serialize(&tree)).unwrap();
+
+        let mut matched: Vec<MalFixTxid> = vec![];
+        let mut indexes = vec![];
+        assert_eq!(tree.extract_malfix_matches(&mut matched, &mut indexes).unwrap(), im_merkle_root);
+        assert_eq!(matched, vec![malfix_txids[1], malfix_txids[4]]);
+        assert_eq!(indexes, vec![1, 4]);
+    }
+
     #[test]
     fn merkleblock_serialization() {
         // Got it by running the rpc call
@@ -704,6 +915,78 @@ mod tests {
         assert_eq!(index.len(), 0);
     }
 
+    /// Create a CMerkleBlock using the immutable ids of transactions in the given block
+    #[test]
+    fn merkleblock_construct_from_malfix_txids() {
+        let block = get_block_13b8a();
+        let txids: HashSet<Txid> = [block.txdata[1].txid(), block.txdata[8].txid()].iter().cloned().collect();
+        let malfix_txids = [block.txdata[1].malfix_txid(), block.txdata[8].malfix_txid()].iter().cloned().collect();
+
+        let merkle_block = MerkleBlock::from_block_with_malfix_txids(&block, &malfix_txids);
+        assert_eq!(merkle_block, MerkleBlock::from_block(&block, &txids));
+
+        let mut matches: Vec<Txid> = vec![];
+        let mut index: Vec<u32> = vec![];
+        merkle_block.extract_matches(&mut matches, &mut index).unwrap();
+        assert_eq!(matches, vec![block.txdata[1].txid(), block.txdata[8].txid()]);
+        assert_eq!(index, vec![1, 8]);
+
+        // txids are not immutable ids
+        let merkle_block = MerkleBlock::from_block_with_malfix_txids(
+            &block,
+            &txids.iter().map(|txid| MalFixTxid::from(txid.as_hash())).collect(),
+        );
+        matches.clear();
+        index.clear();
+        merkle_block.extract_matches(&mut matches, &mut index).unwrap();
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn merkleblock_construct_from_filter() {
+        let block = get_block_13b8a();
+        let txids: HashSet<Txid> = [block.txdata[1].txid(), block.txdata[8].txid()].iter().cloned().collect();
+
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::All);
+        filter.insert(&block.txdata[1].malfix_txid()[..]);
+        filter.insert(&block.txdata[8].malfix_txid()[..]);
+        let merkle_block = MerkleBlock::from_block_with_filter(&block, &mut filter);
+        assert_eq!(merkle_block, MerkleBlock::from_block(&block, &txids));
+    }
+
+    #[test]
+    fn malfix_merkleblock_test() {
+        let mut block = get_block_13b8a();
+        block.header.im_merkle_root = block.immutable_merkle_root();
+        let malfix_txids = [block.txdata[1].malfix_txid(), block.txdata[8].malfix_txid()]
+            .iter()
+            .cloned()
+            .collect();
+
+        let merkle_block = MalFixMerkleBlock::from_block(&block, &malfix_txids);
+        let merkle_block: MalFixMerkleBlock = deserialize(&serialize(&merkle_block)).unwrap();
+
+        let mut matches: Vec<MalFixTxid> = vec![];
+        let mut index: Vec<u32> = vec![];
+        merkle_block.extract_matches(&mut matches, &mut index).unwrap();
+        assert_eq!(matches, vec![block.txdata[1].malfix_txid(), block.txdata[8].malfix_txid()]);
+        assert_eq!(index, vec![1, 8]);
+
+        let mut invalid = merkle_block.clone();
+        invalid.header.im_merkle_root = block.header.merkle_root;
+        assert_eq!(invalid.extract_matches(&mut matches, &mut index), Err(MerkleBlockError::ImMerkleRootMismatch));
+
+        let mut invalid = merkle_block.clone();
+        invalid.header.merkle_root = block.header.im_merkle_root;
+        assert_eq!(invalid.extract_matches(&mut matches, &mut index), Err(MerkleBlockError::MerkleRootMismatch));
+
+        // the tree of txids proves another transaction
+        let txids: HashSet<Txid> = [block.txdata[2].txid()].iter().cloned().collect();
+        let mut invalid = merkle_block.clone();
+        invalid.txn = MerkleBlock::from_block(&block, &txids).txn;
+        assert_eq!(invalid.extract_matches(&mut matches, &mut index), Err(MerkleBlockError::MatchesMismatch));
+    }
+
     impl PartialMerkleTree {
         /// Flip one bit in one of the hashes - this should break the authentication
         fn damage(&mut self, rng: &mut ThreadRng) {
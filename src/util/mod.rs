@@ -25,16 +25,23 @@ pub mod address;
 pub mod amount;
 pub mod base58;
 pub mod bip143;
+pub mod bip152;
 pub mod bip158;
 pub mod bip32;
+pub mod bloom;
 pub mod contracthash;
 pub mod hash;
+pub mod headerchain;
 pub mod key;
 pub mod merkleblock;
 pub mod misc;
 pub mod psbt;
+pub mod scriptsig;
+pub mod spv;
 pub mod uint;
 pub mod signature;
+pub mod threshold;
+pub mod token;
 pub mod prime;
 pub mod rfc7969;
 
@@ -160,7 +160,7 @@ impl Decodable for PartiallySignedTransaction {
 #[cfg(test)]
 mod tests {
     use hashes::hex::FromHex;
-    use hash_types::Txid;
+    use hash_types::MalFixTxid;
 
     use std::collections::BTreeMap;
 
@@ -252,7 +252,7 @@ mod tests {
                 lock_time: 1257139,
                 input: vec![TxIn {
                     previous_output: OutPoint {
-                        txid: Txid::from_hex(
+                        txid: MalFixTxid::from_hex(
                             "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126",
                         )
                         .unwrap(),
@@ -313,7 +313,7 @@ mod tests {
         use hex::decode as hex_decode;
 
         use hashes::hex::FromHex;
-        use hash_types::Txid;
+        use hash_types::{MalFixTxid, Txid};
 
         use blockdata::script::Script;
         use blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut};
@@ -361,7 +361,7 @@ mod tests {
                         lock_time: 1257139,
                         input: vec![TxIn {
                             previous_output: OutPoint {
-                                txid: Txid::from_hex(
+                                txid: MalFixTxid::from_hex(
                                     "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126",
                                 ).unwrap(),
                                 vout: 0,
@@ -389,7 +389,7 @@ mod tests {
                         lock_time: 0,
                         input: vec![TxIn {
                             previous_output: OutPoint {
-                                txid: Txid::from_hex(
+                                txid: MalFixTxid::from_hex(
                                     "e567952fb6cc33857f392efa3a46c995a28f69cca4bb1b37e0204dab1ec7a389",
                                 ).unwrap(),
                                 vout: 1,
@@ -403,7 +403,7 @@ mod tests {
                         },
                         TxIn {
                             previous_output: OutPoint {
-                                txid: Txid::from_hex(
+                                txid: MalFixTxid::from_hex(
                                     "b490486aec3ae671012dddb2bb08466bef37720a533a894814ff1da743aaf886",
                                 ).unwrap(),
                                 vout: 1,
@@ -478,7 +478,8 @@ mod tests {
             let tx_input = &psbt.global.unsigned_tx.input[0];
             let psbt_non_witness_utxo = (&psbt.inputs[0].non_witness_utxo).as_ref().unwrap();
 
-            assert_eq!(tx_input.previous_output.txid, psbt_non_witness_utxo.txid());
+            // bitcoin transactions reference their previous outputs by txid
+            assert_eq!(tx_input.previous_output.txid.as_hash(), psbt_non_witness_utxo.txid().as_hash());
             assert!(
                 psbt_non_witness_utxo.output[tx_input.previous_output.vout as usize]
                     .script_pubkey
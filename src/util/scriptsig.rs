@@ -0,0 +1,359 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Transaction signatures
+//!
+//! Signatures which are pushed in a scriptSig and checked by OP_CHECKSIG and
+//! OP_CHECKMULTISIG. Tapyrus accepts both DER-encoded ECDSA signatures and
+//! 64-byte Schnorr signatures, each followed by a sighash type byte. A
+//! signature of 64 bytes is always taken to be Schnorr.
+//!
+//! Data signatures, checked by OP_CHECKDATASIG, sign the SHA256 of an arbitrary
+//! message and have no sighash type.
+//!
+
+use std::{error, fmt};
+
+use secp256k1;
+use hashes::{sha256, Hash};
+
+use blockdata::script::Script;
+use blockdata::transaction::{SigHashType, Transaction};
+use hash_types::SigHash;
+use util::key::{PrivateKey, PublicKey};
+use util::signature::{self, Signature};
+
+/// The size of a Schnorr signature in a script, without the sighash type
+pub const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+/// An error in parsing or verifying a transaction signature
+#[derive(Debug)]
+pub enum Error {
+    /// The signature is empty, so it has no sighash type
+    EmptySignature,
+    /// The sighash type is not one of the defined types
+    NonStandardSigHashType(u8),
+    /// Invalid Schnorr signature
+    Schnorr(signature::Error),
+    /// Invalid ECDSA signature
+    Ecdsa(secp256k1::Error),
+    /// The input index is out of the inputs of the transaction
+    InputIndexOutOfRange(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EmptySignature => f.write_str(error::Error::description(self)),
+            Error::NonStandardSigHashType(t) => write!(f, "non-standard sighash type: {:#x}", t),
+            Error::Schnorr(ref e) => write!(f, "schnorr signature error: {}", e),
+            Error::Ecdsa(ref e) => write!(f, "ecdsa signature error: {}", e),
+            Error::InputIndexOutOfRange(index) => write!(f, "input index out of range: {}", index),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Schnorr(ref e) => Some(e),
+            Error::Ecdsa(ref e) => Some(e),
+            Error::EmptySignature | Error::NonStandardSigHashType(_) | Error::InputIndexOutOfRange(_) => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::EmptySignature => "empty signature",
+            Error::NonStandardSigHashType(_) => "non-standard sighash type",
+            Error::Schnorr(_) => "schnorr signature error",
+            Error::Ecdsa(_) => "ecdsa signature error",
+            Error::InputIndexOutOfRange(_) => "input index out of range",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<signature::Error> for Error {
+    fn from(e: signature::Error) -> Error {
+        Error::Schnorr(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error {
+        Error::Ecdsa(e)
+    }
+}
+
+/// The signature hash of an input, or an error rather than the panic of
+/// `Transaction::signature_hash` if there is no such input
+fn signature_hash(tx: &Transaction, input_index: usize, script_pubkey: &Script, sighash_type: SigHashType) -> Result<SigHash, Error> {
+    if input_index >= tx.input.len() {
+        return Err(Error::InputIndexOutOfRange(input_index));
+    }
+    Ok(tx.signature_hash(input_index, script_pubkey, sighash_type.as_u32()))
+}
+
+/// A signature with its sighash type, as pushed in a scriptSig
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScriptSignature {
+    /// Schnorr signature
+    Schnorr(Signature, SigHashType),
+    /// DER-encoded ECDSA signature
+    Ecdsa(secp256k1::Signature, SigHashType),
+}
+
+impl ScriptSignature {
+    /// Sign an input of a transaction with a Schnorr signature. `script_pubkey`
+    /// is the script of the output spent by the input, as in `Transaction::signature_hash`.
+    pub fn sign_schnorr(
+        tx: &Transaction,
+        input_index: usize,
+        script_pubkey: &Script,
+        sighash_type: SigHashType,
+        privkey: &PrivateKey,
+    ) -> Result<ScriptSignature, Error> {
+        let sighash = signature_hash(tx, input_index, script_pubkey, sighash_type)?;
+        let sig = Signature::sign(privkey, &sighash.into_inner())?;
+        Ok(ScriptSignature::Schnorr(sig, sighash_type))
+    }
+
+    /// Sign an input of a transaction with an ECDSA signature
+    pub fn sign_ecdsa<C: secp256k1::Signing>(
+        secp: &secp256k1::Secp256k1<C>,
+        tx: &Transaction,
+        input_index: usize,
+        script_pubkey: &Script,
+        sighash_type: SigHashType,
+        privkey: &PrivateKey,
+    ) -> Result<ScriptSignature, Error> {
+        let sighash = signature_hash(tx, input_index, script_pubkey, sighash_type)?;
+        let msg = secp256k1::Message::from_slice(&sighash[..])?;
+        Ok(ScriptSignature::Ecdsa(secp.sign(&msg, &privkey.key), sighash_type))
+    }
+
+    /// Parse a signature pushed in a scriptSig. A signature of 64 bytes
+    /// without the sighash type is Schnorr, otherwise it must be strict DER.
+    pub fn from_slice(data: &[u8]) -> Result<ScriptSignature, Error> {
+        let (&last, sig) = match data.split_last() {
+            Some(x) => x,
+            None => return Err(Error::EmptySignature),
+        };
+
+        let sighash_type = SigHashType::from_u32(last as u32);
+        if sighash_type.as_u32() != last as u32 {
+            return Err(Error::NonStandardSigHashType(last));
+        }
+
+        if sig.len() == SCHNORR_SIGNATURE_SIZE {
+            let mut r_x = [0u8; 32];
+            let mut sigma = [0u8; 32];
+            r_x.copy_from_slice(&sig[..32]);
+            sigma.copy_from_slice(&sig[32..]);
+            Ok(ScriptSignature::Schnorr(Signature { r_x: r_x, sigma: sigma }, sighash_type))
+        } else {
+            Ok(ScriptSignature::Ecdsa(secp256k1::Signature::from_der(sig)?, sighash_type))
+        }
+    }
+
+    /// Serialize the signature followed by the sighash type byte
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = match *self {
+            ScriptSignature::Schnorr(ref sig, _) => {
+                let mut v = Vec::with_capacity(SCHNORR_SIGNATURE_SIZE + 1);
+                v.extend_from_slice(&sig.r_x[..]);
+                v.extend_from_slice(&sig.sigma[..]);
+                v
+            }
+            ScriptSignature::Ecdsa(ref sig, _) => sig.serialize_der()[..].to_vec(),
+        };
+        result.push(self.sighash_type().as_u32() as u8);
+        result
+    }
+
+    /// The sighash type
+    pub fn sighash_type(&self) -> SigHashType {
+        match *self {
+            ScriptSignature::Schnorr(_, t) | ScriptSignature::Ecdsa(_, t) => t,
+        }
+    }
+
+    /// Whether this is a Schnorr signature
+    pub fn is_schnorr(&self) -> bool {
+        match *self {
+            ScriptSignature::Schnorr(..) => true,
+            ScriptSignature::Ecdsa(..) => false,
+        }
+    }
+
+    /// Verify the signature of an input of a transaction against a public key
+    pub fn verify(
+        &self,
+        tx: &Transaction,
+        input_index: usize,
+        script_pubkey: &Script,
+        pk: &PublicKey,
+    ) -> Result<(), Error> {
+        let sighash = signature_hash(tx, input_index, script_pubkey, self.sighash_type())?;
+        match *self {
+            ScriptSignature::Schnorr(ref sig, _) => Ok(sig.verify(&sighash.into_inner(), pk)?),
+            ScriptSignature::Ecdsa(ref sig, _) => {
+                let secp = secp256k1::Secp256k1::verification_only();
+                let mut sig = *sig;
+                // libsecp256k1 only accepts low S signatures
+                sig.normalize_s();
+                let msg = secp256k1::Message::from_slice(&sighash[..])?;
+                Ok(secp.verify(&msg, &sig, &pk.key)?)
+            }
+        }
+    }
+}
+
+/// Verify a data signature of the SHA256 of `message` by `pk`, as OP_CHECKDATASIG
+/// does. A signature of 64 bytes is Schnorr, otherwise it must be DER-encoded ECDSA.
+pub fn verify_data_signature(sig: &[u8], message: &[u8], pk: &PublicKey) -> Result<(), Error> {
+    if sig.is_empty() {
+        return Err(Error::EmptySignature);
+    }
+    let hash = sha256::Hash::hash(message);
+
+    if sig.len() == SCHNORR_SIGNATURE_SIZE {
+        let mut r_x = [0u8; 32];
+        let mut sigma = [0u8; 32];
+        r_x.copy_from_slice(&sig[..32]);
+        sigma.copy_from_slice(&sig[32..]);
+        Ok(Signature { r_x: r_x, sigma: sigma }.verify(&hash.into_inner(), pk)?)
+    } else {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let mut sig = secp256k1::Signature::from_der(sig)?;
+        // libsecp256k1 only accepts low S signatures
+        sig.normalize_s();
+        let msg = secp256k1::Message::from_slice(&hash[..])?;
+        Ok(secp.verify(&msg, &sig, &pk.key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+
+    use hashes::{sha256, Hash};
+    use hashes::hex::FromHex;
+
+    use blockdata::transaction::{SigHashType, Transaction};
+    use consensus::encode::deserialize;
+    use util::address::Address;
+    use util::key::{PrivateKey, PublicKey};
+    use util::scriptsig::{verify_data_signature, Error, ScriptSignature};
+    use util::signature::Signature;
+
+    #[test]
+    fn test_script_signature() {
+        let secp = Secp256k1::new();
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()[..]).unwrap();
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pk = key.public_key(&secp);
+        let script_pubkey = Address::p2pkh(&pk, key.network).script_pubkey();
+
+        for &sighash_type in &[SigHashType::All, SigHashType::SinglePlusAnyoneCanPay] {
+            let schnorr = ScriptSignature::sign_schnorr(&tx, 0, &script_pubkey, sighash_type, &key).unwrap();
+            let ecdsa = ScriptSignature::sign_ecdsa(&secp, &tx, 0, &script_pubkey, sighash_type, &key).unwrap();
+
+            let serialized = schnorr.serialize();
+            assert_eq!(serialized.len(), 65);
+            assert_eq!(*serialized.last().unwrap() as u32, sighash_type.as_u32());
+            let parsed = ScriptSignature::from_slice(&serialized).unwrap();
+            assert!(parsed.is_schnorr());
+            assert_eq!(parsed, schnorr);
+            assert!(parsed.verify(&tx, 0, &script_pubkey, &pk).is_ok());
+
+            let parsed = ScriptSignature::from_slice(&ecdsa.serialize()).unwrap();
+            assert!(!parsed.is_schnorr());
+            assert_eq!(parsed, ecdsa);
+            assert!(parsed.verify(&tx, 0, &script_pubkey, &pk).is_ok());
+        }
+
+        // an input the transaction does not have
+        match ScriptSignature::sign_schnorr(&tx, 1, &script_pubkey, SigHashType::All, &key) {
+            Err(Error::InputIndexOutOfRange(1)) => {},
+            x => panic!("expected InputIndexOutOfRange, got {:?}", x),
+        }
+        match ScriptSignature::sign_ecdsa(&secp, &tx, 1, &script_pubkey, SigHashType::All, &key) {
+            Err(Error::InputIndexOutOfRange(1)) => {},
+            x => panic!("expected InputIndexOutOfRange, got {:?}", x),
+        }
+
+        // signed for another script
+        let schnorr = ScriptSignature::sign_schnorr(&tx, 0, &script_pubkey, SigHashType::All, &key).unwrap();
+        match schnorr.verify(&tx, 0, &tx.output[0].script_pubkey, &pk) {
+            Err(Error::Schnorr(_)) => {},
+            x => panic!("expected Schnorr error, got {:?}", x),
+        }
+
+        // the ECDSA signature in the scriptSig of the transaction
+        let sig = Vec::<u8>::from_hex("3046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c01").unwrap();
+        let parsed = ScriptSignature::from_slice(&sig).unwrap();
+        assert_eq!(parsed.sighash_type(), SigHashType::All);
+        assert_eq!(parsed.serialize(), sig);
+
+        // which has a high S and verifies against the output it spends
+        let spent_pk = PublicKey::from_str("033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52").unwrap();
+        let spent_script_pubkey = Address::p2pkh(&spent_pk, key.network).script_pubkey();
+        assert!(parsed.verify(&tx, 0, &spent_script_pubkey, &spent_pk).is_ok());
+        match parsed.verify(&tx, 1, &spent_script_pubkey, &spent_pk) {
+            Err(Error::InputIndexOutOfRange(1)) => {},
+            x => panic!("expected InputIndexOutOfRange, got {:?}", x),
+        }
+
+        let mut sig = schnorr.serialize();
+        *sig.last_mut().unwrap() = 0x04;
+        match ScriptSignature::from_slice(&sig) {
+            Err(Error::NonStandardSigHashType(0x04)) => {},
+            x => panic!("expected NonStandardSigHashType, got {:?}", x),
+        }
+        match ScriptSignature::from_slice(&[]) {
+            Err(Error::EmptySignature) => {},
+            x => panic!("expected EmptySignature, got {:?}", x),
+        }
+        match ScriptSignature::from_slice(&[0x30, 0x00, 0x01]) {
+            Err(Error::Ecdsa(_)) => {},
+            x => panic!("expected Ecdsa error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_verify_data_signature() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pk = key.public_key(&secp);
+        let message = b"the price is 100";
+        let hash = sha256::Hash::hash(message);
+
+        let schnorr = Signature::sign(&key, &hash.into_inner()).unwrap();
+        let mut sig = schnorr.r_x.to_vec();
+        sig.extend_from_slice(&schnorr.sigma[..]);
+        assert!(verify_data_signature(&sig, message, &pk).is_ok());
+        match verify_data_signature(&sig, b"the price is 200", &pk) {
+            Err(Error::Schnorr(_)) => {},
+            x => panic!("expected Schnorr error, got {:?}", x),
+        }
+
+        let msg = ::secp256k1::Message::from_slice(&hash[..]).unwrap();
+        let ecdsa = secp.sign(&msg, &key.key).serialize_der();
+        assert!(verify_data_signature(&ecdsa[..], message, &pk).is_ok());
+        match verify_data_signature(&ecdsa[..], b"the price is 200", &pk) {
+            Err(Error::Ecdsa(_)) => {},
+            x => panic!("expected Ecdsa error, got {:?}", x),
+        }
+
+        match verify_data_signature(&[], message, &pk) {
+            Err(Error::EmptySignature) => {},
+            x => panic!("expected EmptySignature, got {:?}", x),
+        }
+    }
+}
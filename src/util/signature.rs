@@ -14,6 +14,7 @@ use std::borrow::Borrow;
 use secp256k1::SecretKey;
 use hashes::{sha256, HashEngine, Hash};
 
+use util::endian;
 use util::key::{PublicKey, PrivateKey};
 use util::prime::jacobi;
 use util::rfc7969::nonce_rfc6979;
@@ -41,13 +42,40 @@ impl Signature {
         Self::sign_inner(privkey.key.borrow(), message)
     }
 
+    /// signing to message, mixing the auxiliary data `aux` into the nonce.
+    /// The nonce is still derived deterministically from the key, the message
+    /// and `aux`, so `aux` should be fresh randomness for every signature.
+    pub fn sign_with_aux(privkey: &PrivateKey, message: &[u8; 32], aux: &[u8; 32]) -> Result<Self, Error> {
+        Self::sign_with_nonce_function(privkey, message, &Rfc6979, Some(aux))
+    }
+
+    /// signing to message with the nonce generated by `noncefn`, which is
+    /// given the auxiliary data `data`
+    pub fn sign_with_nonce_function<N: NonceFunction>(
+        privkey: &PrivateKey,
+        message: &[u8; 32],
+        noncefn: &N,
+        data: Option<&[u8; 32]>,
+    ) -> Result<Self, Error> {
+        Self::sign_inner_with(privkey.key.borrow(), message, noncefn, data)
+    }
+
     fn sign_inner(sk: &SecretKey, message: &[u8; 32]) -> Result<Self, Error> {
+        Self::sign_inner_with(sk, message, &Rfc6979, None)
+    }
+
+    fn sign_inner_with<N: NonceFunction>(
+        sk: &SecretKey,
+        message: &[u8; 32],
+        noncefn: &N,
+        data: Option<&[u8; 32]>,
+    ) -> Result<Self, Error> {
         let ctx = secp256k1::Secp256k1::signing_only();
 
         let pk = secp256k1::PublicKey::from_secret_key(&ctx, sk);
 
         // Generate k
-        let mut k = Self::generate_k(sk, message);
+        let mut k = Self::generate_k(sk, message, noncefn, data);
 
         // Compute R = k * G
         let r = secp256k1::PublicKey::from_secret_key(&ctx, &k);
@@ -120,7 +148,7 @@ impl Signature {
     }
 
     /// Compute e
-    fn compute_e(r_x: &[u8], pk: &secp256k1::PublicKey, message: &[u8; 32]) -> Result<SecretKey, secp256k1::Error> {
+    pub(crate) fn compute_e(r_x: &[u8], pk: &secp256k1::PublicKey, message: &[u8; 32]) -> Result<SecretKey, secp256k1::Error> {
         let mut engine = sha256::Hash::engine();
         engine.input(r_x);
         engine.input(&pk.serialize()[..]);
@@ -130,17 +158,11 @@ impl Signature {
         Ok(SecretKey::from_slice(&hash[..])?)
     }
 
-    fn generate_k(sk: &SecretKey, message: &[u8; 32]) -> SecretKey {
+    fn generate_k<N: NonceFunction>(sk: &SecretKey, message: &[u8; 32], noncefn: &N, data: Option<&[u8; 32]>) -> SecretKey {
         let mut count: u32 = 0;
 
         loop {
-            let nonce = nonce_rfc6979(
-                message,
-                sk,
-                &ALGO16,
-                None,
-                count
-            );
+            let nonce = noncefn.nonce(message, sk, data, count);
             count += 1;
 
             if let Ok(k) = SecretKey::from_slice(&nonce[..]) {
@@ -150,6 +172,145 @@ impl Signature {
     }
 }
 
+/// A function generating the nonce k of a Schnorr signature.
+///
+/// The signer calls it with increasing `counter`, starting from 0, until it
+/// returns a valid secret key, i.e. a nonzero value below the curve order.
+/// It must never return the same nonce for different messages signed with
+/// the same key, otherwise the key can be recovered from the signatures.
+pub trait NonceFunction {
+    /// Generate a nonce candidate
+    fn nonce(&self, message: &[u8; 32], key: &SecretKey, data: Option<&[u8; 32]>, counter: u32) -> [u8; 32];
+}
+
+/// The default nonce function, RFC6979 with HMAC-SHA256 and `ALGO16`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Rfc6979;
+
+impl NonceFunction for Rfc6979 {
+    fn nonce(&self, message: &[u8; 32], key: &SecretKey, data: Option<&[u8; 32]>, counter: u32) -> [u8; 32] {
+        nonce_rfc6979(message, key, &ALGO16, data, counter)
+    }
+}
+
+/// Verify a batch of signatures, given as tuples of signature, message and
+/// public key. All the signatures are checked at once with a random linear
+/// combination, in which the terms of signatures sharing a public key, e.g.
+/// the block proofs of a header chain, are summed up into one.
+/// If the batch does not verify, the signatures are verified one by one to
+/// report the first invalid one.
+///
+/// This is not faster than verifying the signatures one by one: without a
+/// multi-scalar multiplication in `secp256k1`, each R of the combination is
+/// still lifted and multiplied on its own (see the `unstable` benchmarks).
+pub fn verify_batch(items: &[(Signature, [u8; 32], PublicKey)]) -> Result<(), Error> {
+    if items.len() > 1 && verify_batch_inner(items).is_ok() {
+        return Ok(());
+    }
+
+    for (i, &(ref sig, ref message, ref pk)) in items.iter().enumerate() {
+        if let Err(e) = sig.verify(message, pk) {
+            return Err(Error::InvalidBatchItem(i, Box::new(e)));
+        }
+    }
+    Ok(())
+}
+
+fn verify_batch_inner(items: &[(Signature, [u8; 32], PublicKey)]) -> Result<(), Error> {
+    let ctx = secp256k1::Secp256k1::verification_only();
+
+    // Seed the coefficients of the linear combination with the whole batch
+    let seed = {
+        let mut engine = sha256::Hash::engine();
+        for &(ref sig, ref message, ref pk) in items {
+            engine.input(&sig.r_x[..]);
+            engine.input(&sig.sigma[..]);
+            engine.input(&message[..]);
+            engine.input(&pk.key.serialize()[..]);
+        }
+        sha256::Hash::from_engine(engine)
+    };
+
+    // Check that (a_0 s_0 + ... + a_n s_n)G = a_0 R_0 + ... + a_n R_n + (a_0 e_0)P_0 + ... + (a_n e_n)P_n,
+    // summing up the scalars for the same public key
+    let mut s_sum: Option<SecretKey> = None;
+    let mut points: Vec<secp256k1::PublicKey> = Vec::with_capacity(items.len());
+    let mut keys: Vec<(secp256k1::PublicKey, SecretKey)> = Vec::new();
+    for (i, &(ref sig, ref message, ref pk)) in items.iter().enumerate() {
+        // a_0 is 1, which saves the multiplications of the first term
+        let a = if i == 0 {
+            None
+        } else {
+            let mut engine = sha256::Hash::engine();
+            engine.input(&seed[..]);
+            engine.input(&endian::u32_to_array_le(i as u32));
+            Some(SecretKey::from_slice(&sha256::Hash::from_engine(engine)[..])?)
+        };
+
+        let mut s = SecretKey::from_slice(&sig.sigma[..])?;
+        if let Some(ref a) = a {
+            s.mul_assign(&a[..])?;
+        }
+        s_sum = Some(match s_sum {
+            Some(mut sum) => {
+                sum.add_assign(&s[..])?;
+                sum
+            }
+            None => s,
+        });
+
+        let mut r = lift_x(&sig.r_x)?;
+        let mut e = Signature::compute_e(&sig.r_x[..], &pk.key, message)?;
+        if let Some(ref a) = a {
+            r.mul_assign(&ctx, &a[..])?;
+            e.mul_assign(&a[..])?;
+        }
+        points.push(r);
+
+        match keys.iter().position(|&(ref key, _)| *key == pk.key) {
+            Some(pos) => keys[pos].1.add_assign(&e[..])?,
+            None => keys.push((pk.key, e)),
+        }
+    }
+
+    for (mut key, e) in keys {
+        key.mul_assign(&ctx, &e[..])?;
+        points.push(key);
+    }
+
+    let sg = {
+        let mut result = PublicKey::generator().key;
+        result.mul_assign(&ctx, &s_sum.ok_or(Error::InvalidSignature)?[..])?;
+        result
+    };
+
+    let mut iter = points.into_iter();
+    let mut rhs = iter.next().ok_or(Error::InvalidSignature)?;
+    for point in iter {
+        rhs = rhs.combine(&point)?;
+    }
+
+    if sg == rhs {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature)
+    }
+}
+
+/// Returns the point R whose x coordinate is `r_x` and jacobi(R.y) is 1
+fn lift_x(r_x: &[u8; 32]) -> Result<secp256k1::PublicKey, Error> {
+    let mut data = [0u8; 33];
+    data[0] = 2;
+    data[1..].clone_from_slice(&r_x[..]);
+    let r = secp256k1::PublicKey::from_slice(&data[..])?;
+    if jacobi(&r.serialize_uncompressed()[33..]) == 1 {
+        return Ok(r);
+    }
+
+    data[0] = 3;
+    Ok(secp256k1::PublicKey::from_slice(&data[..])?)
+}
+
 fn to_bytes(sk: &secp256k1::SecretKey) -> [u8; 32] {
     let mut r = [0u8; 32];
     r.clone_from_slice(&sk[..]);
@@ -175,6 +336,8 @@ pub enum Error {
     InvalidSignature,
     /// secp256k1 error
     Secp256k1Error(secp256k1::Error),
+    /// The signature at the index of a batch is invalid
+    InvalidBatchItem(usize, Box<Error>),
 }
 
 #[doc(hidden)]
@@ -188,6 +351,7 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Secp256k1Error(ref e) => fmt::Display::fmt(e, f),
+            Error::InvalidBatchItem(i, ref e) => write!(f, "invalid signature at index {} of batch: {}", i, e),
             Error::InvalidSignature => {
                 f.write_str(error::Error::description(self))
             }
@@ -200,12 +364,14 @@ impl error::Error for Error {
         match *self {
             Error::Secp256k1Error(ref e) => e.description(),
             Error::InvalidSignature => "Invalid signature",
+            Error::InvalidBatchItem(..) => "Invalid signature in batch",
         }
     }
 
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::Secp256k1Error(ref e) => Some(e),
+            Error::InvalidBatchItem(_, ref e) => Some(e.as_ref()),
             Error::InvalidSignature => None,
         }
     }
@@ -217,8 +383,10 @@ mod tests {
 
     use hashes::Hash;
     use consensus::encode::{deserialize, serialize};
-    use util::signature::Signature;
-    use util::key::PrivateKey;
+    use secp256k1::SecretKey;
+
+    use util::signature::{verify_batch, Error, NonceFunction, Rfc6979, Signature};
+    use util::key::{PrivateKey, PublicKey};
     use test_helpers::*;
 
     #[test]
@@ -332,6 +500,101 @@ mod tests {
         assert!(secp256k1::PublicKey::from_slice(&pk[..]).is_err());
     }
 
+    #[test]
+    fn test_sign_with_aux() {
+        let ctx = secp256k1::Secp256k1::signing_only();
+        let key = PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let pk = key.public_key(&ctx);
+        let msg = hashes::sha256::Hash::hash(b"Very secret message").into_inner();
+
+        let sig = Signature::sign(&key, &msg).unwrap();
+        assert_eq!(Signature::sign_with_nonce_function(&key, &msg, &Rfc6979, None).unwrap(), sig);
+
+        let sig1 = Signature::sign_with_aux(&key, &msg, &[1u8; 32]).unwrap();
+        let sig2 = Signature::sign_with_aux(&key, &msg, &[2u8; 32]).unwrap();
+        assert!(sig1.verify(&msg, &pk).is_ok());
+        assert!(sig2.verify(&msg, &pk).is_ok());
+        assert_ne!(sig1, sig);
+        assert_ne!(sig1, sig2);
+        assert_eq!(Signature::sign_with_aux(&key, &msg, &[1u8; 32]).unwrap(), sig1);
+    }
+
+    #[test]
+    fn test_sign_with_nonce_function() {
+        /// Returns an invalid nonce first, then the aux data
+        struct AuxNonce;
+        impl NonceFunction for AuxNonce {
+            fn nonce(&self, _: &[u8; 32], _: &SecretKey, data: Option<&[u8; 32]>, counter: u32) -> [u8; 32] {
+                if counter == 0 { [0u8; 32] } else { *data.unwrap() }
+            }
+        }
+
+        let ctx = secp256k1::Secp256k1::signing_only();
+        let key = PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let msg = hashes::sha256::Hash::hash(b"Very secret message").into_inner();
+
+        let mut k = [0u8; 32];
+        k[31] = 1;
+        let sig = Signature::sign_with_nonce_function(&key, &msg, &AuxNonce, Some(&k)).unwrap();
+        assert!(sig.verify(&msg, &key.public_key(&ctx)).is_ok());
+        // R = G
+        assert_eq!(&sig.r_x[..], &PublicKey::generator().key.serialize()[1..]);
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let ctx = secp256k1::Secp256k1::signing_only();
+        let keys = vec![
+            PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap(),
+            PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap(),
+            PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap(),
+        ];
+
+        let mut items = Vec::new();
+        for n in 0..16 {
+            let msg = hashes::sha256::Hash::hash(format!("Block {}", n).as_bytes()).into_inner();
+            let key = &keys[n % keys.len()];
+            items.push((Signature::sign(key, &msg).unwrap(), msg, key.public_key(&ctx)));
+        }
+
+        // test vector 4
+        items.push((
+            decode_signature("00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C63BAFC1D697AADBEB208CB8249CC7D9725A0FF8DA59AE04F68349A1EA06D072266"),
+            decode_message("4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703"),
+            PublicKey { compressed: true, key: decode_pk("03D69C3509BB99E412E68B0FE8544E72837DFA30746D8BE2AA65975F29D22DC7B9") },
+        ));
+
+        assert!(verify_batch(&[]).is_ok());
+        assert!(verify_batch(&items[..1]).is_ok());
+        assert!(verify_batch(&items).is_ok());
+
+        // wrong message
+        let mut invalid = items.clone();
+        invalid[5].1[0] ^= 1;
+        match verify_batch(&invalid) {
+            Err(Error::InvalidBatchItem(5, _)) => {},
+            x => panic!("expected InvalidBatchItem, got {:?}", x),
+        }
+
+        // swapped signatures
+        let mut invalid = items.clone();
+        let sig = invalid[3].0;
+        invalid[3].0 = invalid[4].0;
+        invalid[4].0 = sig;
+        match verify_batch(&invalid) {
+            Err(Error::InvalidBatchItem(3, _)) => {},
+            x => panic!("expected InvalidBatchItem, got {:?}", x),
+        }
+
+        // sigma out of range
+        let mut invalid = items.clone();
+        invalid[16].0.sigma = [0xff; 32];
+        match verify_batch(&invalid) {
+            Err(Error::InvalidBatchItem(16, _)) => {},
+            x => panic!("expected InvalidBatchItem, got {:?}", x),
+        }
+    }
+
     #[test]
     fn test_decode() {
         let sig_data =
@@ -351,3 +614,38 @@ mod tests {
         assert_eq!(serialize(&real_decode), sig_data);
     }
 }
+
+#[cfg(all(test, feature = "unstable"))]
+mod benches {
+    use hashes::{sha256, Hash};
+    use test::Bencher;
+
+    use util::key::{PrivateKey, PublicKey};
+    use util::signature::{verify_batch, Signature};
+
+    /// 100 signatures by the same key, as the block proofs of a header chain
+    fn batch() -> Vec<(Signature, [u8; 32], PublicKey)> {
+        let key = PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let pk = key.public_key(&::secp256k1::Secp256k1::signing_only());
+        (0..100u32).map(|i| {
+            let message = sha256::Hash::hash(&i.to_le_bytes()).into_inner();
+            (Signature::sign(&key, &message).unwrap(), message, pk)
+        }).collect()
+    }
+
+    #[bench]
+    fn bench_verify_batch(bh: &mut Bencher) {
+        let items = batch();
+        bh.iter(|| verify_batch(&items).unwrap());
+    }
+
+    #[bench]
+    fn bench_verify_one_by_one(bh: &mut Bencher) {
+        let items = batch();
+        bh.iter(|| {
+            for &(ref sig, ref message, ref pk) in &items {
+                sig.verify(message, pk).unwrap();
+            }
+        });
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! SPV proofs
+//!
+//! A self-contained proof that transactions are included in a block signed by
+//! the federation. It consists of a merkle block whose header carries its
+//! proof, which is verified against the aggregated public key of the
+//! federation, or against a `HeaderChain` the verifier trusts. Only then are
+//! the transactions matched by the partial merkle tree accepted.
+//!
+
+use std::collections::HashSet;
+use std::{error, fmt, io};
+
+use blockdata::block::{Block, ProofError};
+use consensus::encode::{self, Decodable, Encodable};
+use hash_types::{BlockHash, Txid};
+use util::hash::BitcoinHash;
+use util::headerchain::HeaderChain;
+use util::key::PublicKey;
+use util::merkleblock::{MerkleBlock, MerkleBlockError};
+
+/// An error in verifying an SPV proof
+#[derive(Debug)]
+pub enum Error {
+    /// The partial merkle tree does not authenticate against the header
+    MerkleBlock(MerkleBlockError),
+    /// The header proof does not verify against the aggregated public key
+    InvalidProof(ProofError),
+    /// The block is neither in the header chain nor extends its tip
+    UnknownBlock(BlockHash),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MerkleBlock(ref e) => write!(f, "invalid merkle block: {}", e),
+            Error::InvalidProof(ref e) => write!(f, "invalid proof: {}", e),
+            Error::UnknownBlock(ref hash) => write!(f, "unknown block: {}", hash),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::MerkleBlock(ref e) => Some(e),
+            Error::InvalidProof(ref e) => Some(e),
+            Error::UnknownBlock(_) => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::MerkleBlock(_) => "invalid merkle block",
+            Error::InvalidProof(_) => "invalid proof",
+            Error::UnknownBlock(_) => "unknown block",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<MerkleBlockError> for Error {
+    fn from(e: MerkleBlockError) -> Error {
+        Error::MerkleBlock(e)
+    }
+}
+
+/// A proof that transactions are included in a signed block
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SpvProof {
+    /// The merkle block, whose header carries the block proof
+    pub merkle_block: MerkleBlock,
+}
+
+impl SpvProof {
+    /// Create a proof for the transactions of the block whose txids are in `match_txids`
+    pub fn from_block(block: &Block, match_txids: &HashSet<Txid>) -> SpvProof {
+        SpvProof { merkle_block: MerkleBlock::from_block(block, match_txids) }
+    }
+
+    /// The hash of the block
+    pub fn block_hash(&self) -> BlockHash {
+        self.merkle_block.header.bitcoin_hash()
+    }
+
+    /// Verify the block proof against the aggregated public key which signs
+    /// the block, then the partial merkle tree against the header. Returns the
+    /// txids of the matched transactions with their positions in the block.
+    pub fn verify(&self, aggregated_public_key: &PublicKey) -> Result<Vec<(Txid, u32)>, Error> {
+        self.merkle_block.header.verify_proof(aggregated_public_key).map_err(Error::InvalidProof)?;
+        self.extract_matches()
+    }
+
+    /// Verify the proof against a chain of validated headers. The block must
+    /// either be connected to the chain, whose headers were verified when
+    /// connected, or extend its tip and be signed by the aggregated public key
+    /// active at that height. Returns the txids of the matched transactions
+    /// with their positions in the block.
+    pub fn verify_with_chain(&self, chain: &HeaderChain) -> Result<Vec<(Txid, u32)>, Error> {
+        let hash = self.block_hash();
+        if chain.get_height(&hash).is_some() {
+            self.extract_matches()
+        } else if self.merkle_block.header.prev_blockhash == chain.tip() {
+            self.verify(chain.aggregated_public_key())
+        } else {
+            Err(Error::UnknownBlock(hash))
+        }
+    }
+
+    fn extract_matches(&self) -> Result<Vec<(Txid, u32)>, Error> {
+        let mut matches = vec![];
+        let mut indexes = vec![];
+        self.merkle_block.extract_matches(&mut matches, &mut indexes)?;
+        Ok(matches.into_iter().zip(indexes.into_iter()).collect())
+    }
+}
+
+impl Encodable for SpvProof {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.merkle_block.consensus_encode(s)
+    }
+}
+
+impl Decodable for SpvProof {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(SpvProof { merkle_block: Decodable::consensus_decode(d)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use blockdata::block::{Block, BlockHeader, ProofError, XField};
+    use blockdata::genesis::GenesisBuilder;
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use util::hash::BitcoinHash;
+    use util::headerchain::HeaderChain;
+    use util::key::{PrivateKey, PublicKey};
+    use util::merkleblock::{MerkleBlock, MerkleBlockError};
+    use util::signature;
+    use util::spv::{Error, SpvProof};
+
+    fn signed_block(prev: &Block, key: &PrivateKey) -> Block {
+        let txdata: Vec<Transaction> = (0..5u32).map(|i| Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev.txdata[0].malfix_txid(), i),
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 1000 + i as u64, script_pubkey: Script::new() }],
+        }).collect();
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: prev.bitcoin_hash(),
+                merkle_root: Default::default(),
+                im_merkle_root: Default::default(),
+                time: prev.header.time + 1,
+                xfield: XField::None,
+                proof: None,
+            },
+            txdata: txdata,
+        };
+        block.header.merkle_root = block.merkle_root();
+        block.header.im_merkle_root = block.immutable_merkle_root();
+        block.header.sign(key).unwrap();
+        block
+    }
+
+    #[test]
+    fn spv_proof_test() {
+        let key = PrivateKey::from_wif("KzT9HVkpBCGAVi964Hva4yJ6qQuyNQUdZ21HDuDZJpFvkGvBQiT5").unwrap();
+        let pk = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        let other_key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+
+        let genesis = GenesisBuilder::new(1562925929).build(&key).unwrap();
+        let block = signed_block(&genesis, &key);
+        let txids = [block.txdata[1].txid(), block.txdata[3].txid()].iter().cloned().collect();
+
+        let proof = SpvProof::from_block(&block, &txids);
+        assert_eq!(proof.block_hash(), block.bitcoin_hash());
+        let proof: SpvProof = deserialize(&serialize(&proof)).unwrap();
+        let expected = vec![(block.txdata[1].txid(), 1), (block.txdata[3].txid(), 3)];
+        assert_eq!(proof.verify(&pk).unwrap(), expected);
+
+        // signed by another key
+        let forged = SpvProof::from_block(&signed_block(&genesis, &other_key), &txids);
+        match forged.verify(&pk) {
+            Err(Error::InvalidProof(ProofError::BadSignature(signature::Error::InvalidSignature))) => {},
+            x => panic!("expected InvalidProof, got {:?}", x),
+        }
+
+        // the partial merkle tree of another block
+        let mut other = block.clone();
+        other.txdata.pop();
+        let mut invalid = proof.clone();
+        invalid.merkle_block.txn = MerkleBlock::from_block(&other, &txids).txn;
+        match invalid.verify(&pk) {
+            Err(Error::MerkleBlock(MerkleBlockError::MerkleRootMismatch)) => {},
+            x => panic!("expected MerkleBlock, got {:?}", x),
+        }
+
+        // the block extends the tip of the chain, then is connected to it
+        let mut chain = HeaderChain::new(genesis.header.clone()).unwrap();
+        assert_eq!(proof.verify_with_chain(&chain).unwrap(), expected);
+        match forged.verify_with_chain(&chain) {
+            Err(Error::InvalidProof(ProofError::BadSignature(signature::Error::InvalidSignature))) => {},
+            x => panic!("expected InvalidProof, got {:?}", x),
+        }
+        chain.connect(block.header.clone()).unwrap();
+        assert_eq!(proof.verify_with_chain(&chain).unwrap(), expected);
+
+        // neither connected nor extending the tip
+        let next = SpvProof::from_block(&signed_block(&signed_block(&block, &key), &key), &txids);
+        match next.verify_with_chain(&chain) {
+            Err(Error::UnknownBlock(hash)) => assert_eq!(hash, next.block_hash()),
+            x => panic!("expected UnknownBlock, got {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,523 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Threshold signature for block proof
+//!
+//! Implementation of threshold Schnorr signature which is used by the
+//! federation to produce block proofs. The aggregated private key and the
+//! nonce of each signing round are distributed among the signers with
+//! Feldman's verifiable secret sharing, and partial signatures from any
+//! `threshold` signers are combined into a `Signature` which verifies against
+//! the aggregated public key.
+//!
+//! Signers are identified by their index, starting from 1.
+//!
+
+use std::{error, fmt};
+
+use secp256k1::{self, Secp256k1, SecretKey};
+
+use util::key::PublicKey;
+use util::prime::jacobi;
+use util::rfc7969::nonce_rfc6979;
+use util::signature::{Signature, ALGO16};
+
+/// The order of secp256k1 minus one, used to negate points
+const ORDER_MINUS_ONE: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b,
+    0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x40
+];
+
+/// The order of secp256k1 minus two, used to invert scalars
+const ORDER_MINUS_TWO: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b,
+    0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x3f
+];
+
+/// "VSS COEFFICIENTS", the algorithm tag with which the coefficients of the
+/// polynomial are derived, distinct from the one of signing nonces
+const VSS_ALGO16: [u8; 16] = *b"VSS COEFFICIENTS";
+
+/// Commitments to the coefficients of the polynomial with which a signer
+/// shares a secret (Feldman's verifiable secret sharing)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiableSS {
+    /// The number of shares needed to reconstruct the secret
+    pub threshold: usize,
+    /// Commitments to the coefficients of the polynomial, starting from the secret
+    pub commitments: Vec<secp256k1::PublicKey>,
+}
+
+impl VerifiableSS {
+    /// Share the secret among `parties` signers, `threshold` of which are
+    /// needed to reconstruct it. The other coefficients of the polynomial are
+    /// derived from the secret and `seed`, which must be random and never reused.
+    /// Returns the commitments and the shares for the signers in order of index.
+    pub fn split(
+        secret: &SecretKey,
+        seed: &[u8; 32],
+        threshold: usize,
+        parties: usize,
+    ) -> Result<(VerifiableSS, Vec<SecretKey>), Error> {
+        if threshold == 0 || threshold > parties {
+            return Err(Error::InvalidThreshold);
+        }
+
+        let mut coefficients = vec![*secret];
+        let mut count: u32 = 0;
+        while coefficients.len() < threshold {
+            let nonce = nonce_rfc6979(seed, secret, &VSS_ALGO16, None, count);
+            count += 1;
+
+            if let Ok(coefficient) = SecretKey::from_slice(&nonce[..]) {
+                coefficients.push(coefficient);
+            }
+        }
+
+        let ctx = Secp256k1::signing_only();
+        let commitments = coefficients.iter()
+            .map(|c| secp256k1::PublicKey::from_secret_key(&ctx, c))
+            .collect();
+        let shares = (1..parties + 1)
+            .map(|index| evaluate(&coefficients, index))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((VerifiableSS { threshold, commitments }, shares))
+    }
+
+    /// The shared secret multiplied by the generator
+    pub fn public_key(&self) -> secp256k1::PublicKey {
+        self.commitments[0]
+    }
+
+    /// The share of the signer at `index` multiplied by the generator,
+    /// computed from the commitments
+    pub fn public_share(&self, index: usize) -> Result<secp256k1::PublicKey, Error> {
+        let ctx = Secp256k1::verification_only();
+        let x = index_to_scalar(index)?;
+
+        // Horner's method over the commitments
+        let mut iter = self.commitments.iter().rev();
+        let mut result = *iter.next().ok_or(Error::InvalidThreshold)?;
+        for commitment in iter {
+            result.mul_assign(&ctx, &x[..])?;
+            result = result.combine(commitment)?;
+        }
+        Ok(result)
+    }
+
+    /// Check that the share for the signer at `index` matches the commitments
+    pub fn verify_share(&self, index: usize, share: &SecretKey) -> bool {
+        let ctx = Secp256k1::signing_only();
+        match self.public_share(index) {
+            Ok(expected) => secp256k1::PublicKey::from_secret_key(&ctx, share) == expected,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Compute the aggregated public key from the commitments of all signers
+pub fn aggregate_public_key(vss: &[VerifiableSS]) -> Result<PublicKey, Error> {
+    let key = sum_points(vss.iter().map(|v| v.public_key()).collect())?;
+    Ok(PublicKey { compressed: true, key: key })
+}
+
+/// A signer's share of a secret which is jointly generated by all signers,
+/// such as the aggregated private key or the nonce of a signing round
+#[derive(Clone, Debug)]
+pub struct SharedSecret {
+    /// The index of the signer
+    pub index: usize,
+    /// The share of the secret
+    pub secret: SecretKey,
+    /// The jointly generated secret multiplied by the generator
+    pub public_key: secp256k1::PublicKey,
+}
+
+impl SharedSecret {
+    /// Verify the shares which the signer at `index` received from all signers
+    /// and sum them up. `vss[i]` and `shares[i]` must come from the same signer.
+    pub fn aggregate(index: usize, vss: &[VerifiableSS], shares: &[SecretKey]) -> Result<SharedSecret, Error> {
+        if vss.is_empty() || vss.len() != shares.len() {
+            return Err(Error::MismatchedShares);
+        }
+
+        for (i, (v, share)) in vss.iter().zip(shares.iter()).enumerate() {
+            if !v.verify_share(index, share) {
+                return Err(Error::InvalidShare(i));
+            }
+        }
+
+        let mut secret = shares[0];
+        for share in &shares[1..] {
+            secret.add_assign(&share[..])?;
+        }
+
+        Ok(SharedSecret {
+            index: index,
+            secret: secret,
+            public_key: sum_points(vss.iter().map(|v| v.public_key()).collect())?,
+        })
+    }
+
+    /// The jointly generated public key
+    pub fn aggregated_public_key(&self) -> PublicKey {
+        PublicKey { compressed: true, key: self.public_key }
+    }
+
+    /// Generate this signer's contribution to the nonce of a signing round
+    /// and share it among the signers. The nonce is derived from the key
+    /// share, the message and `aux`, which must be random and unique to the
+    /// round: reusing a nonce for a different aggregated nonce leaks the key share.
+    pub fn generate_nonce(
+        &self,
+        message: &[u8; 32],
+        aux: &[u8; 32],
+        threshold: usize,
+        parties: usize,
+    ) -> Result<(VerifiableSS, Vec<SecretKey>), Error> {
+        let mut count: u32 = 0;
+        let k = loop {
+            let nonce = nonce_rfc6979(message, &self.secret, &ALGO16, Some(aux), count);
+            count += 1;
+
+            if let Ok(k) = SecretKey::from_slice(&nonce[..]) {
+                break k;
+            }
+        };
+
+        VerifiableSS::split(&k, aux, threshold, parties)
+    }
+
+    /// Sign the message with this key share and the share of the round's nonce
+    pub fn sign_partial(&self, nonce: &SharedSecret, message: &[u8; 32]) -> Result<PartialSignature, Error> {
+        if self.index != nonce.index {
+            return Err(Error::InvalidIndex(nonce.index));
+        }
+
+        // Negate k if value of jacobi(R.y) is not 1
+        let mut k = nonce.secret;
+        if !has_square_y(&nonce.public_key) {
+            k.negate_assign();
+        }
+
+        // Compute s = k + ex
+        let e = Signature::compute_e(&nonce.public_key.serialize()[1..33], &self.public_key, message)?;
+        let mut sigma = e;
+        sigma.mul_assign(&self.secret[..])?;
+        sigma.add_assign(&k[..])?;
+
+        let mut result = [0u8; 32];
+        result.clone_from_slice(&sigma[..]);
+        Ok(PartialSignature { index: self.index, sigma: result })
+    }
+}
+
+/// A signature by a single signer, which is combined with others into a `Signature`
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct PartialSignature {
+    /// The index of the signer
+    pub index: usize,
+    /// sigma
+    pub sigma: [u8; 32],
+}
+
+impl PartialSignature {
+    /// Verify the partial signature against the commitments of all signers
+    /// to their shares of the aggregated private key and of the round's nonce
+    pub fn verify(&self, message: &[u8; 32], key_vss: &[VerifiableSS], nonce_vss: &[VerifiableSS]) -> Result<(), Error> {
+        let ctx = Secp256k1::new();
+
+        let public_key = sum_points(key_vss.iter().map(|v| v.public_key()).collect())?;
+        let r = sum_points(nonce_vss.iter().map(|v| v.public_key()).collect())?;
+        let public_share = sum_points(
+            key_vss.iter().map(|v| v.public_share(self.index)).collect::<Result<Vec<_>, _>>()?
+        )?;
+        let mut nonce_share = sum_points(
+            nonce_vss.iter().map(|v| v.public_share(self.index)).collect::<Result<Vec<_>, _>>()?
+        )?;
+        if !has_square_y(&r) {
+            nonce_share.mul_assign(&ctx, &ORDER_MINUS_ONE[..])?;
+        }
+
+        // Check that sG = K + eX
+        let e = Signature::compute_e(&r.serialize()[1..33], &public_key, message)?;
+        let mut ex = public_share;
+        ex.mul_assign(&ctx, &e[..])?;
+        let expected = nonce_share.combine(&ex)?;
+
+        let sigma = SecretKey::from_slice(&self.sigma[..])?;
+        if secp256k1::PublicKey::from_secret_key(&ctx, &sigma) == expected {
+            Ok(())
+        } else {
+            Err(Error::InvalidPartialSignature(self.index))
+        }
+    }
+}
+
+/// Combine the partial signatures of at least `threshold` signers into a
+/// signature. `nonce` is the public key of the round's nonce.
+pub fn combine(partials: &[PartialSignature], nonce: &secp256k1::PublicKey) -> Result<Signature, Error> {
+    if partials.is_empty() {
+        return Err(Error::MismatchedShares);
+    }
+
+    let indices: Vec<usize> = partials.iter().map(|p| p.index).collect();
+    let mut sigma: Option<SecretKey> = None;
+    for partial in partials {
+        let mut term = SecretKey::from_slice(&partial.sigma[..])?;
+        term.mul_assign(&lagrange_coefficient(partial.index, &indices)?[..])?;
+        sigma = Some(match sigma {
+            Some(mut s) => {
+                s.add_assign(&term[..])?;
+                s
+            }
+            None => term,
+        });
+    }
+
+    let mut r_x = [0u8; 32];
+    r_x.clone_from_slice(&nonce.serialize()[1..33]);
+    let mut result = [0u8; 32];
+    result.clone_from_slice(&sigma.expect("non-empty")[..]);
+    Ok(Signature { r_x, sigma: result })
+}
+
+/// Evaluate the polynomial at `index`
+fn evaluate(coefficients: &[SecretKey], index: usize) -> Result<SecretKey, Error> {
+    let x = index_to_scalar(index)?;
+
+    // Horner's method
+    let mut iter = coefficients.iter().rev();
+    let mut result = *iter.next().ok_or(Error::InvalidThreshold)?;
+    for coefficient in iter {
+        result.mul_assign(&x[..])?;
+        result.add_assign(&coefficient[..])?;
+    }
+    Ok(result)
+}
+
+/// Compute the Lagrange coefficient of `index` at zero over `indices`
+fn lagrange_coefficient(index: usize, indices: &[usize]) -> Result<SecretKey, Error> {
+    if indices.iter().filter(|&&i| i == index).count() != 1 {
+        return Err(Error::InvalidIndex(index));
+    }
+
+    let mut numerator = index_to_scalar(1)?;
+    let mut denominator = index_to_scalar(1)?;
+    for &other in indices {
+        if other == index {
+            continue;
+        }
+        numerator.mul_assign(&index_to_scalar(other)?[..])?;
+
+        // other - index
+        let diff = if other > index {
+            index_to_scalar(other - index)?
+        } else {
+            let mut d = index_to_scalar(index - other)?;
+            d.negate_assign();
+            d
+        };
+        denominator.mul_assign(&diff[..])?;
+    }
+
+    numerator.mul_assign(&invert(&denominator)?[..])?;
+    Ok(numerator)
+}
+
+/// Compute the inverse of the scalar as x^(n-2)
+fn invert(x: &SecretKey) -> Result<SecretKey, Error> {
+    let mut result = index_to_scalar(1)?;
+    for byte in ORDER_MINUS_TWO.iter() {
+        for i in (0..8).rev() {
+            let square = result;
+            result.mul_assign(&square[..])?;
+            if (byte >> i) & 1 == 1 {
+                result.mul_assign(&x[..])?;
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn index_to_scalar(index: usize) -> Result<SecretKey, Error> {
+    let index = index as u64;
+    let mut data = [0u8; 32];
+    for i in 0..8 {
+        data[31 - i] = (index >> (8 * i)) as u8;
+    }
+    SecretKey::from_slice(&data[..]).map_err(|_| Error::InvalidIndex(index as usize))
+}
+
+fn sum_points(points: Vec<secp256k1::PublicKey>) -> Result<secp256k1::PublicKey, Error> {
+    let mut iter = points.into_iter();
+    let mut result = iter.next().ok_or(Error::MismatchedShares)?;
+    for point in iter {
+        result = result.combine(&point)?;
+    }
+    Ok(result)
+}
+
+fn has_square_y(point: &secp256k1::PublicKey) -> bool {
+    jacobi(&point.serialize_uncompressed()[33..]) == 1
+}
+
+/// Threshold signature error
+#[derive(Debug)]
+pub enum Error {
+    /// The threshold is zero or larger than the number of signers
+    InvalidThreshold,
+    /// The index of a signer is zero or duplicated
+    InvalidIndex(usize),
+    /// No shares were given, or the numbers of commitments and shares differ
+    MismatchedShares,
+    /// The share at the given position does not match the commitments of its sender
+    InvalidShare(usize),
+    /// The partial signature of the signer does not match the commitments
+    InvalidPartialSignature(usize),
+    /// secp256k1 error
+    Secp256k1Error(secp256k1::Error),
+}
+
+#[doc(hidden)]
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error {
+        Error::Secp256k1Error(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Secp256k1Error(ref e) => fmt::Display::fmt(e, f),
+            Error::InvalidIndex(i) => write!(f, "invalid signer index: {}", i),
+            Error::InvalidShare(i) => write!(f, "invalid share at position {}", i),
+            Error::InvalidPartialSignature(i) => write!(f, "invalid partial signature of signer {}", i),
+            Error::InvalidThreshold | Error::MismatchedShares => {
+                f.write_str(error::Error::description(self))
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Secp256k1Error(ref e) => e.description(),
+            Error::InvalidThreshold => "invalid threshold",
+            Error::InvalidIndex(..) => "invalid signer index",
+            Error::MismatchedShares => "mismatched number of shares",
+            Error::InvalidShare(..) => "invalid share",
+            Error::InvalidPartialSignature(..) => "invalid partial signature",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Secp256k1Error(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::SecretKey;
+
+    use util::threshold::*;
+    use test_helpers::*;
+
+    fn shares_for(index: usize, shares: &[Vec<SecretKey>]) -> Vec<SecretKey> {
+        shares.iter().map(|s| s[index - 1]).collect()
+    }
+
+    #[test]
+    fn test_threshold_sign_and_verify() {
+        let (threshold, parties) = (2, 3);
+        let secrets = vec![
+            decode_sk("B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF"),
+            decode_sk("C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C9"),
+            decode_sk("0B432B2677937381AEF05BB02A66ECD012773062CF3FA2549E44F58ED2401710"),
+        ];
+        let message = decode_message("243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89");
+
+        // Key generation
+        let mut key_vss = Vec::new();
+        let mut key_shares = Vec::new();
+        for (i, secret) in secrets.iter().enumerate() {
+            let (vss, shares) = VerifiableSS::split(secret, &[i as u8; 32], threshold, parties).unwrap();
+            assert_eq!(vss.commitments.len(), threshold);
+            assert_eq!(shares.len(), parties);
+            key_vss.push(vss);
+            key_shares.push(shares);
+        }
+        let keys: Vec<SharedSecret> = (1..parties + 1)
+            .map(|index| SharedSecret::aggregate(index, &key_vss, &shares_for(index, &key_shares)).unwrap())
+            .collect();
+        let aggregated_public_key = aggregate_public_key(&key_vss).unwrap();
+        for key in &keys {
+            assert_eq!(key.aggregated_public_key(), aggregated_public_key);
+        }
+
+        // Nonce generation
+        let mut nonce_vss = Vec::new();
+        let mut nonce_shares = Vec::new();
+        for key in &keys {
+            let (vss, shares) = key.generate_nonce(&message, &[0x80 | key.index as u8; 32], threshold, parties).unwrap();
+            nonce_vss.push(vss);
+            nonce_shares.push(shares);
+        }
+        let nonces: Vec<SharedSecret> = (1..parties + 1)
+            .map(|index| SharedSecret::aggregate(index, &nonce_vss, &shares_for(index, &nonce_shares)).unwrap())
+            .collect();
+
+        // Any two signers can produce the signature
+        for signers in [[1, 2], [1, 3], [2, 3]].iter() {
+            let partials: Vec<PartialSignature> = signers.iter()
+                .map(|&index| keys[index - 1].sign_partial(&nonces[index - 1], &message).unwrap())
+                .collect();
+            for partial in &partials {
+                assert!(partial.verify(&message, &key_vss, &nonce_vss).is_ok());
+            }
+
+            let sig = combine(&partials, &nonces[0].public_key).unwrap();
+            assert!(sig.verify(&message, &aggregated_public_key).is_ok());
+        }
+
+        // A single signer can not
+        let partial = keys[0].sign_partial(&nonces[0], &message).unwrap();
+        let sig = combine(&[partial], &nonces[0].public_key).unwrap();
+        assert!(sig.verify(&message, &aggregated_public_key).is_err());
+
+        // A tampered partial signature is detected
+        let mut tampered = partial;
+        tampered.sigma = keys[1].sign_partial(&nonces[1], &message).unwrap().sigma;
+        match tampered.verify(&message, &key_vss, &nonce_vss) {
+            Err(Error::InvalidPartialSignature(1)) => {},
+            x => panic!("expected InvalidPartialSignature, got {:?}", x),
+        }
+
+        // A share for another signer is detected
+        let mut shares = shares_for(1, &key_shares);
+        shares[2] = key_shares[2][1];
+        match SharedSecret::aggregate(1, &key_vss, &shares) {
+            Err(Error::InvalidShare(2)) => {},
+            x => panic!("expected InvalidShare, got {:?}", x),
+        }
+
+        // Duplicated signers are rejected
+        match combine(&[partial, partial], &nonces[0].public_key) {
+            Err(Error::InvalidIndex(1)) => {},
+            x => panic!("expected InvalidIndex, got {:?}", x),
+        }
+
+        assert!(VerifiableSS::split(&secrets[0], &[0; 32], 0, 3).is_err());
+        assert!(VerifiableSS::split(&secrets[0], &[0; 32], 4, 3).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Token validation
+//!
+//! Checks a transaction against the Tapyrus token rules, given the outputs it
+//! spends. For each color, the amount in the outputs must equal the amount in
+//! the inputs, unless the transaction issues the token: a reissuable token is
+//! issued by spending an output with the script its color identifier commits
+//! to, and a non-reissuable token or an NFT by spending the outpoint its color
+//! identifier commits to. An NFT is issued with the amount 1. The fee is paid
+//! in TPC, i.e. computed from uncolored outputs only.
+//!
+
+use std::collections::BTreeMap;
+use std::{error, fmt};
+
+use blockdata::color::{ColorIdentifier, TokenType};
+use blockdata::transaction::{Transaction, TxOut};
+
+/// A violation of the token rules
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The number of spent outputs differs from the number of inputs
+    SpentOutputCountMismatch {
+        /// The number of inputs
+        expected: usize,
+        /// The number of spent outputs
+        actual: usize,
+    },
+    /// The sum of the amounts overflows
+    ValueOverflow,
+    /// The uncolored outputs exceed the uncolored inputs
+    InsufficientFee {
+        /// The uncolored input amount
+        input: u64,
+        /// The uncolored output amount
+        output: u64,
+    },
+    /// The output amount of a color is not equal to its input amount
+    Unbalanced {
+        /// The color
+        color_id: ColorIdentifier,
+        /// The input amount
+        input: u64,
+        /// The output amount
+        output: u64,
+    },
+    /// A token is issued without spending the script or outpoint its color identifier commits to
+    InvalidIssuance(ColorIdentifier),
+    /// An NFT is issued with an amount other than 1
+    InvalidNftAmount {
+        /// The color
+        color_id: ColorIdentifier,
+        /// The output amount
+        amount: u64,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::SpentOutputCountMismatch { expected, actual } => write!(f,
+                "expected {} spent outputs, got {}", expected, actual),
+            Error::ValueOverflow => f.write_str(error::Error::description(self)),
+            Error::InsufficientFee { input, output } => write!(f,
+                "uncolored outputs {} exceed uncolored inputs {}", output, input),
+            Error::Unbalanced { ref color_id, input, output } => write!(f,
+                "unbalanced token {}: inputs {}, outputs {}", color_id, input, output),
+            Error::InvalidIssuance(ref color_id) => write!(f, "invalid issuance of token {}", color_id),
+            Error::InvalidNftAmount { ref color_id, amount } => write!(f,
+                "invalid amount of NFT {}: {}", color_id, amount),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> { None }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::SpentOutputCountMismatch { .. } => "spent output count mismatch",
+            Error::ValueOverflow => "value overflow",
+            Error::InsufficientFee { .. } => "uncolored outputs exceed uncolored inputs",
+            Error::Unbalanced { .. } => "unbalanced token",
+            Error::InvalidIssuance(_) => "invalid token issuance",
+            Error::InvalidNftAmount { .. } => "invalid NFT amount",
+        }
+    }
+}
+
+/// The input and output amounts of a color in a transaction
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ColorBalance {
+    /// The amount of the color in the spent outputs
+    pub input: u64,
+    /// The amount of the color in the outputs
+    pub output: u64,
+}
+
+impl ColorBalance {
+    /// The amount issued by the transaction
+    pub fn issued(&self) -> u64 {
+        self.output.saturating_sub(self.input)
+    }
+}
+
+/// The balances of a valid transaction
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Balances {
+    /// The fee, in uncolored TPC
+    pub fee: u64,
+    /// The uncolored input amount
+    pub uncolored_input: u64,
+    /// The uncolored output amount
+    pub uncolored_output: u64,
+    /// The balance of each color in the inputs or outputs
+    pub colors: BTreeMap<ColorIdentifier, ColorBalance>,
+}
+
+/// Check a transaction against the token rules. `spent` are the outputs spent
+/// by the inputs of the transaction, in the same order.
+pub fn check_transaction(tx: &Transaction, spent: &[TxOut]) -> Result<Balances, Error> {
+    if tx.input.len() != spent.len() {
+        return Err(Error::SpentOutputCountMismatch {
+            expected: tx.input.len(),
+            actual: spent.len(),
+        });
+    }
+
+    let mut balances = Balances::default();
+    for prev_out in spent {
+        match prev_out.script_pubkey.color_id() {
+            Some(color_id) => {
+                let balance = balances.colors.entry(color_id).or_insert_with(ColorBalance::default);
+                balance.input = add(balance.input, prev_out.value)?;
+            }
+            None => balances.uncolored_input = add(balances.uncolored_input, prev_out.value)?,
+        }
+    }
+    for out in &tx.output {
+        match out.script_pubkey.color_id() {
+            Some(color_id) => {
+                let balance = balances.colors.entry(color_id).or_insert_with(ColorBalance::default);
+                balance.output = add(balance.output, out.value)?;
+            }
+            None => balances.uncolored_output = add(balances.uncolored_output, out.value)?,
+        }
+    }
+
+    for (color_id, balance) in &balances.colors {
+        if balance.input == balance.output {
+            continue;
+        }
+        if balance.input > balance.output {
+            return Err(Error::Unbalanced {
+                color_id: *color_id,
+                input: balance.input,
+                output: balance.output,
+            });
+        }
+        if !is_issuable(color_id, tx, spent) {
+            return Err(Error::InvalidIssuance(*color_id));
+        }
+        if color_id.token_type == TokenType::Nft && balance.output != 1 {
+            return Err(Error::InvalidNftAmount {
+                color_id: *color_id,
+                amount: balance.output,
+            });
+        }
+    }
+
+    if balances.uncolored_output > balances.uncolored_input {
+        return Err(Error::InsufficientFee {
+            input: balances.uncolored_input,
+            output: balances.uncolored_output,
+        });
+    }
+    balances.fee = balances.uncolored_input - balances.uncolored_output;
+    Ok(balances)
+}
+
+/// Whether the transaction spends the script or outpoint the color identifier commits to
+fn is_issuable(color_id: &ColorIdentifier, tx: &Transaction, spent: &[TxOut]) -> bool {
+    match color_id.token_type {
+        TokenType::Reissuable => spent.iter()
+            .any(|prev_out| ColorIdentifier::reissuable(&prev_out.script_pubkey) == *color_id),
+        TokenType::NonReissuable => tx.input.iter()
+            .any(|input| ColorIdentifier::non_reissuable(&input.previous_output) == *color_id),
+        TokenType::Nft => tx.input.iter()
+            .any(|input| ColorIdentifier::nft(&input.previous_output) == *color_id),
+    }
+}
+
+fn add(a: u64, b: u64) -> Result<u64, Error> {
+    a.checked_add(b).ok_or(Error::ValueOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use blockdata::color::ColorIdentifier;
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use hash_types::MalFixTxid;
+    use util::token::{check_transaction, ColorBalance, Error};
+
+    fn tx(inputs: &[OutPoint], outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: inputs.iter().map(|out_point| TxIn {
+                previous_output: *out_point,
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }).collect(),
+            output: outputs,
+        }
+    }
+
+    fn txout(value: u64, script_pubkey: &Script) -> TxOut {
+        TxOut { value: value, script_pubkey: script_pubkey.clone() }
+    }
+
+    #[test]
+    fn check_transaction_test() {
+        let p2pkh = Script::from(Vec::<u8>::from_hex("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac").unwrap());
+        let other = Script::from(Vec::<u8>::from_hex("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac").unwrap());
+        let txid = MalFixTxid::from_hex("ce9ea9f6f5e422c6a9dbcdbd3b9a14d1c78fab9ab520cb281aa2a74a09575da1").unwrap();
+        let out_point0 = OutPoint::new(txid, 0);
+        let out_point1 = OutPoint::new(txid, 1);
+
+        // reissuable token issued by spending p2pkh
+        let reissuable = ColorIdentifier::reissuable(&p2pkh);
+        let colored = p2pkh.add_color(&reissuable).unwrap();
+        let issue = tx(&[out_point0], vec![txout(1000, &colored), txout(90, &p2pkh)]);
+        let balances = check_transaction(&issue, &[txout(100, &p2pkh)]).unwrap();
+        assert_eq!(balances.fee, 10);
+        assert_eq!(balances.colors[&reissuable], ColorBalance { input: 0, output: 1000 });
+        assert_eq!(balances.colors[&reissuable].issued(), 1000);
+
+        // not spending p2pkh
+        assert_eq!(check_transaction(&issue, &[txout(100, &other)]), Err(Error::InvalidIssuance(reissuable)));
+
+        // transfer
+        let transfer = tx(&[out_point0, out_point1], vec![
+            txout(600, &other.add_color(&reissuable).unwrap()),
+            txout(400, &colored),
+            txout(50, &other),
+        ]);
+        let balances = check_transaction(&transfer, &[txout(1000, &colored), txout(60, &other)]).unwrap();
+        assert_eq!(balances.fee, 10);
+        assert_eq!(balances.colors[&reissuable].issued(), 0);
+        assert_eq!(check_transaction(&transfer, &[txout(900, &colored), txout(60, &other)]),
+                   Err(Error::InvalidIssuance(reissuable)));
+        assert_eq!(check_transaction(&transfer, &[txout(1100, &colored), txout(60, &other)]),
+                   Err(Error::Unbalanced { color_id: reissuable, input: 1100, output: 1000 }));
+        assert_eq!(check_transaction(&transfer, &[txout(1000, &colored), txout(40, &other)]),
+                   Err(Error::InsufficientFee { input: 40, output: 50 }));
+        assert_eq!(check_transaction(&transfer, &[txout(1000, &colored)]),
+                   Err(Error::SpentOutputCountMismatch { expected: 2, actual: 1 }));
+
+        // non-reissuable token issued by spending out_point1
+        let non_reissuable = ColorIdentifier::non_reissuable(&out_point1);
+        let issue = tx(&[out_point1], vec![txout(1000, &other.add_color(&non_reissuable).unwrap())]);
+        assert!(check_transaction(&issue, &[txout(100, &p2pkh)]).is_ok());
+        let issue = tx(&[out_point0], issue.output);
+        assert_eq!(check_transaction(&issue, &[txout(100, &p2pkh)]), Err(Error::InvalidIssuance(non_reissuable)));
+
+        // NFT
+        let nft = ColorIdentifier::nft(&out_point0);
+        let issue = tx(&[out_point0], vec![txout(1, &other.add_color(&nft).unwrap())]);
+        assert!(check_transaction(&issue, &[txout(100, &p2pkh)]).is_ok());
+        let issue = tx(&[out_point0], vec![txout(1, &other.add_color(&nft).unwrap()), txout(1, &p2pkh.add_color(&nft).unwrap())]);
+        assert_eq!(check_transaction(&issue, &[txout(100, &p2pkh)]),
+                   Err(Error::InvalidNftAmount { color_id: nft, amount: 2 }));
+
+        // overflow
+        let spend = tx(&[out_point0, out_point1], vec![]);
+        assert_eq!(check_transaction(&spend, &[txout(u64::max_value(), &p2pkh), txout(1, &p2pkh)]),
+                   Err(Error::ValueOverflow));
+    }
+}